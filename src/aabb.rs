@@ -23,9 +23,9 @@ pub fn surrounding_box(box0: &Bb, box1: &Bb) -> Bb {
   );
 
   let large_coords = Point3::from(
-    max(box0.min().x(), box1.min().x()),
-    max(box0.min().y(), box1.min().y()),
-    max(box0.min().z(), box1.min().z()),
+    max(box0.max().x(), box1.max().x()),
+    max(box0.max().y(), box1.max().y()),
+    max(box0.max().z(), box1.max().z()),
   );
 
   Bb::from(&small_coords, &large_coords)
@@ -57,9 +57,7 @@ impl Bb {
       let mut t1 = (self.max()[i] - r.origin()[i]) * inv_d;
 
       if inv_d < 0.0 {
-        let prev_t0 = t0;
-        t0 = t1;
-        t1 = prev_t0;
+        std::mem::swap(&mut t0, &mut t1);
       }
       t_min = if t0 > t_min { t0 } else { t_min };
       t_max = if t1 < t_max { t1 } else { t_max };
@@ -73,26 +71,26 @@ impl Bb {
 }
 
 fn min(a: f32, b: f32) -> f32 {
-  if a == std::f32::NAN {
+  if a.is_nan() {
     return b;
-  } else if b == std::f32::NAN {
+  } else if b.is_nan() {
     return a;
   }
   if a < b {
-    return a;
+    a
   } else {
-    return b;
+    b
   }
 }
 fn max(a: f32, b: f32) -> f32 {
-  if a == std::f32::NAN {
+  if a.is_nan() {
     return b;
-  } else if b == std::f32::NAN {
+  } else if b.is_nan() {
     return a;
   }
   if a > b {
-    return a;
+    a
   } else {
-    return b;
+    b
   }
 }
\ No newline at end of file
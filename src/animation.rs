@@ -0,0 +1,331 @@
+/*
+// Description: This file provides keyframe animation for rendering image
+//              sequences. A track holds values at keyframe times and gives
+//              the value at any time, interpolated linearly or along a
+//              Catmull-Rom spline through the keys, and held at the first or
+//              last key outside them. CameraAnimation keeps tracks for the
+//              camera's lookfrom, lookat, vfov and focus distance and builds
+//              the camera of each frame, which poses itself from the tracks
+//              at the time of each ray so its motion is blurred. Animated
+//              moves any object along translation and Y rotation tracks,
+//              evaluated at each ray's time, so a shutter interval blurs its
+//              motion too. The animation struct numbers the frames, times
+//              them from the frame rate and opens the shutter for a fraction
+//              of each frame, giving the time0/time1 of the frame's camera.
+*/
+
+use crate::{aabb::*, camera::*, hittable::*, ray::*, utils::*, vec3::*};
+use std::ops::{Add, Mul, Sub};
+use std::sync::Arc;
+
+/// Values a track can blend between.
+pub trait Interpolate: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {}
+impl<T> Interpolate for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// Smooth curve through every key, with the tangent at each key from
+    /// its neighbours
+    CatmullRom,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Track<T> {
+    /// Track that holds `value` at all times.
+    pub fn from(value: T) -> Self {
+        Self {
+            keys: vec![Keyframe { time: 0., value }],
+            interpolation: Interpolation::Linear,
+        }
+    }
+    /// Track through `(time, value)` keys, given in any order.
+    pub fn from_keys(interpolation: Interpolation, keys: &[(f32, T)]) -> Self {
+        assert!(!keys.is_empty(), "a track needs at least one key");
+        let mut track = Self {
+            keys: Vec::with_capacity(keys.len()),
+            interpolation,
+        };
+        for &(time, value) in keys {
+            track.add(time, value);
+        }
+        track
+    }
+    /// Adds a key, replacing any key already at `time`.
+    pub fn add(&mut self, time: f32, value: T) {
+        let i = self.keys.partition_point(|k| k.time < time);
+        if self.keys.get(i).is_some_and(|k| k.time == time) {
+            self.keys[i].value = value;
+        } else {
+            self.keys.insert(i, Keyframe { time, value });
+        }
+    }
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    pub fn value_at(&self, time: f32) -> T {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if time <= keys[0].time {
+            return keys[0].value;
+        }
+        if time >= keys[last].time {
+            return keys[last].value;
+        }
+        // Segment from key i to key i + 1 holding `time`
+        let i = keys.partition_point(|k| k.time <= time) - 1;
+        let (k0, k1) = (keys[i], keys[i + 1]);
+        let dt = k1.time - k0.time;
+        let s = (time - k0.time) / dt;
+
+        match self.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * s,
+            Interpolation::CatmullRom => {
+                let m0 = self.tangent(i) * dt;
+                let m1 = self.tangent(i + 1) * dt;
+                // Cubic Hermite basis
+                let s2 = s * s;
+                let s3 = s2 * s;
+                k0.value * (2. * s3 - 3. * s2 + 1.)
+                    + m0 * (s3 - 2. * s2 + s)
+                    + k1.value * (-2. * s3 + 3. * s2)
+                    + m1 * (s3 - s2)
+            }
+        }
+    }
+
+    // Rate of change at key i, from its neighbours, or one-sided at the ends
+    fn tangent(&self, i: usize) -> T {
+        let keys = &self.keys;
+        let prev = keys[i.saturating_sub(1)];
+        let next = keys[(i + 1).min(keys.len() - 1)];
+        (next.value - prev.value) * (1. / (next.time - prev.time))
+    }
+}
+
+/// Keyframed camera. Settings not kept in tracks stay the same in every frame.
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    /// Vertical field of view in degrees
+    pub vfov: Track<f32>,
+    pub focus_dist: Track<f32>,
+    pub vup: Vec3,
+    pub aspect_ratio: f32,
+    pub aperture: f32,
+}
+
+impl CameraAnimation {
+    /// Camera standing still, with the same arguments as `Camera::new`
+    /// less the shutter times. Replace its tracks to move it.
+    pub fn from(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
+    ) -> Self {
+        Self {
+            lookfrom: Track::from(lookfrom),
+            lookat: Track::from(lookat),
+            vfov: Track::from(vfov),
+            focus_dist: Track::from(focus_dist),
+            vup,
+            aspect_ratio,
+            aperture,
+        }
+    }
+
+    /// Camera for a shutter open from `time0` to `time1`, following the
+    /// tracks through the interval.
+    pub fn camera_at(&self, time0: f32, time1: f32) -> Camera {
+        let mut cam = Camera::new(
+            self.lookfrom.value_at(time0),
+            self.lookat.value_at(time0),
+            self.vup,
+            self.vfov.value_at(time0),
+            self.aspect_ratio,
+            self.aperture,
+            self.focus_dist.value_at(time0),
+            time0,
+            time1,
+        );
+        let still = self.lookfrom.is_constant()
+            && self.lookat.is_constant()
+            && self.vfov.is_constant()
+            && self.focus_dist.is_constant();
+        if !still {
+            cam.set_motion(Arc::new(self.clone()));
+        }
+        cam
+    }
+}
+
+/// Object rotated around the Y axis by `rotation_y` degrees and then moved
+/// by `translation`, both taken at the time of each ray.
+pub struct Animated {
+    ptr: Arc<dyn Hittable>,
+    translation: Track<Vec3>,
+    rotation_y: Track<f32>,
+}
+
+impl Animated {
+    pub fn from(p: Arc<dyn Hittable>, translation: Track<Vec3>, rotation_y: Track<f32>) -> Self {
+        Self {
+            ptr: p,
+            translation,
+            rotation_y,
+        }
+    }
+    // Bounding box of the object moved to where it is at `time`
+    fn box_at(&self, object: &Bb, time: f32) -> Bb {
+        let radians = degrees_to_radians(self.rotation_y.value_at(time));
+        let (sin_theta, cos_theta) = radians.sin_cos();
+        let offset = self.translation.value_at(time);
+
+        let mut min = Point3::from(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Point3::from(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY);
+        for corner in 0..8 {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) != 0 {
+                    object.max()[axis]
+                } else {
+                    object.min()[axis]
+                }
+            };
+            let (x, y, z) = (pick(0), pick(1), pick(2));
+            let moved = Vec3::from(cos_theta * x + sin_theta * z, y, -sin_theta * x + cos_theta * z)
+                + offset;
+            for c in 0..3 {
+                min[c] = min[c].min(moved[c]);
+                max[c] = max[c].max(moved[c]);
+            }
+        }
+        Bb::from(&min, &max)
+    }
+}
+
+impl Hittable for Animated {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+        let radians = degrees_to_radians(self.rotation_y.value_at(r.time()));
+        let (sin_theta, cos_theta) = radians.sin_cos();
+        let offset = self.translation.value_at(r.time());
+
+        // Into object space: undo the move, then the rotation
+        let origin = r.origin() - offset;
+        let direction = r.direction();
+        let local_r = Ray::new(
+            Point3::from(
+                cos_theta * origin.x() - sin_theta * origin.z(),
+                origin.y(),
+                sin_theta * origin.x() + cos_theta * origin.z(),
+            ),
+            Vec3::from(
+                cos_theta * direction.x() - sin_theta * direction.z(),
+                direction.y(),
+                sin_theta * direction.x() + cos_theta * direction.z(),
+            ),
+            r.time(),
+        )
+        .with_cone(r.width, r.spread);
+
+        if !self.ptr.hit(&local_r, t_min, t_max, rec) {
+            return false;
+        }
+
+        let rotate = |v: Vec3| {
+            Vec3::from(
+                cos_theta * v.x() + sin_theta * v.z(),
+                v.y(),
+                -sin_theta * v.x() + cos_theta * v.z(),
+            )
+        };
+        // Rigid motion keeps the normal facing the ray
+        rec.p = rotate(rec.p) + offset;
+        rec.normal = rotate(rec.normal);
+        rec.dpdu = rotate(rec.dpdu);
+        rec.dpdv = rotate(rec.dpdv);
+        true
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Bb) -> bool {
+        let mut object = Bb::new();
+        if !self.ptr.bounding_box(time0, time1, &mut object) {
+            return false;
+        }
+        // Boxes at the ends, every key in between, and enough steps between
+        // them to follow a curved path
+        const STEPS: usize = 16;
+        let mut times: Vec<f32> = (0..=STEPS)
+            .map(|i| time0 + (time1 - time0) * i as f32 / STEPS as f32)
+            .collect();
+        let key_times = self.translation.keys().iter().map(|k| k.time);
+        let key_times = key_times.chain(self.rotation_y.keys().iter().map(|k| k.time));
+        times.extend(key_times.filter(|t| (time0..=time1).contains(t)));
+
+        *output_box = times
+            .iter()
+            .map(|&t| self.box_at(&object, t))
+            .reduce(|a, b| surrounding_box(&a, &b))
+            .unwrap();
+        true
+    }
+}
+
+/// Frames `first_frame` to `last_frame` at `fps` frames a second. Frame n
+/// starts at n / fps seconds and the shutter stays open for `shutter` of
+/// the frame, 0.5 being the usual 180 degree shutter.
+#[derive(Clone, Copy, Debug)]
+pub struct Animation {
+    pub fps: f32,
+    pub first_frame: usize,
+    pub last_frame: usize,
+    pub shutter: f32,
+}
+
+impl Animation {
+    pub fn from(fps: f32, first_frame: usize, last_frame: usize) -> Self {
+        Self {
+            fps,
+            first_frame,
+            last_frame,
+            shutter: 0.5,
+        }
+    }
+    pub fn frames(&self) -> std::ops::RangeInclusive<usize> {
+        self.first_frame..=self.last_frame
+    }
+    pub fn frame_time(&self, frame: usize) -> f32 {
+        frame as f32 / self.fps
+    }
+    /// Times the shutter opens and closes in `frame`, for the camera's
+    /// time0 and time1.
+    pub fn shutter_interval(&self, frame: usize) -> (f32, f32) {
+        let open = self.frame_time(frame);
+        (open, open + clamp(self.shutter, 0., 1.) / self.fps)
+    }
+}
+
+/// File name of `frame`, numbered to four digits after `prefix`, e.g.
+/// frame_path("out/shot_", 7, "ppm") is "out/shot_0007.ppm".
+pub fn frame_path(prefix: &str, frame: usize, extension: &str) -> String {
+    format!("{}{:04}.{}", prefix, frame, extension)
+}
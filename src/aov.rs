@@ -0,0 +1,303 @@
+/*
+// Description: This file provides the arbitrary output variables (AOVs), the
+//              extra per-pixel passes written next to the beauty image for
+//              compositing. An aovsample holds what a camera path saw at its
+//              first hit: depth along the ray, world position, shading
+//              normal, albedo, texture coords and the material and object
+//              ids, along with its radiance split into light emitted by the
+//              first surface, direct light reflected off it and indirect
+//              light from longer paths. The aovpixel struct sums the samples
+//              of a pixel; geometric passes average over the samples that hit
+//              something, ids come from the first sample that did, and light
+//              passes average over every sample. Passes are written as PFM
+//              images, one per pass, or as layers of a single uncompressed
+//              OpenEXR file.
+*/
+
+use crate::vec3::*;
+use std::io::{Error, Write};
+
+/// First hit data and radiance split of a single camera path.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AovSample {
+    pub hit: bool,
+    /// Distance along the camera ray
+    pub depth: f32,
+    pub position: Point3,
+    pub normal: Vec3,
+    /// Attenuation of the first scattering event
+    pub albedo: Color,
+    pub u: f32,
+    pub v: f32,
+    pub material_id: u32,
+    pub object_id: u32,
+    /// Emitted by the first surface hit, or the background on a miss
+    pub emission: Color,
+    /// Reflected off the first surface straight from a light
+    pub direct: Color,
+    /// Reflected off the first surface after more bounces
+    pub indirect: Color,
+}
+
+impl AovSample {
+    pub fn color(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AovPass {
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    Uv,
+    MaterialId,
+    ObjectId,
+    Direct,
+    Indirect,
+    Emission,
+}
+
+impl AovPass {
+    pub const ALL: [AovPass; 10] = [
+        AovPass::Depth,
+        AovPass::Position,
+        AovPass::Normal,
+        AovPass::Albedo,
+        AovPass::Uv,
+        AovPass::MaterialId,
+        AovPass::ObjectId,
+        AovPass::Direct,
+        AovPass::Indirect,
+        AovPass::Emission,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovPass::Depth => "depth",
+            AovPass::Position => "position",
+            AovPass::Normal => "normal",
+            AovPass::Albedo => "albedo",
+            AovPass::Uv => "uv",
+            AovPass::MaterialId => "material_id",
+            AovPass::ObjectId => "object_id",
+            AovPass::Direct => "direct",
+            AovPass::Indirect => "indirect",
+            AovPass::Emission => "emission",
+        }
+    }
+    pub fn channels(&self) -> usize {
+        match self {
+            AovPass::Depth | AovPass::MaterialId | AovPass::ObjectId => 1,
+            AovPass::Uv => 2,
+            _ => 3,
+        }
+    }
+    /// Light passes are radiance and take the image exposure, the others
+    /// are written as they are.
+    pub fn is_light(&self) -> bool {
+        matches!(self, AovPass::Direct | AovPass::Indirect | AovPass::Emission)
+    }
+}
+
+/// Sums of the AOV samples of a pixel.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AovPixel {
+    samples: u32,
+    hits: u32,
+    depth: f32,
+    position: Point3,
+    normal: Vec3,
+    uv: (f32, f32),
+    albedo: Color,
+    emission: Color,
+    direct: Color,
+    indirect: Color,
+    ids: Option<(u32, u32)>,
+}
+
+/// Words an aovpixel is stored as in a checkpoint.
+pub const AOV_PIXEL_WORDS: usize = 26;
+
+impl AovPixel {
+    pub fn add(&mut self, s: &AovSample) {
+        self.samples += 1;
+        self.albedo.add(s.albedo);
+        self.emission.add(s.emission);
+        self.direct.add(s.direct);
+        self.indirect.add(s.indirect);
+        if !s.hit {
+            return;
+        }
+        self.hits += 1;
+        self.depth += s.depth;
+        self.position.add(s.position);
+        self.normal.add(s.normal);
+        self.uv = (self.uv.0 + s.u, self.uv.1 + s.v);
+        if self.ids.is_none() {
+            self.ids = Some((s.material_id, s.object_id));
+        }
+    }
+    pub fn merge(&mut self, other: &AovPixel) {
+        self.samples += other.samples;
+        self.hits += other.hits;
+        self.depth += other.depth;
+        self.position.add(other.position);
+        self.normal.add(other.normal);
+        self.uv = (self.uv.0 + other.uv.0, self.uv.1 + other.uv.1);
+        self.albedo.add(other.albedo);
+        self.emission.add(other.emission);
+        self.direct.add(other.direct);
+        self.indirect.add(other.indirect);
+        self.ids = self.ids.or(other.ids);
+    }
+
+    /// Pass value of the pixel in the first `pass.channels()` components.
+    /// Pixels where nothing was hit read 0 in the geometric passes.
+    pub fn value(&self, pass: AovPass) -> Color {
+        let per_sample = 1. / self.samples.max(1) as f32;
+        let per_hit = 1. / self.hits.max(1) as f32;
+        let (material_id, object_id) = self.ids.unwrap_or((0, 0));
+        match pass {
+            AovPass::Depth => Color::from(self.depth * per_hit, 0., 0.),
+            AovPass::Position => self.position * per_hit,
+            AovPass::Normal => {
+                let n = self.normal * per_hit;
+                if n.near_zero() {
+                    n
+                } else {
+                    unit_vector(n)
+                }
+            }
+            AovPass::Albedo => self.albedo * per_sample,
+            AovPass::Uv => Color::from(self.uv.0 * per_hit, self.uv.1 * per_hit, 0.),
+            AovPass::MaterialId => Color::from(material_id as f32, 0., 0.),
+            AovPass::ObjectId => Color::from(object_id as f32, 0., 0.),
+            AovPass::Direct => self.direct * per_sample,
+            AovPass::Indirect => self.indirect * per_sample,
+            AovPass::Emission => self.emission * per_sample,
+        }
+    }
+
+    pub fn to_words(&self) -> [u32; AOV_PIXEL_WORDS] {
+        let mut words = [0u32; AOV_PIXEL_WORDS];
+        words[0] = self.samples;
+        words[1] = self.hits;
+        let mut floats = vec![self.depth];
+        for c in [self.position, self.normal, self.albedo, self.emission, self.direct, self.indirect] {
+            floats.extend(c.e);
+        }
+        floats.extend([self.uv.0, self.uv.1]);
+        for (w, f) in words[2..23].iter_mut().zip(floats) {
+            *w = f.to_bits();
+        }
+        if let Some((material_id, object_id)) = self.ids {
+            words[23] = 1;
+            words[24] = material_id;
+            words[25] = object_id;
+        }
+        words
+    }
+    pub fn from_words(words: &[u32; AOV_PIXEL_WORDS]) -> Self {
+        let f = |i: usize| f32::from_bits(words[i]);
+        let c = |i: usize| Color::from(f(i), f(i + 1), f(i + 2));
+        Self {
+            samples: words[0],
+            hits: words[1],
+            depth: f(2),
+            position: c(3),
+            normal: c(6),
+            albedo: c(9),
+            emission: c(12),
+            direct: c(15),
+            indirect: c(18),
+            uv: (f(21), f(22)),
+            ids: (words[23] != 0).then_some((words[24], words[25])),
+        }
+    }
+}
+
+/// Writes `channels` (1 or 3) floats per pixel as a PFM image, rows bottom
+/// first as PFM stores them.
+pub fn write_pfm<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: &[f32],
+) -> Result<(), Error> {
+    let kind = if channels == 1 { "Pf" } else { "PF" };
+    write!(w, "{}\n{} {}\n-1.0\n", kind, width, height)?;
+    for value in data.iter().take(width * height * channels) {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Attribute of an OpenEXR header
+fn exr_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend(name.as_bytes());
+    out.push(0);
+    out.extend(kind.as_bytes());
+    out.push(0);
+    out.extend((value.len() as i32).to_le_bytes());
+    out.extend(value);
+}
+
+/// Writes float channels, each with one value per pixel and rows top first,
+/// as an uncompressed scanline OpenEXR file. Channel names follow the
+/// `layer.R` convention so compositing tools group them into layers.
+pub fn write_exr<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    channels: &[(String, Vec<f32>)],
+) -> Result<(), Error> {
+    // Channels are listed, and stored in each scanline, sorted by name
+    let mut sorted: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut chlist = vec![];
+    for (name, _) in &sorted {
+        chlist.extend(name.as_bytes());
+        chlist.push(0);
+        chlist.extend(2i32.to_le_bytes()); // FLOAT
+        chlist.extend([0, 0, 0, 0]); // pLinear and reserved
+        chlist.extend(1i32.to_le_bytes());
+        chlist.extend(1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let mut window = vec![];
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend(v.to_le_bytes());
+    }
+    exr_attribute(&mut out, "channels", "chlist", &chlist);
+    exr_attribute(&mut out, "compression", "compression", &[0]);
+    exr_attribute(&mut out, "dataWindow", "box2i", &window);
+    exr_attribute(&mut out, "displayWindow", "box2i", &window);
+    exr_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    exr_attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    out.push(0);
+
+    // Offset table, then one block per scanline
+    let line_size = width * sorted.len() * 4;
+    let first = out.len() + height * 8;
+    for y in 0..height {
+        out.extend(((first + y * (line_size + 8)) as u64).to_le_bytes());
+    }
+    for y in 0..height {
+        out.extend((y as i32).to_le_bytes());
+        out.extend((line_size as i32).to_le_bytes());
+        for (_, data) in &sorted {
+            for x in 0..width {
+                let value = data.get(y * width + x).copied().unwrap_or(0.);
+                out.extend(value.to_le_bytes());
+            }
+        }
+    }
+    w.write_all(&out)
+}
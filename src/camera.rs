@@ -6,12 +6,293 @@
 //              dimension vector of the view plane, the vertical dimension vector of 
 //              the view plane, the camera's orthonormal basis vectors, the radius of 
 //              camera lens, and time range. The get_ray method generates a ray passing
-//              through the camera lens.
+//              through the camera lens. The lens aperture can be a circle, a polygon
+//              with a given number of blades, or an image mask, and can be clipped
+//              towards the frame edges for cat-eye bokeh. Tilt rotates the plane of
+//              focus and shift slides the frame across the image plane. Besides
+//              perspective, the camera can project orthographically, through an
+//              equidistant or equisolid fisheye, or as a 360 degree equirectangular
+//              panorama, all through the same get_ray(s, t) call. An exposure of
+//              iso, shutter speed and f-stop gives the image brightness scale, and
+//              the f-stop also sets the aperture for the lens focal length.
+//              The time and lens position of each ray come from the current
+//              sampler. Given the image height, each ray also carries the
+//              cone of its pixel, which sets how widely textures are filtered.
+//              A camera given a cameraanimation is posed from its tracks at
+//              the time of each ray, so its own motion is blurred too.
 */
 
-use crate::{ray::*, utils::*, vec3::*};
+use crate::{animation::*, ray::*, sampler::*, texture::*, utils::*, vec3::*};
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
 
-pub struct Camera {
+#[derive(Clone, Debug)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon with `blades` sides, rotated by `rotation` degrees
+    Polygon { blades: usize, rotation: f32 },
+    /// Opening taken from the image's alpha, or its brightness when it has none
+    Mask(Arc<ImageTexture>),
+}
+
+impl ApertureShape {
+    // Point on the aperture in the [-1, 1] square, uniform over the opening,
+    // from a point in the unit square. Masks are sampled from their table
+    fn sample(&self, u: f32, v: f32) -> Vec3 {
+        match self {
+            ApertureShape::Circle => Vec3::in_unit_disk_from(u, v),
+            ApertureShape::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                let wedge = 2. * PI / blades as f32;
+                // The first coordinate picks the blade and is reused within it
+                let k = ((u * blades as f32) as usize).min(blades - 1);
+                let u = u * blades as f32 - k as f32;
+                let a0 = degrees_to_radians(*rotation) + wedge * k as f32;
+                let (p1, p2) = (
+                    Vec3::from(a0.cos(), a0.sin(), 0.),
+                    Vec3::from((a0 + wedge).cos(), (a0 + wedge).sin(), 0.),
+                );
+                // Uniform point in the triangle between the center and one edge
+                let r1 = u.sqrt();
+                let r2 = v;
+                p1 * (r1 * (1. - r2)) + p2 * (r1 * r2)
+            }
+            ApertureShape::Mask(_) => Vec3::new(),
+        }
+    }
+
+    // Open fraction of the span from x0 to x1 at height y
+    fn opening(&self, x0: f32, x1: f32, y: f32) -> f32 {
+        let covered = |lo: f32, hi: f32| (x1.min(hi) - x0.max(lo)).max(0.) / (x1 - x0);
+        match self {
+            ApertureShape::Circle => {
+                let half = (1. - y * y).max(0.).sqrt();
+                covered(-half, half)
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                let wedge = 2. * PI / blades as f32;
+                let a0 = degrees_to_radians(*rotation);
+                let corner = |k: usize| {
+                    let a = a0 + wedge * k as f32;
+                    (a.cos(), a.sin())
+                };
+                // Where the edges cross the line at y
+                let (mut lo, mut hi) = (f32::INFINITY, f32::NEG_INFINITY);
+                for k in 0..blades {
+                    let ((xa, ya), (xb, yb)) = (corner(k), corner(k + 1));
+                    if (ya - y) * (yb - y) <= 0. && ya != yb {
+                        let x = xa + (xb - xa) * (y - ya) / (yb - ya);
+                        lo = lo.min(x);
+                        hi = hi.max(x);
+                    }
+                }
+                covered(lo, hi)
+            }
+            ApertureShape::Mask(mask) => {
+                let (u, v) = (0.25 * (x0 + x1) + 0.5, 0.5 * (y + 1.));
+                if mask.has_alpha() {
+                    mask.alpha(u, v, 0.)
+                } else {
+                    let c = mask.sample(u, v, 0.);
+                    (c.x() + c.y() + c.z()) / 3.
+                }
+            }
+        }
+    }
+}
+
+/// Opening of an aperture tabulated on a grid over the [-1, 1] square, top
+/// row first, so masks and clipped apertures are sampled in proportion to
+/// it. Masks use their own texels, circles and polygons a fixed resolution.
+struct ApertureTable {
+    width: usize,
+    height: usize,
+    /// Running sums of the opening along each row, width + 1 per row
+    row_sums: Vec<f32>,
+    /// Running sums of the row totals, height + 1 of them
+    totals: Vec<f32>,
+}
+
+impl fmt::Debug for ApertureTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApertureTable")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ApertureTable {
+    const RESOLUTION: usize = 256;
+
+    fn from(shape: &ApertureShape) -> Self {
+        let (width, height) = match shape {
+            ApertureShape::Mask(mask) => mask.dimensions(),
+            _ => (Self::RESOLUTION, Self::RESOLUTION),
+        };
+        let mut row_sums = Vec::with_capacity((width + 1) * height);
+        let mut totals = Vec::with_capacity(height + 1);
+        totals.push(0.);
+        for j in 0..height {
+            let y = 1. - 2. * (j as f32 + 0.5) / height as f32;
+            let mut sum = 0.;
+            row_sums.push(sum);
+            for i in 0..width {
+                let x0 = 2. * i as f32 / width as f32 - 1.;
+                let x1 = 2. * (i + 1) as f32 / width as f32 - 1.;
+                sum += shape.opening(x0, x1, y).max(0.);
+                row_sums.push(sum);
+            }
+            totals.push(totals[j] + sum);
+        }
+        Self {
+            width,
+            height,
+            row_sums,
+            totals,
+        }
+    }
+
+    fn row(&self, j: usize) -> &[f32] {
+        &self.row_sums[j * (self.width + 1)..(j + 1) * (self.width + 1)]
+    }
+
+    // Running sum of row `j` up to texel coordinate `x`
+    fn row_sum_at(&self, j: usize, x: f32) -> f32 {
+        let row = self.row(j);
+        let x = clamp(x, 0., self.width as f32);
+        let i = (x as usize).min(self.width - 1);
+        row[i] + (row[i + 1] - row[i]) * (x - i as f32)
+    }
+
+    // Texel coordinate where the running sum of row `j` reaches `target`
+    fn invert_row(&self, j: usize, target: f32) -> f32 {
+        let row = self.row(j);
+        let i = row.partition_point(|&sum| sum <= target).clamp(1, self.width) - 1;
+        let span = row[i + 1] - row[i];
+        let fraction = if span > 0. { (target - row[i]) / span } else { 0.5 };
+        i as f32 + clamp(fraction, 0., 1.)
+    }
+
+    // Aperture point at texel coordinate `x`, `fy` of the way down row `j`
+    fn point(&self, x: f32, j: usize, fy: f32) -> Vec3 {
+        Vec3::from(
+            2. * x / self.width as f32 - 1.,
+            1. - 2. * (j as f32 + fy) / self.height as f32,
+            0.,
+        )
+    }
+
+    /// Point on the aperture in proportion to the opening, from a point in
+    /// the unit square. None when nothing is open.
+    fn sample(&self, u: f32, v: f32) -> Option<Vec3> {
+        let total = self.totals[self.height];
+        if total <= 0. {
+            return None;
+        }
+        let target = u * total;
+        let j = self.totals.partition_point(|&sum| sum <= target).clamp(1, self.height) - 1;
+        let weight = self.totals[j + 1] - self.totals[j];
+        let fy = if weight > 0. { (target - self.totals[j]) / weight } else { 0.5 };
+        let x = self.invert_row(j, v * self.row(j)[self.width]);
+        Some(self.point(x, j, clamp(fy, 0., 1.)))
+    }
+
+    /// Like `sample`, within the unit circle around `clip`.
+    fn sample_clipped(&self, u: f32, v: f32, clip: Vec3) -> Option<Vec3> {
+        // Where the circle crosses row j, in texel coordinates
+        let span = |j: usize| {
+            let dy = 1. - 2. * (j as f32 + 0.5) / self.height as f32 - clip.y();
+            let half = (1. - dy * dy).max(0.).sqrt();
+            let scale = self.width as f32 / 2.;
+            ((clip.x() - half + 1.) * scale, (clip.x() + half + 1.) * scale)
+        };
+        let weight = |j: usize| {
+            let (a, b) = span(j);
+            (self.row_sum_at(j, b) - self.row_sum_at(j, a)).max(0.)
+        };
+        let total: f32 = (0..self.height).map(weight).sum();
+        if total <= 0. {
+            return None;
+        }
+
+        let mut target = u * total;
+        let mut picked = None;
+        for j in 0..self.height {
+            let w = weight(j);
+            if w <= 0. {
+                continue;
+            }
+            picked = Some((j, clamp(target / w, 0., 1.)));
+            if target < w {
+                break;
+            }
+            target -= w;
+        }
+        let (j, fy) = picked?;
+        let (a, b) = span(j);
+        let (sa, sb) = (self.row_sum_at(j, a), self.row_sum_at(j, b));
+        let x = clamp(self.invert_row(j, sa + v * (sb - sa)), a, b);
+        Some(self.point(x, j, fy))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// Distance from the image center is proportional to the view angle
+    Equidistant,
+    /// Equal solid angles cover equal image areas
+    Equisolid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays along the view direction, framing the same area the
+    /// perspective view sees at the focus distance
+    Orthographic,
+    /// `fov` in degrees is the angle spanned across the image width
+    Fisheye { fov: f32, mapping: FisheyeMapping },
+    /// Full sphere, longitude across the width and latitude up the height
+    Equirectangular,
+}
+
+/// Photographic exposure settings. The scale maps scene radiance to the
+/// output so that a surface of luminance 1 / (1.2 * 2^EV100) is just white.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    pub iso: f32,
+    /// Seconds the shutter is open
+    pub shutter_speed: f32,
+    pub f_stop: f32,
+    /// Extra exposure in stops, positive brightens
+    pub compensation: f32,
+}
+
+impl Exposure {
+    pub fn from(iso: f32, shutter_speed: f32, f_stop: f32) -> Self {
+        Self {
+            iso,
+            shutter_speed,
+            f_stop,
+            compensation: 0.,
+        }
+    }
+    /// Exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter_speed * 100. / self.iso).log2() - self.compensation
+    }
+    pub fn scale(&self) -> f32 {
+        1. / (1.2 * self.ev100().exp2())
+    }
+}
+
+/// Where the camera is and how it frames the scene: everything a pose of an
+/// animated camera changes.
+#[derive(Clone, Copy, Debug)]
+struct View {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
@@ -19,21 +300,20 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    lens_radius: f32,
-    time0: f32,
-    time1: f32,
+    focus_dist: f32,
+    shift: Vec3,
+    focus_normal: Vec3,
+    tilted: bool,
 }
-impl Camera {
-    pub fn new(
+
+impl View {
+    fn from(
         lookfrom: Point3,
         lookat: Point3,
         vup: Vec3,
         vfov: f32,
         aspect_ratio: f32,
-        aperture: f32,
         focus_dist: f32,
-        time0: f32,
-        time1: f32,
     ) -> Self {
         let theta = degrees_to_radians(vfov);
         let h = (theta / 2.).tan();
@@ -45,36 +325,265 @@ impl Camera {
         let u = unit_vector(cross(vup, w));
         let v = cross(w, u);
 
-        let _focal_length = 1.;
-
         let origin = lookfrom;
         let horizontal = u * focus_dist * viewport_width;
         let vertical = v * focus_dist * viewport_height;
         let lower_left_corner = origin - horizontal / 2. - vertical / 2. - w * focus_dist;
 
-        let lens_radius = aperture / 2.;
-
         Self {
             origin,
             lower_left_corner,
             horizontal,
             vertical,
-            w,
             u,
             v,
+            w,
+            focus_dist,
+            shift: Vec3::new(),
+            focus_normal: w,
+            tilted: false,
+        }
+    }
+
+    fn set_tilt_shift(&mut self, (tilt_x, tilt_y, shift_x, shift_y): (f32, f32, f32, f32)) {
+        let (ax, ay) = (degrees_to_radians(tilt_x), degrees_to_radians(tilt_y));
+        let tilted_x = self.w * ax.cos() + self.v * ax.sin();
+        let normal = tilted_x * ay.cos() + self.u * ay.sin();
+        self.focus_normal = unit_vector(normal);
+        self.tilted = tilt_x != 0. || tilt_y != 0.;
+        self.shift = self.horizontal * shift_x + self.vertical * shift_y;
+    }
+
+    // Where the ray through the lens center and the untilted focus point
+    // `film` crosses the (possibly tilted) plane of focus
+    fn focus_point(&self, film: Point3) -> Point3 {
+        if !self.tilted {
+            return film;
+        }
+        let d = film - self.origin;
+        let denom = dot(d, self.focus_normal);
+        if denom.abs() < 1e-6 {
+            return film;
+        }
+        let plane_dist = dot(self.w.inv() * self.focus_dist, self.focus_normal);
+        self.origin + d * (plane_dist / denom)
+    }
+
+    // Direction at angle `theta` off the view axis, `phi` around it
+    fn direction_from_angles(&self, theta: f32, phi: f32) -> Vec3 {
+        (self.u * phi.cos() + self.v * phi.sin()) * theta.sin() - self.w * theta.cos()
+    }
+}
+
+#[derive(Debug)]
+pub struct Camera {
+    view: View,
+    lens_radius: f32,
+    time0: f32,
+    time1: f32,
+    aperture_shape: ApertureShape,
+    /// Built for masks and cat-eye clipping
+    aperture_table: Option<Arc<ApertureTable>>,
+    cat_eye: f32,
+    /// Tilt and shift as given to `set_tilt_shift`, to pose moving cameras
+    tilt_shift: (f32, f32, f32, f32),
+    aspect_ratio: f32,
+    projection: Projection,
+    exposure: Option<Exposure>,
+    /// Height of a pixel as a fraction of the frame, 0 for thin rays
+    pixel_size: f32,
+    /// Tracks the camera is posed from at the time of each ray
+    motion: Option<Arc<CameraAnimation>>,
+}
+impl Camera {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
+        time0: f32,
+        time1: f32,
+    ) -> Self {
+        let lens_radius = aperture / 2.;
+
+        Self {
+            view: View::from(lookfrom, lookat, vup, vfov, aspect_ratio, focus_dist),
             lens_radius,
             time0,
             time1,
+            aperture_shape: ApertureShape::Circle,
+            aperture_table: None,
+            cat_eye: 0.,
+            tilt_shift: (0., 0., 0., 0.),
+            aspect_ratio,
+            projection: Projection::Perspective,
+            exposure: None,
+            pixel_size: 0.,
+            motion: None,
+        }
+    }
+
+    /// Lens effects (aperture, tilt-shift) only apply to the perspective and
+    /// orthographic projections, the others are pinholes.
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+    /// Uses physical exposure for the image brightness, and opens the lens to
+    /// `focal_length / f_stop`, with the focal length in scene units.
+    pub fn set_exposure(&mut self, exposure: Exposure, focal_length: f32) {
+        self.lens_radius = focal_length / exposure.f_stop / 2.;
+        self.exposure = Some(exposure);
+    }
+    /// Factor applied to the rendered radiance, 1 without an exposure set.
+    pub fn exposure_scale(&self) -> f32 {
+        self.exposure.map_or(1., |e| e.scale())
+    }
+    /// Makes each ray stand for the cone of one of `height` pixel rows, so
+    /// textures are filtered to the size of a pixel where they are hit.
+    /// Without it rays are thin and textures are read at full resolution.
+    pub fn set_image_height(&mut self, height: usize) {
+        self.pixel_size = 1. / height.max(1) as f32;
+    }
+    // Width at the origin and spread per unit distance of a pixel's cone
+    fn pixel_cone(&self, view: &View) -> (f32, f32) {
+        let frame_height = view.vertical.len();
+        match self.projection {
+            Projection::Perspective => (0., frame_height / view.focus_dist * self.pixel_size),
+            Projection::Orthographic => (frame_height * self.pixel_size, 0.),
+            Projection::Fisheye { fov, .. } => {
+                (0., degrees_to_radians(fov) / self.aspect_ratio * self.pixel_size)
+            }
+            Projection::Equirectangular => (0., PI * self.pixel_size),
+        }
+    }
+    pub fn set_aperture_shape(&mut self, shape: ApertureShape) {
+        self.aperture_shape = shape;
+        self.aperture_table = None;
+        self.tabulate_aperture();
+    }
+    /// Clips the aperture with a lens sized circle displaced towards the
+    /// frame edge by `strength` (0 = off, 1 = a full radius at the corners),
+    /// squeezing off-center bokeh into cat-eye shapes.
+    pub fn set_cat_eye(&mut self, strength: f32) {
+        self.cat_eye = clamp(strength, 0., 1.);
+        self.tabulate_aperture();
+    }
+    // Masks are always sampled from a table, circles and polygons only once
+    // they are clipped
+    fn tabulate_aperture(&mut self) {
+        let needed = matches!(self.aperture_shape, ApertureShape::Mask(_)) || self.cat_eye > 0.;
+        if needed && self.aperture_table.is_none() {
+            self.aperture_table = Some(Arc::new(ApertureTable::from(&self.aperture_shape)));
         }
     }
+    /// Tilts the plane of focus by `tilt_x` degrees around the horizontal
+    /// axis and `tilt_y` degrees around the vertical axis, and shifts the
+    /// frame by `shift_x`, `shift_y` fractions of its width and height.
+    pub fn set_tilt_shift(&mut self, tilt_x: f32, tilt_y: f32, shift_x: f32, shift_y: f32) {
+        self.tilt_shift = (tilt_x, tilt_y, shift_x, shift_y);
+        self.view.set_tilt_shift(self.tilt_shift);
+    }
+    /// Moves the camera along `motion`'s lookfrom, lookat, vfov and focus
+    /// distance tracks, taken at the time of each ray. The vup, aspect ratio
+    /// and aperture stay the camera's own.
+    pub fn set_motion(&mut self, motion: Arc<CameraAnimation>) {
+        self.motion = Some(motion);
+    }
+
+    // The view `motion` has at `time`, tilted and shifted like the camera's
+    fn view_at(&self, motion: &CameraAnimation, time: f32) -> View {
+        let mut view = View::from(
+            motion.lookfrom.value_at(time),
+            motion.lookat.value_at(time),
+            motion.vup,
+            motion.vfov.value_at(time),
+            self.aspect_ratio,
+            motion.focus_dist.value_at(time),
+        );
+        if self.tilt_shift != (0., 0., 0., 0.) {
+            view.set_tilt_shift(self.tilt_shift);
+        }
+        view
+    }
+
+    fn sample_lens(&self, s: f32, t: f32) -> Vec3 {
+        let (u, v) = sample_2d();
+        let Some(table) = &self.aperture_table else {
+            return self.aperture_shape.sample(u, v);
+        };
+        let p = if self.cat_eye > 0. {
+            // The barrel's exit pupil, seen from off axis, is a unit circle
+            // offset towards the frame edge. An aperture it hides entirely
+            // is left unclipped
+            let clip = Vec3::from(2. * s - 1., 2. * t - 1., 0.) * self.cat_eye;
+            table.sample_clipped(u, v, clip).or_else(|| table.sample(u, v))
+        } else {
+            table.sample(u, v)
+        };
+        // A mask with nothing open is a pinhole
+        p.unwrap_or_else(Vec3::new)
+    }
+
     pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
-        let offset = self.u * rd.x() + self.v * rd.y();
+        let time = self.time0 + sample_1d() * (self.time1 - self.time0);
+        match &self.motion {
+            Some(motion) => self.get_ray_from(&self.view_at(motion, time), s, t, time),
+            None => self.get_ray_from(&self.view, s, t, time),
+        }
+    }
 
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-            random_double(self.time0, self.time1),
-        )
+    fn get_ray_from(&self, view: &View, s: f32, t: f32, time: f32) -> Ray {
+        let (width, spread) = self.pixel_cone(view);
+        let r = match self.projection {
+            Projection::Perspective => {
+                let rd = self.sample_lens(s, t) * self.lens_radius;
+                let offset = view.u * rd.x() + view.v * rd.y();
+                let film =
+                    view.lower_left_corner + view.horizontal * s + view.vertical * t + view.shift;
+                let target = view.focus_point(film);
+
+                Ray::new(view.origin + offset, target - view.origin - offset, time)
+            }
+            Projection::Orthographic => {
+                let rd = self.sample_lens(s, t) * self.lens_radius;
+                let offset = view.u * rd.x() + view.v * rd.y();
+                let film =
+                    view.lower_left_corner + view.horizontal * s + view.vertical * t + view.shift;
+                // Move the whole frame back to the camera plane
+                let start = film + view.w * view.focus_dist;
+                let target = view.focus_point(film);
+
+                Ray::new(start + offset, target - start - offset, time)
+            }
+            Projection::Fisheye { fov, mapping } => {
+                let x = 2. * s - 1.;
+                let y = (2. * t - 1.) / self.aspect_ratio;
+                let r = (x * x + y * y).sqrt();
+                let theta_max = degrees_to_radians(fov) / 2.;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * theta_max,
+                    FisheyeMapping::Equisolid => {
+                        2. * clamp(r * (theta_max / 2.).sin(), -1., 1.).asin()
+                    }
+                };
+                let theta = theta.min(PI);
+
+                Ray::new(view.origin, view.direction_from_angles(theta, y.atan2(x)), time)
+            }
+            Projection::Equirectangular => {
+                let longitude = (s - 0.5) * 2. * PI;
+                let latitude = (t - 0.5) * PI;
+                let direction = (view.u * longitude.sin() - view.w * longitude.cos())
+                    * latitude.cos()
+                    + view.v * latitude.sin();
+
+                Ray::new(view.origin, direction, time)
+            }
+        };
+        r.with_cone(width, spread)
     }
-}
\ No newline at end of file
+}
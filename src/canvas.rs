@@ -8,28 +8,202 @@
 //              progress tracking bar during runtime. These functions take the
 //              the pixel computing function in as a parameter. There are also
 //              various helper methods and methods to properly write out the
-//              image in ppm format. 
+//              image in ppm format. The progressive render adds samples in
+//              passes until each pixel converges, splatting them onto a
+//              filtered film, and can be limited to a crop window,
+//              checkpointed, resumed, merged and composited. The canvas can
+//              also keep AOV passes and denoise the finished image.
 */
 
 
+use crate::aov::*;
 use crate::colors::write_color_to_writer;
+use crate::denoise::*;
+use crate::filter::PixelFilter;
+use crate::sampler::*;
+use crate::stats::*;
 use crate::vec3::Color;
 use rayon::prelude::*;
 
+use std::fs::File;
 use std::io::stdout;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 //use std::sync::{Mutex , Condvar , Arc , atomic::{Ordering , AtomicUsize}};
 
 pub struct Canvas {
+    /// Sum of the samples taken in each pixel
     pixels: Box<[Color]>,
-    samples_per_pixel: usize,
+    /// Sum of the squared luminance of each sample, for the variance
+    lum_sq: Box<[f32]>,
+    counts: Box<[usize]>,
+    /// Filter weighted sum of the samples splatted onto each pixel
+    film: Box<[Color]>,
+    film_weights: Box<[f32]>,
+    /// AOV sums, when they are captured
+    aovs: Option<Box<[AovPixel]>>,
+    /// Denoised image, shown in place of the film once computed
+    denoised: Option<Box<[Color]>>,
     xsize: usize,
     ysize: usize,
+    /// Position of this canvas' first pixel in the full frame, non zero
+    /// when only a crop window is rendered
+    origin: (usize, usize),
+    /// Size of the full frame the canvas is part of
+    frame: (usize, usize),
+    /// Identifies the scene and settings the samples were rendered with
+    fingerprint: u64,
+    /// Sampler seeds of the renders whose samples the canvas holds
+    seeds: Vec<u32>,
+    exposure: f32,
+}
+
+/// Rectangle of pixels `x0..x1`, `y0..y1` in a frame, rows counted from the
+/// top of the image as it is written out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropWindow {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl CropWindow {
+    pub fn from(x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        Self { x0, y0, x1, y1 }
+    }
+    /// Window given as fractions of the frame, (0, 0) top left.
+    pub fn from_normalized(
+        fx0: f32,
+        fy0: f32,
+        fx1: f32,
+        fy1: f32,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let to_px = |f: f32, n: usize| ((f.clamp(0., 1.) * n as f32).round() as usize).min(n);
+        Self::from(
+            to_px(fx0, width),
+            to_px(fy0, height),
+            to_px(fx1, width),
+            to_px(fy1, height),
+        )
+    }
+    /// The window clamped to the frame, as an origin and size in canvas
+    /// coordinates (bottom row first).
+    pub fn canvas_rect(&self, width: usize, height: usize) -> ((usize, usize), (usize, usize)) {
+        let x1 = self.x1.min(width);
+        let y1 = self.y1.min(height);
+        let x0 = self.x0.min(x1);
+        let y0 = self.y0.min(y1);
+        ((x0, height - y1), (x1 - x0, y1 - y0))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveSettings {
+    pub min_spp: usize,
+    pub max_spp: usize,
+    /// Samples added to every unconverged pixel per pass
+    pub pass_spp: usize,
+    /// Relative standard error of the pixel mean at which a pixel is done
+    pub threshold: f32,
+    /// Stops starting new passes after this long, whatever the error
+    pub time_budget: Option<Duration>,
+    /// File the accumulated samples are saved to between passes
+    pub checkpoint_path: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    /// Only render this part of the frame
+    pub crop: Option<CropWindow>,
+    /// Reconstruction filter the samples are splatted with
+    pub filter: PixelFilter,
+    pub sampler: Arc<dyn Sampler>,
+    /// Picks the sampler's sequences. Partial renders to be merged need
+    /// different seeds, or they take the very same samples
+    pub seed: u32,
+    /// Capture the AOV passes of render functions that return them
+    pub aovs: bool,
+    /// Identifies the scene and settings, see `fingerprint`. Checkpoints
+    /// carry it so a render is never resumed with another scene's samples
+    pub fingerprint: u64,
+}
+
+/// What a progressive render function returns for one sample, either just
+/// its color or an aovsample carrying the AOV passes too.
+pub trait RenderSample: Send {
+    fn color(&self) -> Color;
+    fn aov(&self) -> Option<&AovSample> {
+        None
+    }
+}
+impl RenderSample for Color {
+    fn color(&self) -> Color {
+        *self
+    }
+}
+impl RenderSample for AovSample {
+    fn color(&self) -> Color {
+        AovSample::color(self)
+    }
+    fn aov(&self) -> Option<&AovSample> {
+        Some(self)
+    }
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        Self {
+            min_spp: 64,
+            max_spp: 5000,
+            pass_spp: 16,
+            threshold: 0.01,
+            time_budget: None,
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(300),
+            crop: None,
+            filter: PixelFilter::default(),
+            sampler: Arc::new(IndependentSampler),
+            seed: 0,
+            aovs: false,
+            fingerprint: 0,
+        }
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RTCK";
+const CHECKPOINT_VERSION: u32 = 1;
+// Magic, version, then size, origin, fingerprint, frame size and the
+// number of seeds, which follow the header
+const CHECKPOINT_HEADER_LEN: u64 = 8 + 8 * 8;
+// Color sum, squared luminance sum, count, film sum and film weight
+const CHECKPOINT_PIXEL_LEN: u64 = 12 + 4 + 8 + 12 + 4;
+
+const HISTOGRAM_BINS: usize = 128;
+const HISTOGRAM_MIN_LOG2: f32 = -16.;
+const HISTOGRAM_MAX_LOG2: f32 = 16.;
+
+/// Hash of a description of the scene and render settings, the same on
+/// every run and platform, for `AdaptiveSettings::fingerprint`.
+pub fn fingerprint(description: &str) -> u64 {
+    // FNV-1a
+    description.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn luminance(c: Color) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
 }
 
 //const UPDATE_INTEVAL: usize = 1024;
 
+// A pixel's index, the film positions and colors of the samples it took in
+// a pass, and their AOVs
+type PixelSamples = (usize, Vec<(f32, f32, Color)>, Option<AovPixel>);
+
 #[inline]
 fn index_to_xy(xsize: usize , index: usize) -> (usize , usize){
     (index % xsize , index / xsize)
@@ -56,10 +230,39 @@ impl Canvas {
         
 
         Self {
-            samples_per_pixel,
             pixels,
+            lum_sq: vec![0.; size].into_boxed_slice(),
+            counts: vec![samples_per_pixel; size].into_boxed_slice(),
+            film: vec![Color::default(); size].into_boxed_slice(),
+            film_weights: vec![0.; size].into_boxed_slice(),
+            aovs: None,
+            denoised: None,
             xsize: x,
             ysize: y,
+            origin: (0, 0),
+            frame: (x, y),
+            fingerprint: 0,
+            seeds: vec![],
+            exposure: 1.,
+        }
+    }
+    pub fn new(x: usize, y: usize) -> Self {
+        let size = x.checked_mul(y).unwrap();
+        Self {
+            pixels: vec![Color::default(); size].into_boxed_slice(),
+            lum_sq: vec![0.; size].into_boxed_slice(),
+            counts: vec![0; size].into_boxed_slice(),
+            film: vec![Color::default(); size].into_boxed_slice(),
+            film_weights: vec![0.; size].into_boxed_slice(),
+            aovs: None,
+            denoised: None,
+            xsize: x,
+            ysize: y,
+            origin: (0, 0),
+            frame: (x, y),
+            fingerprint: 0,
+            seeds: vec![],
+            exposure: 1.,
         }
     }
     pub fn from_fn_parallel<F>(x: usize, y: usize, samples_per_pixel: usize, f: F) -> Self
@@ -73,10 +276,499 @@ impl Canvas {
             f(x , y)
         }));
         Self {
-            samples_per_pixel,
             pixels: pixels.into_boxed_slice(),
+            lum_sq: vec![0.; size].into_boxed_slice(),
+            counts: vec![samples_per_pixel; size].into_boxed_slice(),
+            film: vec![Color::default(); size].into_boxed_slice(),
+            film_weights: vec![0.; size].into_boxed_slice(),
+            aovs: None,
+            denoised: None,
             xsize: x,
             ysize: y,
+            origin: (0, 0),
+            frame: (x, y),
+            fingerprint: 0,
+            seeds: vec![],
+            exposure: 1.,
+        }
+    }
+
+    /// Renders with passes of `settings.pass_spp` samples, `f` returning a
+    /// single sample at a film position in pixels (pixel `i` spans `i` to
+    /// `i + 1`), until every pixel has converged, reached `max_spp`, or the
+    /// time budget is spent. A pixel has converged once the relative error
+    /// of its mean luminance, from its sample count and luminance moments,
+    /// drops below `settings.threshold`. Samples are also splatted onto a
+    /// film through the reconstruction filter, and the image is written
+    /// from the film.
+    pub fn from_fn_progressive<F, S>(x: usize, y: usize, settings: AdaptiveSettings, f: F) -> Self
+    where
+        F: Fn(f32, f32) -> S + Send + Sync,
+        S: RenderSample,
+    {
+        let mut canvas = match settings.crop {
+            Some(crop) => Self::from_crop(x, y, crop),
+            None => Self::new(x, y),
+        };
+        canvas.fingerprint = settings.fingerprint;
+        canvas.render_progressive(&settings, f);
+        canvas
+    }
+
+    /// Empty canvas covering `crop` of an `x` by `y` frame. Samples are passed
+    /// to the render function with their full frame coordinates. The frame
+    /// around the crop is sampled as far as the filter reaches, so the edge
+    /// pixels get the same splats as in a full render, and splats landing
+    /// outside the crop are dropped.
+    pub fn from_crop(x: usize, y: usize, crop: CropWindow) -> Self {
+        let (origin, (w, h)) = crop.canvas_rect(x, y);
+        let mut canvas = Self::new(w, h);
+        canvas.origin = origin;
+        canvas.frame = (x, y);
+        canvas
+    }
+    pub fn origin(&self) -> (usize, usize) {
+        self.origin
+    }
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+    pub fn seeds(&self) -> &[u32] {
+        &self.seeds
+    }
+
+    /// Replaces the pixels under a cropped render with its samples. The
+    /// canvas must be a full render of the same frame, scene and settings,
+    /// and keep AOVs only if the crop has them too.
+    pub fn composite(&mut self, region: &Canvas) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::new(ErrorKind::InvalidInput, reason.to_string()));
+        if self.origin != (0, 0) || self.dimensions() != self.frame {
+            return invalid("can only composite onto a full frame render");
+        }
+        if region.frame != self.frame {
+            return invalid("crop window is from a frame of another size");
+        }
+        if region.fingerprint != self.fingerprint {
+            return invalid("cannot composite renders of different scenes or settings");
+        }
+        if self.aovs.is_some() && region.aovs.is_none() {
+            return invalid("crop window has no AOVs to replace the frame's");
+        }
+        let (ox, oy) = region.origin;
+        if ox + region.xsize > self.xsize || oy + region.ysize > self.ysize {
+            return invalid("crop window lies outside the frame");
+        }
+        self.denoised = None;
+        for seed in &region.seeds {
+            if !self.seeds.contains(seed) {
+                self.seeds.push(*seed);
+            }
+        }
+        for y in 0..region.ysize {
+            for x in 0..region.xsize {
+                let src = y * region.xsize + x;
+                let dst = (y + oy) * self.xsize + (x + ox);
+                self.pixels[dst] = region.pixels[src];
+                self.lum_sq[dst] = region.lum_sq[src];
+                self.counts[dst] = region.counts[src];
+                self.film[dst] = region.film[src];
+                self.film_weights[dst] = region.film_weights[src];
+                if let Some(region_aovs) = &region.aovs {
+                    let size = self.pixels.len();
+                    let aovs = self
+                        .aovs
+                        .get_or_insert_with(|| vec![AovPixel::default(); size].into_boxed_slice());
+                    aovs[dst] = region_aovs[src];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps adding passes to the samples already in the canvas, so a canvas
+    /// loaded from a checkpoint carries on where it stopped.
+    pub fn render_progressive<F, S>(&mut self, settings: &AdaptiveSettings, f: F)
+    where
+        F: Fn(f32, f32) -> S + Send + Sync,
+        S: RenderSample,
+    {
+        let start = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        let mut pass = 0;
+
+        loop {
+            let sampled = self.render_pass(settings.pass_spp.max(1), settings, &f);
+            pass += 1;
+            eprint!("\rPass {}: {} pixels sampled     ", pass, sampled);
+
+            let out_of_time = settings
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget);
+            let finished = sampled == 0 || out_of_time;
+
+            if let Some(path) = &settings.checkpoint_path {
+                if finished || last_checkpoint.elapsed() >= settings.checkpoint_interval {
+                    if let Err(e) = self.save_checkpoint(path) {
+                        eprintln!("\nERROR: could not write checkpoint {}: {}", path.display(), e);
+                    }
+                    last_checkpoint = Instant::now();
+                }
+            }
+            if finished {
+                break;
+            }
+        }
+    }
+
+    /// Adds up to `spp` samples to each pixel that still needs them and
+    /// returns how many pixels were sampled. Samples are traced in parallel
+    /// and splatted onto the film once the pass is done. Each one is started
+    /// on the settings' sampler, which also gives its position in the pixel.
+    pub fn render_pass<F, S>(&mut self, spp: usize, settings: &AdaptiveSettings, f: &F) -> usize
+    where
+        F: Fn(f32, f32) -> S + Send + Sync,
+        S: RenderSample,
+    {
+        self.denoised = None;
+        if !self.seeds.contains(&settings.seed) {
+            self.seeds.push(settings.seed);
+        }
+        let xsize = self.xsize;
+        let (ox, oy) = self.origin;
+        let samples: Vec<PixelSamples> = self
+            .pixels
+            .par_iter_mut()
+            .zip(self.lum_sq.par_iter_mut())
+            .zip(self.counts.par_iter_mut())
+            .enumerate()
+            .filter(|(_, ((p, sq), n))| !pixel_done(**p, **sq, **n, settings))
+            .map(|(idx, ((p, sq), n))| {
+                let (x, y) = index_to_xy(xsize, idx);
+                let (x, y) = (x + ox, y + oy);
+                let take = spp.min(settings.max_spp - *n);
+                let mut taken = Vec::with_capacity(take);
+                let mut aov: Option<AovPixel> = None;
+                for k in 0..take {
+                    start_sample(&settings.sampler, (x, y), *n + k, settings.seed);
+                    let (jx, jy) = sample_2d();
+                    let (sx, sy) = (x as f32 + jx, y as f32 + jy);
+                    let result = f(sx, sy);
+                    let sample = result.color();
+                    let lum = luminance(sample);
+                    p.add(sample);
+                    *sq += lum * lum;
+                    taken.push((sx, sy, sample));
+                    if let (true, Some(s)) = (settings.aovs, result.aov()) {
+                        aov.get_or_insert_with(AovPixel::default).add(s);
+                    }
+                }
+                end_sample();
+                count_samples(take as u64);
+                *n += take;
+                (idx, taken, aov)
+            })
+            .collect();
+
+        let margin = self.sample_margin(&samples, settings, f);
+        for &(sx, sy, sample) in &margin {
+            self.splat(&settings.filter, sx, sy, sample);
+        }
+        for (idx, taken, aov) in &samples {
+            for &(sx, sy, sample) in taken {
+                self.splat(&settings.filter, sx, sy, sample);
+            }
+            if let Some(aov) = aov {
+                let size = self.pixels.len();
+                let aovs = self
+                    .aovs
+                    .get_or_insert_with(|| vec![AovPixel::default(); size].into_boxed_slice());
+                aovs[*idx].merge(aov);
+            }
+        }
+        samples.len()
+    }
+
+    /// Samples the pixels of the frame outside the canvas whose samples can
+    /// splat into it, each as many times as the nearest canvas pixel was
+    /// sampled in this pass. They are only splatted, the canvas keeps no
+    /// sums or counts for them.
+    fn sample_margin<F, S>(
+        &self,
+        pass: &[PixelSamples],
+        settings: &AdaptiveSettings,
+        f: &F,
+    ) -> Vec<(f32, f32, Color)>
+    where
+        F: Fn(f32, f32) -> S + Send + Sync,
+        S: RenderSample,
+    {
+        let (ox, oy) = self.origin;
+        let m = settings.filter.radius.max(0.).ceil() as usize;
+        let (x0, y0) = (ox.saturating_sub(m), oy.saturating_sub(m));
+        let x1 = (ox + self.xsize + m).min(self.frame.0);
+        let y1 = (oy + self.ysize + m).min(self.frame.1);
+        if pass.is_empty() || (x0, y0, x1, y1) == (ox, oy, ox + self.xsize, oy + self.ysize) {
+            return Vec::new();
+        }
+
+        // Sample index and count each canvas pixel took in the pass
+        let mut takes = vec![(0, 0); self.pixels.len()];
+        for (idx, taken, _) in pass {
+            takes[*idx] = (self.counts[*idx] - taken.len(), taken.len());
+        }
+        let outside: Vec<(usize, usize)> = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                !(ox..ox + self.xsize).contains(&x) || !(oy..oy + self.ysize).contains(&y)
+            })
+            .collect();
+        outside
+            .into_par_iter()
+            .flat_map_iter(|(x, y)| {
+                let nx = x.clamp(ox, ox + self.xsize - 1) - ox;
+                let ny = y.clamp(oy, oy + self.ysize - 1) - oy;
+                let (n, take) = takes[ny * self.xsize + nx];
+                let mut taken = Vec::with_capacity(take);
+                for k in 0..take {
+                    start_sample(&settings.sampler, (x, y), n + k, settings.seed);
+                    let (jx, jy) = sample_2d();
+                    let (sx, sy) = (x as f32 + jx, y as f32 + jy);
+                    taken.push((sx, sy, f(sx, sy).color()));
+                }
+                end_sample();
+                count_samples(take as u64);
+                taken
+            })
+            .collect()
+    }
+
+    /// Adds a sample at full frame film position `sx`, `sy` to every pixel
+    /// of the canvas under the filter.
+    pub fn splat(&mut self, filter: &PixelFilter, sx: f32, sy: f32, sample: Color) {
+        if !(sample.x().is_finite() && sample.y().is_finite() && sample.z().is_finite()) {
+            return;
+        }
+        self.denoised = None;
+        // Film position relative to this canvas, pixel centers at i + 0.5
+        let cx = sx - self.origin.0 as f32 - 0.5;
+        let cy = sy - self.origin.1 as f32 - 0.5;
+        let r = filter.radius;
+        let x0 = (cx - r).ceil().max(0.) as usize;
+        let y0 = (cy - r).ceil().max(0.) as usize;
+        let x1 = (cx + r).floor().min(self.xsize as f32 - 1.);
+        let y1 = (cy + r).floor().min(self.ysize as f32 - 1.);
+        if x1 < 0. || y1 < 0. {
+            return;
+        }
+        for y in y0..=y1 as usize {
+            for x in x0..=x1 as usize {
+                let weight = filter.eval(x as f32 - cx, y as f32 - cy);
+                if weight == 0. {
+                    continue;
+                }
+                let idx = y * self.xsize + x;
+                self.film[idx].add(sample * weight);
+                self.film_weights[idx] += weight;
+            }
+        }
+    }
+
+    /// Writes the raw sample sums and counts. The file is written next to
+    /// `path` first and renamed over it, so an interrupted write never
+    /// leaves a broken checkpoint behind. The fingerprint, frame and seeds
+    /// are kept with the samples, so the render can be resumed or merged.
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            w.write_all(CHECKPOINT_MAGIC)?;
+            w.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+            w.write_all(&(self.xsize as u64).to_le_bytes())?;
+            w.write_all(&(self.ysize as u64).to_le_bytes())?;
+            w.write_all(&(self.origin.0 as u64).to_le_bytes())?;
+            w.write_all(&(self.origin.1 as u64).to_le_bytes())?;
+            w.write_all(&self.fingerprint.to_le_bytes())?;
+            w.write_all(&(self.frame.0 as u64).to_le_bytes())?;
+            w.write_all(&(self.frame.1 as u64).to_le_bytes())?;
+            w.write_all(&(self.seeds.len() as u64).to_le_bytes())?;
+            for seed in &self.seeds {
+                w.write_all(&seed.to_le_bytes())?;
+            }
+            for idx in 0..self.pixels.len() {
+                for c in self.pixels[idx].e {
+                    w.write_all(&c.to_le_bytes())?;
+                }
+                w.write_all(&self.lum_sq[idx].to_le_bytes())?;
+                w.write_all(&(self.counts[idx] as u64).to_le_bytes())?;
+                for c in self.film[idx].e {
+                    w.write_all(&c.to_le_bytes())?;
+                }
+                w.write_all(&self.film_weights[idx].to_le_bytes())?;
+            }
+            w.write_all(&[self.aovs.is_some() as u8])?;
+            if let Some(aovs) = &self.aovs {
+                for aov in aovs.iter() {
+                    for word in aov.to_words() {
+                        w.write_all(&word.to_le_bytes())?;
+                    }
+                }
+            }
+            w.flush()?;
+        }
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Reads a checkpoint written by `save_checkpoint`. The file's length is
+    /// checked against its header before any pixels are allocated.
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a render checkpoint"));
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut r)?);
+        if version != CHECKPOINT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported checkpoint version {}", version),
+            ));
+        }
+        let mut words = [0u64; 8];
+        for word in words.iter_mut() {
+            *word = u64::from_le_bytes(read_bytes(&mut r)?);
+        }
+        let [x, y, ox, oy, fingerprint, fx, fy, seed_count] = words;
+        let (x, y) = (x as usize, y as usize);
+        let origin = (ox as usize, oy as usize);
+        let frame = (fx as usize, fy as usize);
+
+        // Check the file holds every pixel before allocating for them, so a
+        // corrupt size fails here instead of exhausting memory
+        let too_short = || Error::new(ErrorKind::InvalidData, "checkpoint is shorter than its size");
+        let size = x
+            .checked_mul(y)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "checkpoint size overflows"))?;
+        let pixels_len = (size as u64).checked_mul(CHECKPOINT_PIXEL_LEN).ok_or_else(too_short)?;
+        let body_len = seed_count
+            .checked_mul(4)
+            .and_then(|seeds_len| seeds_len.checked_add(pixels_len))
+            .ok_or_else(too_short)?;
+        if body_len > file_len.saturating_sub(CHECKPOINT_HEADER_LEN) {
+            return Err(too_short());
+        }
+        let mut seeds = Vec::with_capacity(seed_count as usize);
+        for _ in 0..seed_count {
+            seeds.push(u32::from_le_bytes(read_bytes(&mut r)?));
+        }
+
+        let mut canvas = Self::new(x, y);
+        canvas.origin = origin;
+        canvas.fingerprint = fingerprint;
+        canvas.frame = frame;
+        canvas.seeds = seeds;
+        for idx in 0..size {
+            let mut c = Color::new();
+            for i in 0..3 {
+                c[i] = f32::from_le_bytes(read_bytes(&mut r)?);
+            }
+            canvas.pixels[idx] = c;
+            canvas.lum_sq[idx] = f32::from_le_bytes(read_bytes(&mut r)?);
+            canvas.counts[idx] = u64::from_le_bytes(read_bytes(&mut r)?) as usize;
+            for i in 0..3 {
+                c[i] = f32::from_le_bytes(read_bytes(&mut r)?);
+            }
+            canvas.film[idx] = c;
+            canvas.film_weights[idx] = f32::from_le_bytes(read_bytes(&mut r)?);
+        }
+        if read_bytes::<_, 1>(&mut r)?[0] != 0 {
+            let mut aovs = vec![AovPixel::default(); size].into_boxed_slice();
+            for aov in aovs.iter_mut() {
+                let mut words = [0u32; AOV_PIXEL_WORDS];
+                for word in words.iter_mut() {
+                    *word = u32::from_le_bytes(read_bytes(&mut r)?);
+                }
+                *aov = AovPixel::from_words(&words);
+            }
+            canvas.aovs = Some(aovs);
+        }
+        Ok(canvas)
+    }
+
+    /// Adds the samples of another render of the same frame to this one.
+    /// The two must have been rendered with different seeds, since the same
+    /// seed takes the very same samples again.
+    pub fn merge(&mut self, other: &Canvas) -> Result<(), Error> {
+        if self.dimensions() != other.dimensions() || self.origin != other.origin {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot merge a {}x{} render into a {}x{} one",
+                    other.xsize, other.ysize, self.xsize, self.ysize
+                ),
+            ));
+        }
+        if self.fingerprint != other.fingerprint {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "cannot merge renders of different scenes or settings",
+            ));
+        }
+        if let Some(seed) = other.seeds.iter().find(|seed| self.seeds.contains(seed)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("both renders took the samples of seed {}", seed),
+            ));
+        }
+        self.seeds.extend_from_slice(&other.seeds);
+        self.denoised = None;
+        for idx in 0..self.pixels.len() {
+            self.pixels[idx].add(other.pixels[idx]);
+            self.lum_sq[idx] += other.lum_sq[idx];
+            self.counts[idx] += other.counts[idx];
+            self.film[idx].add(other.film[idx]);
+            self.film_weights[idx] += other.film_weights[idx];
+        }
+        if let Some(other_aovs) = &other.aovs {
+            let size = self.pixels.len();
+            let aovs = self
+                .aovs
+                .get_or_insert_with(|| vec![AovPixel::default(); size].into_boxed_slice());
+            for (aov, other) in aovs.iter_mut().zip(other_aovs.iter()) {
+                aov.merge(other);
+            }
+        }
+        Ok(())
+    }
+
+    /// Relative standard error of the pixel mean's luminance.
+    pub fn relative_error(&self, idx: usize) -> f32 {
+        relative_error(self.pixels[idx], self.lum_sq[idx], self.counts[idx])
+    }
+    pub fn sample_count(&self, idx: usize) -> usize {
+        self.counts[idx]
+    }
+    pub fn total_samples(&self) -> usize {
+        self.counts.iter().sum()
+    }
+    /// Average of the samples in a pixel.
+    pub fn pixel_mean(&self, idx: usize) -> Color {
+        self.pixels[idx] / self.counts[idx].max(1) as f32
+    }
+    /// Filtered pixel value, the plain average where nothing was splatted,
+    /// or the denoised value after `denoise` until more samples are added.
+    pub fn pixel_color(&self, idx: usize) -> Color {
+        if let Some(denoised) = &self.denoised {
+            return denoised[idx];
+        }
+        let weight = self.film_weights[idx];
+        if weight > 1e-6 {
+            self.film[idx] / weight
+        } else {
+            self.pixel_mean(idx)
         }
     }
     
@@ -86,8 +778,13 @@ impl Canvas {
         self.write_pixels_to_writer(&mut locked).unwrap();
     }
     pub fn write_pixels_to_writer<W: Write>(&self, writer: &mut W) -> Result<(), std::io::Error> {
-        self.iter_pixels()
-            .try_for_each(|&p| write_color_to_writer(writer, p, self.samples_per_pixel))
+        for y in (0..self.ysize).rev() {
+            for x in 0..self.xsize {
+                let idx = y * self.xsize + x;
+                write_color_to_writer(writer, self.pixel_color(idx) * self.exposure, 1)?;
+            }
+        }
+        Ok(())
     }
     pub fn write_header(&self) {
         self.write_header_to_writer(&mut stdout()).unwrap();
@@ -95,6 +792,123 @@ impl Canvas {
     pub fn write_header_to_writer<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
         writeln!(w, "P3\n{} {}\n255", self.xsize, self.ysize)
     }
+    /// Writes the image to a PPM file, as write_header and write_pixels
+    /// write it to stdout.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_header_to_writer(&mut w)?;
+        self.write_pixels_to_writer(&mut w)?;
+        w.flush()
+    }
+
+    /// Variance of the estimate of a pixel's luminance.
+    pub fn luminance_variance(&self, idx: usize) -> f32 {
+        let n = self.counts[idx];
+        let mean = luminance(self.pixels[idx]) / n.max(1) as f32;
+        if n < 2 {
+            return mean * mean;
+        }
+        let n = n as f32;
+        let variance = (self.lum_sq[idx] / n - mean * mean).max(0.) * n / (n - 1.);
+        variance / n
+    }
+
+    /// Runs the denoiser over the image, guided by the albedo, normal and
+    /// depth passes when AOVs were captured. The samples are kept, so the
+    /// canvas can still be checkpointed, merged or denoised again.
+    pub fn denoise(&mut self, settings: &DenoiseSettings) {
+        self.denoised = None;
+        let size = self.pixels.len();
+        let color: Vec<Color> = (0..size).map(|idx| self.pixel_color(idx)).collect();
+        let variance: Vec<f32> = (0..size).map(|idx| self.luminance_variance(idx)).collect();
+        let guides = self.aovs.as_ref().map(|aovs| DenoiseGuides {
+            albedo: aovs.iter().map(|a| a.value(AovPass::Albedo)).collect(),
+            normal: aovs.iter().map(|a| a.value(AovPass::Normal)).collect(),
+            depth: aovs.iter().map(|a| a.value(AovPass::Depth).x()).collect(),
+        });
+        let denoised = denoise(
+            self.xsize,
+            self.ysize,
+            &color,
+            &variance,
+            guides.as_ref(),
+            settings,
+        );
+        self.denoised = Some(denoised.into_boxed_slice());
+    }
+
+    /// AOVs are captured when the render function returns AovSamples and
+    /// `settings.aovs` is on.
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+    /// Values of one AOV pass, `pass.channels()` per pixel, bottom row first.
+    /// Light passes are scaled by the exposure. Empty without captured AOVs.
+    pub fn aov_pass(&self, pass: AovPass) -> Vec<f32> {
+        let Some(aovs) = &self.aovs else {
+            return vec![];
+        };
+        let scale = if pass.is_light() { self.exposure } else { 1. };
+        aovs.iter()
+            .flat_map(|aov| {
+                let value = aov.value(pass) * scale;
+                value.e.into_iter().take(pass.channels())
+            })
+            .collect()
+    }
+
+    /// Writes each AOV pass to `<prefix>_<pass>.pfm`.
+    pub fn write_aov_pfms(&self, prefix: &str) -> Result<(), Error> {
+        for pass in AovPass::ALL {
+            let data = self.aov_pass(pass);
+            if data.is_empty() {
+                continue;
+            }
+            // PFM holds 1 or 3 channels, so uv gets an empty third one
+            let (channels, data) = match pass.channels() {
+                2 => (
+                    3,
+                    data.chunks(2).flat_map(|uv| [uv[0], uv[1], 0.]).collect(),
+                ),
+                n => (n, data),
+            };
+            let path = format!("{}_{}.pfm", prefix, pass.name());
+            let mut w = BufWriter::new(File::create(path)?);
+            write_pfm(&mut w, self.xsize, self.ysize, channels, &data)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the exposed beauty image as R, G, B and every captured AOV
+    /// pass as its own layer of an EXR file.
+    pub fn write_exr<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let (w, h) = (self.xsize, self.ysize);
+        // EXR rows run top to bottom
+        let flip = |idx: usize| (h - 1 - idx / w) * w + idx % w;
+        let mut channels = vec![];
+        for (i, name) in ["R", "G", "B"].iter().enumerate() {
+            let data = (0..w * h)
+                .map(|idx| self.pixel_color(flip(idx))[i] * self.exposure)
+                .collect();
+            channels.push((name.to_string(), data));
+        }
+        for pass in AovPass::ALL {
+            let data = self.aov_pass(pass);
+            if data.is_empty() {
+                continue;
+            }
+            let n = pass.channels();
+            let names: &[&str] = if n == 1 { &["Y"] } else { &["R", "G", "B"][..n] };
+            for (i, channel) in names.iter().enumerate() {
+                let values = (0..w * h).map(|idx| data[flip(idx) * n + i]).collect();
+                channels.push((format!("{}.{}", pass.name(), channel), values));
+            }
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        write_exr(&mut out, w, h, &channels)?;
+        out.flush()
+    }
 
     pub fn iter_pixels(&self) -> impl Iterator<Item = &'_ Color> + '_ {
         self.pixels.chunks(self.xsize).rev().flatten()
@@ -104,7 +918,83 @@ impl Canvas {
         (self.xsize, self.ysize)
     }
     pub fn get_pixels(&self) -> &[Color] {
-        &*self.pixels
+        &self.pixels
+    }
+
+    /// Scales the pixels as they are written.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
     }
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Exposure that brings the scene's typical luminance to `key` (0.18 is
+    /// middle grey). Black pixels are ignored, and the average is taken over
+    /// the 50th to 95th percentile of a log2 luminance histogram so neither
+    /// shadows nor small bright lights dominate.
+    pub fn auto_exposure(&self, key: f32) -> f32 {
+        let mut histogram = [0usize; HISTOGRAM_BINS];
+        let bin_width = (HISTOGRAM_MAX_LOG2 - HISTOGRAM_MIN_LOG2) / HISTOGRAM_BINS as f32;
+        let mut count = 0;
+
+        for idx in 0..self.pixels.len() {
+            let lum = luminance(self.pixel_color(idx));
+            if lum <= 0. || !lum.is_finite() {
+                continue;
+            }
+            let bin = ((lum.log2() - HISTOGRAM_MIN_LOG2) / bin_width) as isize;
+            histogram[bin.clamp(0, HISTOGRAM_BINS as isize - 1) as usize] += 1;
+            count += 1;
+        }
+        if count == 0 {
+            return 1.;
+        }
+
+        let low = (count as f32 * 0.5) as usize;
+        let high = (count as f32 * 0.95) as usize;
+        let (mut seen, mut weight, mut sum) = (0, 0, 0.);
+        for (i, &n) in histogram.iter().enumerate() {
+            // Part of this bin that falls between the two percentiles
+            let take = (seen + n).min(high).saturating_sub(seen.max(low));
+            seen += n;
+            if take > 0 {
+                let log_lum = HISTOGRAM_MIN_LOG2 + (i as f32 + 0.5) * bin_width;
+                sum += log_lum * take as f32;
+                weight += take;
+            }
+        }
+        if weight == 0 {
+            return 1.;
+        }
+        key / (sum / weight as f32).exp2()
+    }
+}
+
+
+fn relative_error(sum: Color, lum_sq: f32, n: usize) -> f32 {
+    if n < 2 {
+        return f32::INFINITY;
+    }
+    let n = n as f32;
+    let mean = luminance(sum) / n;
+    let variance = (lum_sq / n - mean * mean).max(0.) * n / (n - 1.);
+    let std_error = (variance / n).sqrt();
+    if std_error == 0. {
+        return 0.;
+    }
+    std_error / mean.abs().max(1e-4)
 }
 
+fn pixel_done(sum: Color, lum_sq: f32, n: usize, settings: &AdaptiveSettings) -> bool {
+    if n >= settings.max_spp {
+        return true;
+    }
+    n >= settings.min_spp.max(2) && relative_error(sum, lum_sq, n) < settings.threshold
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], Error> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
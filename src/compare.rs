@@ -0,0 +1,572 @@
+/*
+// Description: This file provides image comparison for checking renders
+//              against references. Images load from PPM (P3 or P6), PNG or
+//              PFM files, or straight from a canvas, as linear colors. 8-bit
+//              images are decoded with the gamma 2 encoding the canvas
+//              writes, PFM images are taken as linear. Comparing a test image
+//              with a reference gives the MSE, RMSE and relative MSE of the
+//              linear colors, the PSNR and SSIM of the displayed values, and
+//              a FLIP-style perceptual difference: colors are blurred the way
+//              the eye's contrast sensitivity does, compared with the HyAB
+//              color distance in CIELab, and the error is boosted where edges
+//              or points differ. The per-pixel FLIP-style error can be
+//              written as a false-color image, and images saved as PFM to
+//              serve as references.
+*/
+
+use crate::aov::write_pfm;
+use crate::canvas::*;
+use crate::vec3::*;
+use stb_image::image::{load, LoadResult};
+use std::fmt;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io { path: String, error: std::io::Error },
+    Decode { path: String, reason: String },
+    SizeMismatch { test: (usize, usize), reference: (usize, usize) },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io { path, error } => write!(f, "could not read image `{}`: {}", path, error),
+            ImageError::Decode { path, reason } => {
+                write!(f, "could not decode image `{}`: {}", path, reason)
+            }
+            ImageError::SizeMismatch { test, reference } => write!(
+                f,
+                "images differ in size, {}x{} against a {}x{} reference",
+                test.0, test.1, reference.0, reference.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Linear colors, top row first.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn from(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+    /// The image a canvas writes, exposure included, before encoding.
+    pub fn from_canvas(canvas: &Canvas) -> Self {
+        let (width, height) = canvas.dimensions();
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                pixels.push(canvas.pixel_color(y * width + x) * canvas.exposure());
+            }
+        }
+        Self::from(width, height, pixels)
+    }
+
+    /// Loads a PPM, PFM or PNG image, going by the file's magic bytes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let path_str = path.as_ref().display().to_string();
+        let data = fs::read(&path).map_err(|error| ImageError::Io {
+            path: path_str.clone(),
+            error,
+        })?;
+        let decoded = match data.get(..2) {
+            Some(b"P3") | Some(b"P6") => decode_ppm(&data),
+            Some(b"PF") | Some(b"Pf") => decode_pfm(&data),
+            _ => decode_with_stb(&path_str),
+        };
+        decoded.map_err(|reason| ImageError::Decode {
+            path: path_str,
+            reason,
+        })
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Saves the linear colors as a PFM image.
+    pub fn save_pfm<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for row in self.pixels.chunks(self.width.max(1)).rev() {
+            for c in row {
+                data.extend(c.e);
+            }
+        }
+        let mut w = BufWriter::new(fs::File::create(path)?);
+        write_pfm(&mut w, self.width, self.height, 3, &data)?;
+        w.flush()
+    }
+}
+
+// How the canvas encodes for display: gamma 2 and clamped to [0, 1]
+fn encode(c: f32) -> f32 {
+    c.max(0.).sqrt().min(1.)
+}
+fn decode(v: f32) -> f32 {
+    v * v
+}
+
+// Whitespace separated header fields, skipping comments
+fn ppm_fields(data: &[u8], count: usize) -> Result<(Vec<String>, usize), String> {
+    let (mut fields, mut pos) = (vec![], 0);
+    while fields.len() < count {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos < data.len() && data[pos] == b'#' {
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("header ends early".to_string());
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+    }
+    // A single whitespace byte ends the header
+    Ok((fields, pos + 1))
+}
+
+fn parse_size(fields: &[String]) -> Result<(usize, usize), String> {
+    let parse = |s: &String| s.parse::<usize>().map_err(|_| format!("bad size `{}`", s));
+    Ok((parse(&fields[1])?, parse(&fields[2])?))
+}
+
+fn decode_ppm(data: &[u8]) -> Result<Image, String> {
+    let (fields, body) = ppm_fields(data, 4)?;
+    let (width, height) = parse_size(&fields)?;
+    let maxval: usize = fields[3]
+        .parse()
+        .map_err(|_| format!("bad maximum value `{}`", fields[3]))?;
+    if maxval == 0 || maxval > 255 {
+        return Err(format!("unsupported maximum value {}", maxval));
+    }
+    let values: Vec<usize> = if fields[0] == "P3" {
+        String::from_utf8_lossy(&data[body.min(data.len())..])
+            .split_ascii_whitespace()
+            .map(|v| v.parse().map_err(|_| format!("bad pixel value `{}`", v)))
+            .collect::<Result<_, _>>()?
+    } else {
+        data[body.min(data.len())..].iter().map(|&b| b as usize).collect()
+    };
+    if values.len() < width * height * 3 {
+        return Err("not enough pixel data".to_string());
+    }
+    // Middle of each quantization step
+    let level = |v: usize| decode((v as f32 + 0.5) / (maxval + 1) as f32);
+    let pixels = values
+        .chunks_exact(3)
+        .take(width * height)
+        .map(|c| Color::from(level(c[0]), level(c[1]), level(c[2])))
+        .collect();
+    Ok(Image::from(width, height, pixels))
+}
+
+fn decode_pfm(data: &[u8]) -> Result<Image, String> {
+    let (fields, body) = ppm_fields(data, 4)?;
+    let channels = if fields[0] == "PF" { 3 } else { 1 };
+    let (width, height) = parse_size(&fields)?;
+    let scale: f32 = fields[3]
+        .parse()
+        .map_err(|_| format!("bad scale `{}`", fields[3]))?;
+    let bytes = &data[body.min(data.len())..];
+    if bytes.len() < width * height * channels * 4 {
+        return Err("not enough pixel data".to_string());
+    }
+    let value = |i: usize| {
+        let b = [bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]];
+        if scale < 0. {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    };
+    // Rows are stored bottom first
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            pixels.push(if channels == 3 {
+                Color::from(value(i), value(i + 1), value(i + 2))
+            } else {
+                Color::from(value(i), value(i), value(i))
+            });
+        }
+    }
+    Ok(Image::from(width, height, pixels))
+}
+
+fn decode_with_stb(path: &str) -> Result<Image, String> {
+    let (width, height, depth, values): (usize, usize, usize, Vec<f32>) = match load(path) {
+        LoadResult::ImageU8(img) => (
+            img.width,
+            img.height,
+            img.depth,
+            img.data.iter().map(|&b| decode((b as f32 + 0.5) / 256.)).collect(),
+        ),
+        LoadResult::ImageF32(img) => (img.width, img.height, img.depth, img.data),
+        LoadResult::Error(reason) => return Err(reason),
+    };
+    if depth == 0 || values.len() < width * height * depth {
+        return Err("not enough pixel data".to_string());
+    }
+    let pixels = values
+        .chunks_exact(depth)
+        .take(width * height)
+        .map(|c| match depth {
+            1 | 2 => Color::from(c[0], c[0], c[0]),
+            _ => Color::from(c[0], c[1], c[2]),
+        })
+        .collect();
+    Ok(Image::from(width, height, pixels))
+}
+
+/// Errors of a test image against a reference.
+#[derive(Clone, Debug)]
+pub struct ImageComparison {
+    pub mse: f32,
+    pub rmse: f32,
+    /// Squared error over the squared reference value, per channel
+    pub rel_mse: f32,
+    /// Peak signal to noise ratio of the displayed values, in dB
+    pub psnr: f32,
+    /// Mean structural similarity of the displayed luminance, 1 when equal
+    pub ssim: f32,
+    /// Mean FLIP-style perceptual difference, 0 when equal, up to 1
+    pub flip: f32,
+    width: usize,
+    height: usize,
+    flip_map: Vec<f32>,
+}
+
+impl fmt::Display for ImageComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MSE:     {:.6e}", self.mse)?;
+        writeln!(f, "RMSE:    {:.6e}", self.rmse)?;
+        writeln!(f, "relMSE:  {:.6e}", self.rel_mse)?;
+        writeln!(f, "PSNR:    {:.2} dB", self.psnr)?;
+        writeln!(f, "SSIM:    {:.5}", self.ssim)?;
+        write!(f, "FLIP:    {:.5}", self.flip)
+    }
+}
+
+pub fn compare_images(test: &Image, reference: &Image) -> Result<ImageComparison, ImageError> {
+    if test.dimensions() != reference.dimensions() {
+        return Err(ImageError::SizeMismatch {
+            test: test.dimensions(),
+            reference: reference.dimensions(),
+        });
+    }
+    let (width, height) = reference.dimensions();
+    let n = (width * height).max(1) as f32;
+
+    let (mut sq, mut rel, mut display_sq) = (0., 0., 0.);
+    for (t, r) in test.pixels.iter().zip(&reference.pixels) {
+        for i in 0..3 {
+            let d = t[i] - r[i];
+            sq += d * d;
+            rel += d * d / (r[i] * r[i] + 1e-2);
+            let dd = encode(t[i]) - encode(r[i]);
+            display_sq += dd * dd;
+        }
+    }
+    let mse = sq / (3. * n);
+    let display_mse = display_sq / (3. * n);
+    let psnr = if display_mse > 0. {
+        -10. * display_mse.log10()
+    } else {
+        f32::INFINITY
+    };
+
+    let flip_map = flip_error(test, reference);
+    let flip = flip_map.iter().sum::<f32>() / n;
+
+    Ok(ImageComparison {
+        mse,
+        rmse: mse.sqrt(),
+        rel_mse: rel / (3. * n),
+        psnr,
+        ssim: ssim(test, reference),
+        flip,
+        width,
+        height,
+        flip_map,
+    })
+}
+
+impl ImageComparison {
+    /// FLIP-style error of each pixel, top row first.
+    pub fn flip_map(&self) -> &[f32] {
+        &self.flip_map
+    }
+    /// Writes the FLIP-style error as a PPM image, black through purple and
+    /// orange to pale yellow for the largest differences.
+    pub fn write_false_color<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        writeln!(w, "P3\n{} {}\n255", self.width, self.height)?;
+        for &e in &self.flip_map {
+            let (r, g, b) = false_color(e);
+            writeln!(w, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
+    }
+    pub fn save_false_color<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        let mut w = BufWriter::new(fs::File::create(path)?);
+        self.write_false_color(&mut w)?;
+        w.flush()
+    }
+}
+
+const MAGMA: [(u8, u8, u8); 9] = [
+    (0, 0, 4),
+    (28, 16, 68),
+    (79, 18, 123),
+    (129, 37, 129),
+    (181, 54, 122),
+    (229, 80, 100),
+    (251, 135, 97),
+    (254, 194, 135),
+    (252, 253, 191),
+];
+
+fn false_color(e: f32) -> (u8, u8, u8) {
+    let t = e.clamp(0., 1.) * (MAGMA.len() - 1) as f32;
+    let i = (t as usize).min(MAGMA.len() - 2);
+    let f = t - i as f32;
+    let (a, b) = (MAGMA[i], MAGMA[i + 1]);
+    let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * f).round() as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+// Normalized 1D Gaussian of the given standard deviation
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (3. * sigma).ceil().max(1.) as isize;
+    let k: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = k.iter().sum();
+    k.into_iter().map(|v| v / sum).collect()
+}
+
+// Separable convolution with clamped edges
+fn convolve(data: &[f32], width: usize, height: usize, kx: &[f32], ky: &[f32]) -> Vec<f32> {
+    let pass = |src: &[f32], k: &[f32], horizontal: bool| -> Vec<f32> {
+        let r = (k.len() / 2) as isize;
+        (0..width * height)
+            .map(|idx| {
+                let (x, y) = ((idx % width) as isize, (idx / width) as isize);
+                k.iter()
+                    .enumerate()
+                    .map(|(i, w)| {
+                        let o = i as isize - r;
+                        let (sx, sy) = if horizontal {
+                            ((x + o).clamp(0, width as isize - 1), y)
+                        } else {
+                            (x, (y + o).clamp(0, height as isize - 1))
+                        };
+                        w * src[sy as usize * width + sx as usize]
+                    })
+                    .sum()
+            })
+            .collect()
+    };
+    pass(&pass(data, kx, true), ky, false)
+}
+
+fn ssim(test: &Image, reference: &Image) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+    let (w, h) = reference.dimensions();
+    let lum = |img: &Image| -> Vec<f32> {
+        img.pixels
+            .iter()
+            .map(|c| luminance(Color::from(encode(c.x()), encode(c.y()), encode(c.z()))))
+            .collect()
+    };
+    let (x, y) = (lum(test), lum(reference));
+    let k = gaussian_kernel(1.5);
+    let blur = |v: Vec<f32>| convolve(&v, w, h, &k, &k);
+    let mu_x = blur(x.clone());
+    let mu_y = blur(y.clone());
+    let xx = blur(x.iter().map(|v| v * v).collect());
+    let yy = blur(y.iter().map(|v| v * v).collect());
+    let xy = blur(x.iter().zip(&y).map(|(a, b)| a * b).collect());
+
+    let total: f32 = (0..w * h)
+        .map(|i| {
+            let (mx, my) = (mu_x[i], mu_y[i]);
+            let var_x = xx[i] - mx * mx;
+            let var_y = yy[i] - my * my;
+            let cov = xy[i] - mx * my;
+            ((2. * mx * my + C1) * (2. * cov + C2))
+                / ((mx * mx + my * my + C1) * (var_x + var_y + C2))
+        })
+        .sum();
+    total / (w * h).max(1) as f32
+}
+
+// sRGB primaries, D65 white
+fn linear_to_xyz(c: Color) -> Color {
+    Color::from(
+        0.4124 * c.x() + 0.3576 * c.y() + 0.1805 * c.z(),
+        0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z(),
+        0.0193 * c.x() + 0.1192 * c.y() + 0.9505 * c.z(),
+    )
+}
+fn xyz_to_linear(c: Color) -> Color {
+    Color::from(
+        3.2406 * c.x() - 1.5372 * c.y() - 0.4986 * c.z(),
+        -0.9689 * c.x() + 1.8758 * c.y() + 0.0415 * c.z(),
+        0.0557 * c.x() - 0.2040 * c.y() + 1.0570 * c.z(),
+    )
+}
+const WHITE: (f32, f32, f32) = (0.9505, 1., 1.089);
+
+// Linearized CIELab, which can be blurred channel by channel
+fn xyz_to_ycxcz(c: Color) -> Color {
+    let (x, y, z) = (c.x() / WHITE.0, c.y() / WHITE.1, c.z() / WHITE.2);
+    Color::from(116. * y - 16., 500. * (x - y), 200. * (y - z))
+}
+fn ycxcz_to_xyz(c: Color) -> Color {
+    let y = (c.x() + 16.) / 116.;
+    let x = y + c.y() / 500.;
+    let z = y - c.z() / 200.;
+    Color::from(x * WHITE.0, y * WHITE.1, z * WHITE.2)
+}
+fn xyz_to_lab(c: Color) -> Color {
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16. / 116.
+        }
+    };
+    let (fx, fy, fz) = (f(c.x() / WHITE.0), f(c.y() / WHITE.1), f(c.z() / WHITE.2));
+    Color::from(116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz))
+}
+fn hyab(a: Color, b: Color) -> f32 {
+    let d = a - b;
+    d.x().abs() + (d.y() * d.y() + d.z() * d.z()).sqrt()
+}
+
+// Contrast sensitivity blurs at about 67 pixels per degree, for the
+// achromatic, red-green and blue-yellow channels
+const CSF_SIGMAS: [f32; 3] = [1.0, 1.1, 2.8];
+// Feature detector scale, in pixels
+const FEATURE_SIGMA: f32 = 2.75;
+
+fn flip_error(test: &Image, reference: &Image) -> Vec<f32> {
+    let (w, h) = reference.dimensions();
+    let displayed = |c: Color| {
+        Color::from(
+            decode(encode(c.x())),
+            decode(encode(c.y())),
+            decode(encode(c.z())),
+        )
+    };
+
+    // Color term: blur in YCxCz, then HyAB distance in CIELab
+    let filtered = |img: &Image| -> Vec<Color> {
+        let opponent: Vec<Color> = img
+            .pixels
+            .iter()
+            .map(|&c| xyz_to_ycxcz(linear_to_xyz(displayed(c))))
+            .collect();
+        let mut channels = vec![];
+        for (i, sigma) in CSF_SIGMAS.iter().enumerate() {
+            let k = gaussian_kernel(*sigma);
+            let data: Vec<f32> = opponent.iter().map(|c| c[i]).collect();
+            channels.push(convolve(&data, w, h, &k, &k));
+        }
+        (0..w * h)
+            .map(|p| {
+                let c = Color::from(channels[0][p], channels[1][p], channels[2][p]);
+                let rgb = xyz_to_linear(ycxcz_to_xyz(c));
+                let rgb = Color::from(
+                    rgb.x().clamp(0., 1.),
+                    rgb.y().clamp(0., 1.),
+                    rgb.z().clamp(0., 1.),
+                );
+                xyz_to_lab(linear_to_xyz(rgb))
+            })
+            .collect()
+    };
+    let (lab_t, lab_r) = (filtered(test), filtered(reference));
+    let green = xyz_to_lab(linear_to_xyz(Color::from(0., 1., 0.)));
+    let blue = xyz_to_lab(linear_to_xyz(Color::from(0., 0., 1.)));
+    let c_max = hyab(green, blue).powf(0.7);
+    let (pc, pt) = (0.4, 0.95);
+    let color_error = |p: usize| {
+        let e = hyab(lab_t[p], lab_r[p]).powf(0.7);
+        if e < pc * c_max {
+            pt / (pc * c_max) * e
+        } else {
+            pt + (e - pc * c_max) / (c_max - pc * c_max) * (1. - pt)
+        }
+    };
+
+    // Feature term: edges and points of the achromatic channel
+    let sigma = FEATURE_SIGMA;
+    let g = gaussian_kernel(sigma);
+    let r = (g.len() / 2) as isize;
+    let normalize_signed = |k: Vec<f32>| -> Vec<f32> {
+        let pos: f32 = k.iter().filter(|v| **v > 0.).sum();
+        let neg: f32 = -k.iter().filter(|v| **v < 0.).sum::<f32>();
+        k.into_iter()
+            .map(|v| if v > 0. { v / pos } else { v / neg.max(1e-8) })
+            .collect()
+    };
+    let first = normalize_signed(
+        (-r..=r)
+            .zip(&g)
+            .map(|(x, gv)| -(x as f32) * gv)
+            .collect(),
+    );
+    let second = normalize_signed(
+        (-r..=r)
+            .zip(&g)
+            .map(|(x, gv)| ((x * x) as f32 / (sigma * sigma) - 1.) * gv)
+            .collect(),
+    );
+    let features = |img: &Image| -> (Vec<f32>, Vec<f32>) {
+        let y: Vec<f32> = img
+            .pixels
+            .iter()
+            .map(|&c| linear_to_xyz(displayed(c)).y())
+            .collect();
+        let (ex, ey) = (convolve(&y, w, h, &first, &g), convolve(&y, w, h, &g, &first));
+        let (pxx, pyy) = (convolve(&y, w, h, &second, &g), convolve(&y, w, h, &g, &second));
+        let edges = (0..w * h).map(|p| ex[p].hypot(ey[p])).collect();
+        let points = (0..w * h).map(|p| pxx[p].hypot(pyy[p])).collect();
+        (edges, points)
+    };
+    let ((edges_t, points_t), (edges_r, points_r)) = (features(test), features(reference));
+
+    (0..w * h)
+        .map(|p| {
+            let feature = ((edges_r[p] - edges_t[p]).abs())
+                .max((points_r[p] - points_t[p]).abs())
+                / std::f32::consts::SQRT_2;
+            let feature = feature.clamp(0., 1.).sqrt();
+            color_error(p).clamp(0., 1.).powf(1. - feature)
+        })
+        .collect()
+}
@@ -0,0 +1,165 @@
+/*
+// Description: This file provides the denoiser run on a finished render
+//              before exposure and tone mapping. It is an edge-avoiding
+//              a-trous wavelet filter: a 5x5 B3-spline kernel applied a few
+//              times with its taps spread twice as far each time, where every
+//              tap is weighted down by how much its luminance differs from
+//              the center relative to the estimated noise, and, when the AOV
+//              passes are there, by how far apart the normals and depths are.
+//              Colors are divided by the albedo before filtering and
+//              multiplied back after, so texture detail is kept and only the
+//              lighting is smoothed. The pixel variance, never taken below
+//              the spread of the surrounding pixels on the same surface so
+//              pixels whose few samples all agreed still get smoothed, is
+//              filtered along with the colors so later passes know how much
+//              noise is left.
+*/
+
+use crate::canvas::luminance;
+use crate::vec3::*;
+use rayon::prelude::*;
+
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseSettings {
+    /// How many standard deviations of noise a luminance difference may span
+    /// and still be blurred over, 0 turns the denoiser off
+    pub strength: f32,
+    /// Filter passes, each doubling the reach
+    pub iterations: usize,
+    /// Exponent on the cosine between normals, higher keeps creases sharper
+    pub normal_power: f32,
+    /// Depth difference tolerated, relative to the depth and tap distance
+    pub depth_sigma: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            strength: 4.,
+            iterations: 5,
+            normal_power: 128.,
+            depth_sigma: 0.02,
+        }
+    }
+}
+
+/// Per-pixel guide images, taken from the AOV passes.
+pub struct DenoiseGuides {
+    pub albedo: Vec<Color>,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<f32>,
+}
+
+/// Denoises a `width` by `height` image, `variance` being the variance of
+/// each pixel's luminance estimate.
+pub fn denoise(
+    width: usize,
+    height: usize,
+    color: &[Color],
+    variance: &[f32],
+    guides: Option<&DenoiseGuides>,
+    settings: &DenoiseSettings,
+) -> Vec<Color> {
+    if settings.strength <= 0. || width == 0 || height == 0 {
+        return color.to_vec();
+    }
+
+    // Untextured lighting, with the variance scaled to match
+    let albedo_of = |i: usize| match guides {
+        Some(g) => {
+            let a = g.albedo[i];
+            Color::from(a.x().max(0.01), a.y().max(0.01), a.z().max(0.01))
+        }
+        None => Color::from(1., 1., 1.),
+    };
+    let mut illum: Vec<Color> = (0..color.len())
+        .map(|i| {
+            let a = albedo_of(i);
+            color[i] * Color::from(1. / a.x(), 1. / a.y(), 1. / a.z())
+        })
+        .collect();
+    // Edge stopping on the guides alone
+    let guide_weight = |p: usize, q: usize, step: isize| match guides {
+        Some(g) => {
+            let (zp, zq) = (g.depth[p], g.depth[q]);
+            let tolerance = settings.depth_sigma * step as f32 * zp.max(zq) + 1e-4;
+            normal_weight(g.normal[p], g.normal[q], settings.normal_power)
+                * (-(zp - zq).abs() / tolerance).exp()
+        }
+        None => 1.,
+    };
+    let taps = |p: usize, step: isize| {
+        let (px, py) = ((p % width) as isize, (p / width) as isize);
+        (0..25).filter_map(move |k| {
+            let (i, j) = (k % 5, k / 5);
+            let qx = px + (i as isize - 2) * step;
+            let qy = py + (j as isize - 2) * step;
+            if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                return None;
+            }
+            Some((qy as usize * width + qx as usize, KERNEL[i] * KERNEL[j]))
+        })
+    };
+
+    let mut var: Vec<f32> = (0..color.len())
+        .into_par_iter()
+        .map(|p| {
+            let (mut sum, mut sum_sq, mut sum_w) = (0., 0., 0.);
+            for (q, k) in taps(p, 1) {
+                let w = k * guide_weight(p, q, 1);
+                let lum = luminance(illum[q]);
+                sum += w * lum;
+                sum_sq += w * lum * lum;
+                sum_w += w;
+            }
+            let mean = sum / sum_w;
+            let spatial = (sum_sq / sum_w - mean * mean).max(0.);
+            let a = luminance(albedo_of(p));
+            (variance[p] / (a * a)).max(spatial)
+        })
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1isize << iteration;
+        let (next_illum, next_var): (Vec<Color>, Vec<f32>) = (0..color.len())
+            .into_par_iter()
+            .map(|p| {
+                let lum_p = luminance(illum[p]);
+                let sigma_l = settings.strength * var[p].max(0.).sqrt() + 1e-4;
+
+                let (mut sum, mut sum_var, mut sum_w) = (Color::new(), 0., 0.);
+                for (q, k) in taps(p, step) {
+                    let w = k
+                        * (-(lum_p - luminance(illum[q])).abs() / sigma_l).exp()
+                        * guide_weight(p, q, step);
+                    if !w.is_finite() || w <= 0. {
+                        continue;
+                    }
+                    sum.add(illum[q] * w);
+                    sum_var += w * w * var[q];
+                    sum_w += w;
+                }
+                if sum_w <= 0. {
+                    return (illum[p], var[p]);
+                }
+                (sum / sum_w, sum_var / (sum_w * sum_w))
+            })
+            .unzip();
+        illum = next_illum;
+        var = next_var;
+    }
+
+    (0..color.len()).map(|i| illum[i] * albedo_of(i)).collect()
+}
+
+// Pixels where nothing was hit have a zero normal and only mix among
+// themselves
+fn normal_weight(np: Vec3, nq: Vec3, power: f32) -> f32 {
+    match (np.near_zero(), nq.near_zero()) {
+        (true, true) => 1.,
+        (false, false) => dot(np, nq).max(0.).powf(power),
+        _ => 0.,
+    }
+}
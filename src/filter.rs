@@ -0,0 +1,97 @@
+/*
+// Description: This file provides the pixel reconstruction filters used when
+//              samples are splatted onto the canvas. Every sample adds its
+//              color, weighted by the filter, to each pixel whose center lies
+//              within the filter radius, and a pixel is the weighted sum over
+//              the sum of the weights. The box filter with a half pixel radius
+//              is the plain per-pixel average. The tent, Gaussian,
+//              Mitchell-Netravali and Lanczos filters trade sharpness against
+//              aliasing, the last two with negative lobes that sharpen edges.
+//              All filters are separable, the product of a 1D filter in x and y.
+*/
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    /// exp(-alpha x^2), shifted down so it reaches zero at the radius
+    Gaussian { alpha: f32 },
+    /// Cubic with the B and C parameters, 1/3 and 1/3 being the recommended ones
+    Mitchell { b: f32, c: f32 },
+    /// Sinc windowed by a sinc stretched to the radius
+    Lanczos,
+}
+
+/// `radius` is in pixels, measured from the pixel center along each axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelFilter {
+    pub kind: FilterKind,
+    pub radius: f32,
+}
+
+impl PixelFilter {
+    pub fn from(kind: FilterKind, radius: f32) -> Self {
+        Self { kind, radius }
+    }
+    pub fn box_filter() -> Self {
+        Self::from(FilterKind::Box, 0.5)
+    }
+    pub fn gaussian(radius: f32) -> Self {
+        Self::from(FilterKind::Gaussian { alpha: 2. }, radius)
+    }
+    pub fn mitchell(radius: f32) -> Self {
+        Self::from(FilterKind::Mitchell { b: 1. / 3., c: 1. / 3. }, radius)
+    }
+
+    /// Weight of a sample `dx`, `dy` pixels away from the pixel center.
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let r = self.radius;
+        if x > r || r <= 0. {
+            return 0.;
+        }
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => 1. - x / r,
+            FilterKind::Gaussian { alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * r * r).exp()).max(0.)
+            }
+            FilterKind::Mitchell { b, c } => mitchell_1d(2. * x / r, b, c),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        Self::box_filter()
+    }
+}
+
+// Mitchell-Netravali cubic over [0, 2]
+fn mitchell_1d(x: f32, b: f32, c: f32) -> f32 {
+    let x2 = x * x;
+    let x3 = x2 * x;
+    let w = if x < 1. {
+        (12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)
+    } else if x < 2. {
+        (-b - 6. * c) * x3 + (6. * b + 30. * c) * x2 + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+    } else {
+        0.
+    };
+    w / 6.
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.;
+    }
+    let px = PI * x;
+    px.sin() / px
+}
@@ -1,16 +1,20 @@
 /*
 // Description: This file defines ray-object interactions and transformations.
 //              The HitRecord struct records details of ray-object interactions,
-//              including point, normal, material, and texture coords. The 
+//              including point, normal, material, texture coords and the
+//              surface tangents dpdu/dpdv used for normal mapping. The 
 //              hittable trait is an interface for objects that can be
 //              intersected by rays. Translate will shift over an object by
 //              a specified offset. RotateY will rotate an object around the
-//              Y-axis.
+//              Y-axis. FlipFace swaps which side of an object counts as its
+//              front, so one-sided lights can face the other way. AlphaMask
+//              cuts holes in any object that fills in texture coords,
+//              letting rays pass through where its opacity texture is below
+//              a threshold.
 */
 
-use crate::{aabb::*, material::*, ray::*, utils::*, vec3::*};
+use crate::{aabb::*, material::*, ray::*, texture::*, utils::*, vec3::*};
 use lazy_static::*;
-use std::f32::INFINITY as inf;
 use std::sync::Arc;
 
 #[derive(Clone, Copy)]
@@ -21,7 +25,15 @@ pub struct HitRecord<'a> {
     pub t: f32,
     pub u: f32,
     pub v: f32,
+    /// Partial derivatives of the hit point along the texture coords
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
+    /// Width in u,v units a texture lookup at the hit covers, 0 for a
+    /// single point
+    pub footprint: f32,
+    /// Index of the object in the outermost hittablelist that was hit
+    pub object_id: usize,
 }
 
 lazy_static! {
@@ -37,9 +49,26 @@ impl HitRecord<'_> {
             t: 0.,
             u: 0.,
             v: 0.,
+            dpdu: Vec3::new(),
+            dpdv: Vec3::new(),
+            object_id: 0,
             front_face: true,
+            footprint: 0.,
         }
     }
+    /// Sets the texture footprint from the width of `r`'s cone at the hit,
+    /// stretched where the ray grazes the surface, over the u,v rate of the
+    /// surface.
+    pub fn set_footprint(&mut self, r: &Ray) {
+        let width = r.width_at(self.t);
+        let rate = self.dpdu.len().min(self.dpdv.len());
+        if width <= 0. || rate <= 0. {
+            self.footprint = 0.;
+            return;
+        }
+        let cos_theta = dot(unit_vector(r.direction()), self.normal).abs().max(0.05);
+        self.footprint = width / cos_theta / rate;
+    }
     pub fn set_face_normal(&mut self, r: Ray, outward_normal: Vec3) {
         self.front_face = dot(r.direction(), outward_normal) < 0.;
         self.normal = if self.front_face {
@@ -54,7 +83,7 @@ pub trait Hittable: Send + Sync {
     fn hit<'a>(&'a self, _r: &Ray, _t_min: f32, _t_max: f32, _rec: &mut HitRecord<'a>) -> bool {
         true
     }
-    fn bounding_box<'a>(&'a self, _time0: f32, _time1: f32, _output_box: &mut Bb) -> bool {
+    fn bounding_box(&self, _time0: f32, _time1: f32, _output_box: &mut Bb) -> bool {
         true
     }
 }
@@ -79,8 +108,8 @@ impl Hittable for Translate {
             return false;
         }
 
+        // The normal already faces the ray, which moving doesn't change
         rec.p = rec.p + self.offset;
-        rec.set_face_normal(moved_r, rec.normal);
         true
     }
 
@@ -114,15 +143,15 @@ impl RotateY {
         let mut bbox = Bb::new();
         let has_box = p.bounding_box(0., 1., &mut bbox);
 
-        let mut min = Point3::from(inf, inf, inf);
-        let mut max = Point3::from(-inf, -inf, -inf);
+        let mut min = Point3::from(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Point3::from(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
                 for k in 0..2 {
                     let x = i as f32 * bbox.max().x() + (1. - i as f32) * bbox.min().x();
-                    let y = i as f32 * bbox.max().y() + (1. - j as f32) * bbox.min().y();
-                    let z = i as f32 * bbox.max().z() + (1. - k as f32) * bbox.min().z();
+                    let y = j as f32 * bbox.max().y() + (1. - j as f32) * bbox.min().y();
+                    let z = k as f32 * bbox.max().z() + (1. - k as f32) * bbox.min().z();
 
                     let newx = cos_theta * x + sin_theta * z;
                     let newz = -sin_theta * x + cos_theta * z;
@@ -176,10 +205,102 @@ impl Hittable for RotateY {
         normal[0] = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
         normal[2] = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
 
+        for tangent in [&mut rec.dpdu, &mut rec.dpdv] {
+            let (x, z) = (tangent[0], tangent[2]);
+            tangent[0] = self.cos_theta * x + self.sin_theta * z;
+            tangent[2] = -self.sin_theta * x + self.cos_theta * z;
+        }
+
+        // Rotating the normal with the ray keeps it facing the ray
         rec.p = p;
+        rec.normal = normal;
+
+        true
+    }
+}
 
-        rec.set_face_normal(rotated_r, normal);
+pub struct FlipFace {
+    ptr: Arc<dyn Hittable>,
+}
+impl FlipFace {
+    pub fn from(p: Arc<dyn Hittable>) -> Self {
+        Self { ptr: p }
+    }
+}
 
+impl Hittable for FlipFace {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+        if !self.ptr.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        // The normal still faces the ray, only the side it came from changes
+        rec.front_face = !rec.front_face;
         true
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Bb) -> bool {
+        self.ptr.bounding_box(time0, time1, output_box)
+    }
+}
+
+pub enum OpacitySource {
+    /// Opacity is the average of the texture's color channels
+    Texture(Arc<dyn Texture>),
+    /// Opacity is the image's alpha channel
+    Alpha(Arc<ImageTexture>),
+}
+
+impl OpacitySource {
+    pub fn opacity(&self, u: f32, v: f32, p: &Point3, footprint: f32) -> f32 {
+        match self {
+            OpacitySource::Texture(tex) => {
+                let c = tex.filtered_value(u, v, p, footprint);
+                (c.x() + c.y() + c.z()) / 3.
+            }
+            OpacitySource::Alpha(image) => image.alpha(u, v, footprint),
+        }
+    }
+}
+
+pub struct AlphaMask {
+    ptr: Arc<dyn Hittable>,
+    mask: OpacitySource,
+    threshold: f32,
+}
+
+impl AlphaMask {
+    pub fn from(p: Arc<dyn Hittable>, mask: OpacitySource, threshold: f32) -> Self {
+        Self {
+            ptr: p,
+            mask,
+            threshold,
+        }
+    }
+    pub fn from_texture(p: Arc<dyn Hittable>, mask: Arc<dyn Texture>) -> Self {
+        Self::from(p, OpacitySource::Texture(mask), 0.5)
+    }
+    pub fn from_alpha(p: Arc<dyn Hittable>, image: Arc<ImageTexture>) -> Self {
+        Self::from(p, OpacitySource::Alpha(image), 0.5)
+    }
+}
+
+impl Hittable for AlphaMask {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+        // Keep searching past transparent hits until an opaque one is found
+        let mut t_start = t_min;
+        loop {
+            if !self.ptr.hit(r, t_start, t_max, rec) {
+                return false;
+            }
+            rec.set_footprint(r);
+            if self.mask.opacity(rec.u, rec.v, &rec.p, rec.footprint) >= self.threshold {
+                return true;
+            }
+            t_start = rec.t + 0.0001 * rec.t.abs().max(1.);
+        }
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Bb) -> bool {
+        self.ptr.bounding_box(time0, time1, output_box)
+    }
+}
@@ -6,12 +6,13 @@
 //              bounding box that encompasses all objects in the list.
 //              The hit method determines if a ray hits any objects in
 //              the list and updates the hit record with details of the 
-//              closest hit.
+//              closest hit, tagging it with the index of the object hit.
+//              Every object is tried, which the render statistics count.
 */
 
 use std::sync::Arc;
 
-use crate::{aabb::*, hittable::*, ray::*};
+use crate::{aabb::*, hittable::*, ray::*, stats::*};
 
 pub struct HittableList {
     pub objects: Vec<Arc<dyn Hittable>>,
@@ -25,13 +26,18 @@ impl HittableList {
         self.objects.push(object);
     }
     pub fn clear(&mut self){
-        while self.objects.len() > 0 {
+        while !self.objects.is_empty() {
             self.objects.pop();
         }
     }
 }
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Hittable for HittableList {
-    fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Bb) -> bool {
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Bb) -> bool {
         if self.objects.is_empty() {
             return false;
         }
@@ -45,7 +51,7 @@ impl Hittable for HittableList {
             *output_box = if first_box {
                 temp_box
             } else {
-                surrounding_box(&output_box, &temp_box)
+                surrounding_box(output_box, &temp_box)
             };
             first_box = false;
         }
@@ -55,12 +61,14 @@ impl Hittable for HittableList {
         let mut temp_rec = HitRecord::void();
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
+        count_object_visits(self.objects.len() as u64);
 
-        for object in &self.objects {
+        for (i, object) in self.objects.iter().enumerate() {
             if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec;
+                rec.object_id = i;
             }
         }
         hit_anything
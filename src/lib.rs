@@ -0,0 +1,57 @@
+/*
+// Description: This file is the root of the ray tracing library. It declares
+//              the modules of the renderer and re-exports their contents, so
+//              the ray_trace binary, the tests and other tools all build
+//              scenes and render them through the same code.
+*/
+
+mod hittable_list;
+mod aabb;
+mod hittable;
+mod ray;
+mod vec3;
+mod utils;
+mod material;
+mod texture;
+mod perlin;
+mod camera;
+mod canvas;
+mod colors;
+mod sphere;
+mod rect;
+mod light;
+mod stereo;
+mod filter;
+mod sampler;
+mod aov;
+mod denoise;
+mod compare;
+mod scenes;
+mod stats;
+mod animation;
+
+
+pub use hittable_list::*;
+pub use aabb::*;
+pub use hittable::*;
+pub use ray::*;
+pub use vec3::*;
+pub use utils::*;
+pub use material::*;
+pub use texture::*;
+pub use perlin::*;
+pub use camera::*;
+pub use canvas::*;
+pub use colors::*;
+pub use sphere::*;
+pub use rect::*;
+pub use light::*;
+pub use stereo::*;
+pub use filter::*;
+pub use sampler::*;
+pub use aov::*;
+pub use denoise::*;
+pub use compare::*;
+pub use scenes::*;
+pub use stats::*;
+pub use animation::*;
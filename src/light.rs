@@ -0,0 +1,144 @@
+/*
+// Description: This file defines delta light sources, lights with no surface
+//              that rays can hit, so they are only reached by shadow rays. The
+//              light trait returns the direction, distance and radiance
+//              arriving at a shaded point. Point lights emit equally in every
+//              direction with inverse square falloff. Spot lights are point
+//              lights restricted to a cone with a smooth falloff between the
+//              inner and outer angles. Directional lights model a distant
+//              source like the sun, arriving from the same direction
+//              everywhere without falloff. The lightlist struct holds the
+//              lights of a scene next to its hittablelist.
+*/
+
+use crate::{material::*, vec3::*};
+use std::sync::Arc;
+
+pub struct LightSample {
+    /// Unit direction from the shaded point towards the light
+    pub wi: Vec3,
+    /// Distance to the light, infinite for directional lights
+    pub dist: f32,
+    /// Radiance arriving at the shaded point, before any occlusion
+    pub radiance: Color,
+}
+
+pub trait Light: Send + Sync {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample>;
+}
+
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+impl PointLight {
+    pub fn from(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let dist_squared = to_light.len_squared();
+        if dist_squared <= 0. {
+            return None;
+        }
+        let dist = dist_squared.sqrt();
+        Some(LightSample {
+            wi: to_light / dist,
+            dist,
+            radiance: self.intensity / dist_squared,
+        })
+    }
+}
+
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    profile: EmissionProfile,
+}
+impl SpotLight {
+    /// `inner` and `outer` are the cone half angles in degrees.
+    pub fn from(position: Point3, lookat: Point3, intensity: Color, inner: f32, outer: f32) -> Self {
+        Self {
+            position,
+            direction: unit_vector(lookat - position),
+            intensity,
+            profile: EmissionProfile::spot(inner, outer),
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let dist_squared = to_light.len_squared();
+        if dist_squared <= 0. {
+            return None;
+        }
+        let dist = dist_squared.sqrt();
+        let wi = to_light / dist;
+        let falloff = self.profile.weight(dot(wi.inv(), self.direction));
+        if falloff <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            dist,
+            radiance: self.intensity * (falloff / dist_squared),
+        })
+    }
+}
+
+pub struct DirectionalLight {
+    direction: Vec3,
+    radiance: Color,
+}
+impl DirectionalLight {
+    /// `direction` is the way the light travels, e.g. down for a noon sun.
+    pub fn from(direction: Vec3, radiance: Color) -> Self {
+        Self {
+            direction: unit_vector(direction),
+            radiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: self.direction.inv(),
+            dist: f32::INFINITY,
+            radiance: self.radiance,
+        })
+    }
+}
+
+pub struct LightList {
+    pub lights: Vec<Arc<dyn Light>>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self { lights: vec![] }
+    }
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+}
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,175 +1,134 @@
 use std::sync::Arc;
-use std::time::Instant;
-use rand::Rng;
-
-
-mod hittable_list;
-mod aabb;
-mod hittable;
-mod ray;
-mod vec3;
-mod utils;
-mod material;
-mod texture;
-mod camera;
-mod canvas;
-mod colors;
-mod sphere;
-mod rect;
-
-
-pub use hittable_list::*;
-pub use aabb::*;
-pub use hittable::*;
-pub use ray::*;
-pub use vec3::*;
-pub use utils::*;
-pub use material::*;
-pub use texture::*;
-pub use camera::*;
-pub use canvas::*;
-pub use colors::*;
-pub use sphere::*;
-pub use rect::*;
-
-
-fn scene_driver(select: i32) -> HittableList {
-    let mut objects = HittableList::new();
-    
-    //create matte colors and light source
-    let light = Arc::new(DiffuseLight::from_color(Color::from(2.5, 2.5, 2.5)));
-    //let light = Arc::new(DiffuseLight::from_color(Color::from(20., 20., 20.)));
-    let red = Arc::new(Lambertian::from(Color::from(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73)));
-    let green = Arc::new(Lambertian::from(Color::from(0.12, 0.45, 0.15)));
-    let yellow = Arc::new(Lambertian::from(Color::from(0.65, 0.65, 0.05)));
-    let blue = Arc::new(Lambertian::from(Color::from(0.05, 0.05, 0.65)));
-    let skyblue = Arc::new(Lambertian::from(Color::from(0.53, 0.80, 0.92)));
-    let brown = Arc::new(Lambertian::from(Color::from(0.47, 0.20, 0.08)));
-    let darkbrown = Arc::new(Lambertian::from(Color::from(0.345, 0.17, 0.08)));
-    
-    
-    if select == 0 {
-
-        objects.add(Arc::new(Sphere::new(
-            Point3::from(4., -0.5, 0.),
-            1.,
-            white.clone(),
-        )));
-        objects.add(Arc::new(Sphere::new(
-            Point3::from(0., 2., 0.),
-            2.,
-            green.clone(),
-        )));
-        objects.add(Arc::new(Sphere::new(
-            Point3::from(-3., 1., -1.5),
-            1.,
-            white.clone(),
-        )));
-        objects.add(Arc::new(Sphere::new(
-            Point3::from(3., -1., -1.2),
-            1.2,
-            yellow.clone(),
-        )));
-
-        let difflight = Arc::new(DiffuseLight::from_color(Color::from(7., 7., 7.)));
-        objects.add(Arc::new(Sphere::new(
-            Point3::from(-2., -1., -3.),
-            1.2,
-            difflight.clone()
-        )));
-            
-        objects.add(Arc::new(YzRect::from(-1.5, -0.5, 1., 4., -5., light.clone())));
-        objects.add(Arc::new(YzRect::from(0., 10., 0., 10., 0., red.clone())));
-        objects.add(Arc::new(XyRect::from(-10., 4., -2.5, 2.5, -3.75, blue.clone())));
+use std::time::{Duration, Instant};
 
-    }
-    else if select == 1 {
-        let brightlight = Arc::new(DiffuseLight::from_color(Color::from(20., 20., 20.)));
-        objects.add(Arc::new(YzRect::from(-5., 5., -5., 5., -16., brightlight.clone())));
-
-        objects.add(Arc::new(YzRect::from(0., 10., -10., 10., 0., skyblue.clone())));
-        objects.add(Arc::new(YzRect::from(-10., 0., -10., 10., -1., brown.clone())));
-        
-        objects.add(Arc::new(YzRect::from(-0.5, 1.5, -0.3, 0.3, -5., darkbrown.clone())));
-        objects.add(Arc::new(Sphere::new(Point3::from(-5., 1.8, 0.), 0.6, green.clone())));
-        objects.add(Arc::new(Sphere::new(Point3::from(1., 0., -4.), 2., yellow.clone())));
-    }
-    else if select == 2 {
-        objects.add(Arc::new(YzRect::from(-10., 10., -10., 10., -15., light.clone())));
-        
-        let mut randlist = Vec::new();
-        let mut rng = rand::thread_rng();
-        for _ in 0..100 {
-            randlist.push(Arc::new(Lambertian::from(Color::from(rng.gen_range(0.10..0.95), rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))));
-        }
-        
-        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.2, 0.4), (0.4, 0.6), (0.2, -0.2), -8., red.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.2, 0.4), (-0.6, 0.3), (-0.9, -0.2), -8., blue.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-1.2, -0.3), (-1.1, 0.3), (-0.6, 0.3), (-0.9, -0.2), -8., green.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.9, -0.2), (-1.2, -0.3), (-1.5, -0.9), -8., brown.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-1.5, -0.9), (-0.8, -1.25), (-0.6, -0.6), -8., skyblue.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.6, -0.6), (0., -0.4), (0.2, -0.2), -8., yellow.clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.6, -0.6), (-0.8, -1.25), (-0.1, -0.6), (0., -0.4), -8., randlist[0].clone())));
-        objects.add(Arc::new(Quadrilateral::from((0., -0.4), (-0.1, -0.6), (0.7, -1.0), (0.2, -0.2), -8., randlist[1].clone())));
-        objects.add(Arc::new(Quadrilateral::from((0.4, 0.6), (0.8, 0.7), (0.6, 0.), (0.2, -0.2), -8., randlist[2].clone())));
-        objects.add(Arc::new(Quadrilateral::from((0.2, -0.2), (0.6, 0.), (1.2, -0.3), (0.7, -1.0), -8., randlist[3].clone())));
-        objects.add(Arc::new(Quadrilateral::from((1.2, 0.3), (1.4, 0.8), (0.8, 0.7), (0.6, 0.),  -8., randlist[4].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.1, -0.6), (0.7, -1.0), (0.4, -1.25), (-0.8, -1.25),  -8., randlist[5].clone())));
-        objects.add(Arc::new(Quadrilateral::from((1.2, -0.3), (0.6, 0.), (1.2, 0.3), (1.6, 0.4),  -8., randlist[6].clone())));
-        objects.add(Arc::new(Quadrilateral::from((1.2, 0.3), (1.4, 0.8), (1.5, 1.25), (1.6, 0.4),  -8., randlist[7].clone()))); //top point
-
-        //Sorted after this point; order:(top left, top right, botom right, bottom left)
-        objects.add(Arc::new(Quadrilateral::from((1.5, 1.25), (1.8, 1.25), (2.0, 0.5), (1.6, 0.4),  -8., randlist[8].clone())));
-        objects.add(Arc::new(Quadrilateral::from((1.8, 1.25), (2.25, 1.25), (2.25, 0.4), (2.0, 0.5),  -8., randlist[9].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((1.6, 0.4), (2.0, 0.5), (2.25, 0.4), (2.25, 0.1),  -8., randlist[10].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((1.6, 0.4), (2.25, 0.1), (2.25, -0.6), (1.2, -0.3),  -8., randlist[11].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((1.2, -0.3), (2.25, -0.6), (1.8, -0.8), (0.7, -1.0),  -8., randlist[12].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((1.8, -0.8), (2.25, -0.6), (2.25, -1.25), (1.6, -1.1),  -8., randlist[13].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((0.7, -1.0), (1.8, -0.8), (1.6, -1.1), (0.4, -1.25),  -8., randlist[14].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((1.6, -1.1), (1.6, -1.1), (2.25, -1.25), (0.4, -1.25),  -8., randlist[15].clone()))); //end of the bottom closure
-
-        objects.add(Arc::new(Quadrilateral::from((1.3, 1.25), (1.5, 1.25), (1.4, 0.8), (0.8, 0.7),  -8., randlist[16].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((0.9, 1.1), (1.3, 1.25), (0.8, 0.7), (0.4, 0.6), -8., randlist[17].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((0.7, 1.25), (1.3, 1.25), (0.9, 1.1), (0.3, 0.8), -8., randlist[18].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((0.3, 0.8), (1.3, 1.25), (0.4, 0.6), (-0.2, 0.4), -8., randlist[19].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((0.1, 1.25), (0.7, 1.25), (0.3, 0.8),(-0.5, 0.9), -8., randlist[20].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((-0.5, 0.9), (0.3, 0.8), (-0.2, 0.4), (-0.6, 0.3), -8., randlist[21].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-0.4, 1.25), (0.1, 1.25), (-0.5, 0.9), (-0.8, 0.8), -8., randlist[22].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((-0.9, 1.25), (-0.4, 1.25), (-0.8, 0.8), (-1.1, 0.9), -8., randlist[23].clone()))); 
-        objects.add(Arc::new(Quadrilateral::from((-1.1, 0.9), (-0.8, 0.8), (-0.8, 0.8), (-1.1, 0.3), -8., randlist[99].clone()))); //triangle repair
-        objects.add(Arc::new(Quadrilateral::from((-0.8, 0.8), (-0.5, 0.9), (-0.6, 0.3), (-1.1, 0.3), -8., randlist[24].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-1.9, 0.9), (-1.1, 0.9), (-1.1, 0.3), (-1.7, 0.5), -8., randlist[25].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-1.5, 1.25), (-0.9, 1.25), (-1.1, 0.9), (-1.9, 0.9), -8., randlist[26].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-2.25, 1.25), (-1.5, 1.25), (-1.9, 0.9), (-2.25, 0.8), -8., randlist[27].clone()))); //top left
-        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.8), (-1.9, 0.9), (-1.7, 0.5), (-2.25, 0.1), -8., randlist[28].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.1), (-1.7, 0.5), (-1.1, 0.3), (-1.9, -0.1), -8., randlist[29].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-1.9, -0.1), (-1.1, 0.3), (-1.2, -0.3), (-1.8, -0.4), -8., randlist[30].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-1.8, -0.4), (-1.2, -0.3), (-1.5, -0.9), (-2.0, -0.8), -8., randlist[31].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-2.0, -0.8), (-1.5, -0.9), (-0.8, -1.25), (-1.7, -1.25), -8., randlist[32].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-2.25, -0.7), (-2.0, -0.8), (-1.7, -1.25), (-2.25, -1.25), -8., randlist[33].clone()))); //bottom left
-        objects.add(Arc::new(Quadrilateral::from((-2.25, -0.4), (-1.8, -0.4), (-2.0, -0.8), (-2.25, -0.7), -8., randlist[34].clone())));
-        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.1), (-1.9, -0.1), (-1.8, -0.4), (-2.25, -0.4), -8., randlist[35].clone())));
-        
 
-    }
+use ray_trace::*;
 
 
-        objects
+// Combines independent partial renders of the same frame into `out` and
+// writes the merged image to stdout
+fn merge_checkpoints(out: &str, inputs: &[String]) -> Result<(), std::io::Error> {
+    let mut merged = Canvas::load_checkpoint(&inputs[0])?;
+    for input in &inputs[1..] {
+        merged.merge(&Canvas::load_checkpoint(input)?)?;
+    }
+    merged.save_checkpoint(out)?;
+    merged.write_header();
+    merged.write_pixels();
+    Ok(())
 }
 
+// Prints how far `test` is from `reference`, optionally writing the
+// per-pixel error as a false-color image
+fn compare_files(test: &str, reference: &str, diff: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let comparison = compare_images(&Image::load(test)?, &Image::load(reference)?)?;
+    println!("{}", comparison);
+    if let Some(path) = diff {
+        comparison.save_false_color(path)?;
+    }
+    Ok(())
+}
 
+// A camera sample, with its AOVs only when they are captured
+enum CameraSample {
+    Plain(Color),
+    Aov(AovSample),
+}
+impl RenderSample for CameraSample {
+    fn color(&self) -> Color {
+        match self {
+            CameraSample::Plain(c) => *c,
+            CameraSample::Aov(s) => s.color(),
+        }
+    }
+    fn aov(&self) -> Option<&AovSample> {
+        match self {
+            CameraSample::Plain(_) => None,
+            CameraSample::Aov(s) => Some(s),
+        }
+    }
+}
+
+// Prints the render statistics, and writes them as JSON to `json` if given
+fn report_stats(stats: &RenderStats, json: Option<&str>) {
+    eprint!("\n{}", stats);
+    if let Some(path) = json {
+        if let Err(e) = stats.write_json(path) {
+            eprintln!("ERROR: could not write the render statistics: {}", e);
+        }
+    }
+}
 
 fn main() {
     let time = Instant::now(); // Time counter
+
+    // `ray_trace merge <out> <in>...` merges checkpoints,
+    // `ray_trace resume <checkpoint>` continues an interrupted render,
+    // `ray_trace compare <test> <reference> [diff.ppm]` reports image errors
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 4 && args[1] == "compare" {
+        if let Err(e) = compare_files(&args[2], &args[3], args.get(4)) {
+            eprintln!("ERROR: could not compare images: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.len() >= 4 && args[1] == "merge" {
+        if let Err(e) = merge_checkpoints(&args[2], &args[3..]) {
+            eprintln!("ERROR: could not merge checkpoints: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let resume = if args.len() >= 3 && args[1] == "resume" {
+        Some(args[2].clone())
+    } else {
+        None
+    };
+
     //Image info
     let image_width : usize = 400;
     let aspect_ratio : f32 = 16.0/9.0;
+    let image_height: usize = (image_width as f32 / aspect_ratio) as usize;
 
     let samples_per_pixel : usize = 5000;
     const MAX_DEPTH : usize = 50;
+    let adaptive = AdaptiveSettings {
+        min_spp: 64,
+        max_spp: samples_per_pixel,
+        pass_spp: 16,
+        threshold: 0.01,
+        time_budget: None,
+        checkpoint_path: Some(resume.clone().unwrap_or_else(|| "render.ckpt".to_string()).into()),
+        checkpoint_interval: Duration::from_secs(300),
+        // Some(CropWindow::from_normalized(0.25, 0.25, 0.75, 0.75, image_width, image_height))
+        // renders only the middle of the frame
+        crop: None,
+        filter: PixelFilter::mitchell(2.),
+        sampler: Arc::new(SobolSampler),
+        // Give each partial render to be merged its own seed
+        seed: 0,
+        aovs: false,
+        // Set from the scene and camera below
+        fingerprint: 0,
+    };
+    // With aovs on, writes render.exr, or one render_<pass>.pfm per pass
+    let aov_prefix = "render";
+    let aov_layered = true;
+    // e.g. Some(DenoiseSettings::default()), best with aovs on for the guides
+    let denoise: Option<DenoiseSettings> = None;
+    // Checkpoint of an earlier full render to paste a cropped render into
+    let composite_onto: Option<&str> = None;
 
-    let world = scene_driver(0);
+    // Render statistics are printed at the end, and written as JSON here
+    let stats_json: Option<&str> = None; // e.g. Some("stats.json")
+
+    // e.g. Some(1) renders the same image every run
+    let seed: Option<u64> = None;
+    seed_random(seed);
+
+    let scene = 0;
+    let (world, lights) = scene_driver(scene);
 
     //Camera
     let lookfrom = Point3::from(-15., 0., 0.);
@@ -179,11 +138,12 @@ fn main() {
     let aperture: f32 = 0.0;
     let vfov = 20.;
     let background = Color::new();
-
-    let image_height: usize = (image_width as f32 / aspect_ratio) as usize;
+    let exposure: Option<Exposure> = None; // e.g. Some(Exposure::from(100., 1. / 60., 2.8))
+    let focal_length: f32 = 0.05;
+    let auto_exposure = false;
 
 
-    let cam = Camera::new(
+    let mut cam = Camera::new(
         lookfrom,
         lookat,
         vup,
@@ -194,36 +154,206 @@ fn main() {
         0.,
         1.,
     );
+    cam.set_image_height(image_height);
+    if let Some(exposure) = exposure {
+        cam.set_exposure(exposure, focal_length);
+    }
 
-    let render_pixel = |i, j| -> Color {
-        let mut pixel_color = Color::new();
-        for _ in 0..samples_per_pixel {
-            let u = (i as f32 + random_double(0., 1.)) / ((image_width - 1) as f32); //horizontal random 
-            let v = (j as f32 + random_double(0., 1.)) / ((image_height - 1) as f32); //vertical random
-            let r = cam.get_ray(u, v);
-            pixel_color.add(ray_color(r, &background, &world, MAX_DEPTH as i32));
+    // e.g. Some(StereoCamera::new(lookfrom, lookat, vup, vfov, aspect_ratio,
+    // aperture, dist_to_focus, 0., 1., 0.065, Convergence::OffAxis,
+    // StereoLayout::SideBySide, image_height)) renders both eyes side by side
+    // into one frame, with crop windows in that frame. Animations stay mono
+    let mut stereo: Option<StereoCamera> = None;
+    if let Some(exposure) = exposure {
+        if let Some((left, right)) = stereo.as_mut().and_then(|rig| rig.eyes_mut()) {
+            left.set_exposure(exposure, focal_length);
+            right.set_exposure(exposure, focal_length);
         }
-        pixel_color
+    }
+    let (frame_width, frame_height) = match &stereo {
+        Some(rig) => rig.frame_size(image_width, image_height),
+        None => (image_width, image_height),
     };
 
-    // Render
-    let c = Canvas::from_fn(
-        image_width as usize,
-        image_height as usize,
-        samples_per_pixel as usize,
-        render_pixel,
+    // Everything that changes what a sample looks like or where it lands,
+    // so checkpoints of another scene, camera or film are refused. The crop
+    // window is left out, so a crop can be composited onto the full render;
+    // checkpoints keep the crop's origin and size, which are checked apart
+    let adaptive = AdaptiveSettings {
+        fingerprint: fingerprint(&format!(
+            "scene {} {}x{} depth {} background {:?} camera {:?} stereo {:?} filter {:?} sampler {:?} aovs {}",
+            scene,
+            frame_width,
+            frame_height,
+            MAX_DEPTH,
+            background,
+            cam,
+            stereo,
+            adaptive.filter,
+            adaptive.sampler,
+            adaptive.aovs,
+        )),
+        ..adaptive
+    };
+
+    // e.g. Some(Animation::from(24., 0, 47)) renders frames 0 to 47 to
+    // frame_0000.ppm onwards instead of one image to stdout
+    let animation: Option<Animation> = None;
+    let frame_prefix = "frame_";
+    let camera_animation = CameraAnimation::from(
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        aspect_ratio,
+        aperture,
+        dist_to_focus,
     );
+    // e.g. let camera_animation = CameraAnimation {
+    //     lookfrom: Track::from_keys(
+    //         Interpolation::CatmullRom,
+    //         &[(0., lookfrom), (1., Point3::from(-10., 5., 10.)), (2., Point3::from(0., 0., 15.))],
+    //     ),
+    //     ..camera_animation
+    // };
 
-    // let c: Canvas = Canvas::from_fn_parallel(
-    //     image_width as usize,
-    //     image_height as usize,
-    //     samples_per_pixel as usize,
-    //     render_pixel,
-    // );
+    // One sample at film position x, y, in pixels from the bottom left
+    let aovs = adaptive.aovs;
+    let sample_ray = |r: Ray| -> CameraSample {
+        if aovs {
+            CameraSample::Aov(ray_color_aov(r, &background, &world, &lights, MAX_DEPTH as i32))
+        } else {
+            CameraSample::Plain(ray_color(r, &background, &world, &lights, MAX_DEPTH as i32))
+        }
+    };
+    let sample_camera = |cam: &Camera, x: f32, y: f32| -> CameraSample {
+        let u = x / ((image_width - 1) as f32);
+        let v = y / ((image_height - 1) as f32);
+        sample_ray(cam.get_ray(u, v))
+    };
+    let render_sample = |x: f32, y: f32| match &stereo {
+        Some(rig) => {
+            let u = x / ((frame_width - 1) as f32);
+            let v = y / ((frame_height - 1) as f32);
+            sample_ray(rig.get_ray(u, v))
+        }
+        None => sample_camera(&cam, x, y),
+    };
 
+    if let Some(animation) = animation {
+        reset_stats();
+        let render_time = Instant::now();
+        for frame in animation.frames() {
+            let (time0, time1) = animation.shutter_interval(frame);
+            let mut frame_cam = camera_animation.camera_at(time0, time1);
+            frame_cam.set_image_height(image_height);
+            if let Some(exposure) = exposure {
+                frame_cam.set_exposure(exposure, focal_length);
+            }
+            let settings = AdaptiveSettings {
+                checkpoint_path: None,
+                ..adaptive.clone()
+            };
+            let mut c = Canvas::from_fn_progressive(image_width, image_height, settings, |x, y| {
+                sample_camera(&frame_cam, x, y)
+            });
+            if let Some(settings) = &denoise {
+                c.denoise(settings);
+            }
+            if auto_exposure {
+                c.set_exposure(c.auto_exposure(0.18));
+            } else {
+                c.set_exposure(frame_cam.exposure_scale());
+            }
+
+            let path = frame_path(frame_prefix, frame, "ppm");
+            if let Err(e) = c.write_ppm(&path) {
+                eprintln!("ERROR: could not write {}: {}", path, e);
+                std::process::exit(1);
+            }
+            if c.has_aovs() {
+                let written = if aov_layered {
+                    c.write_exr(frame_path(frame_prefix, frame, "exr"))
+                } else {
+                    c.write_aov_pfms(&format!("{}{:04}", frame_prefix, frame))
+                };
+                if let Err(e) = written {
+                    eprintln!("ERROR: could not write the AOV passes: {}", e);
+                }
+            }
+            eprint!("\nFrame {} written to {}\n", frame, path);
+        }
+        report_stats(&collect_stats(render_time.elapsed()), stats_json);
+        eprint!("\nDone in {:.2}s\n", time.elapsed().as_secs_f32());
+        return;
+    }
+
+    // Render
+    reset_stats();
+    let render_time = Instant::now();
+    let mut c = match &resume {
+        Some(path) => {
+            let mut c = Canvas::load_checkpoint(path).unwrap_or_else(|e| {
+                eprintln!("ERROR: could not resume from {}: {}", path, e);
+                std::process::exit(1);
+            });
+            let (origin, dimensions) = match adaptive.crop {
+                Some(crop) => crop.canvas_rect(frame_width, frame_height),
+                None => ((0, 0), (frame_width, frame_height)),
+            };
+            if c.dimensions() != dimensions || c.origin() != origin {
+                eprintln!("ERROR: checkpoint {} is for a different image size", path);
+                std::process::exit(1);
+            }
+            if c.fingerprint() != adaptive.fingerprint {
+                eprintln!("ERROR: checkpoint {} is for a different scene or settings", path);
+                std::process::exit(1);
+            }
+            c.render_progressive(&adaptive, render_sample);
+            c
+        }
+        None => Canvas::from_fn_progressive(
+            frame_width,
+            frame_height,
+            adaptive,
+            render_sample,
+        ),
+    };
+    let stats = collect_stats(render_time.elapsed());
+
+    if let Some(path) = composite_onto {
+        match Canvas::load_checkpoint(path) {
+            Ok(mut full) => match full.composite(&c) {
+                Ok(()) => c = full,
+                Err(e) => eprintln!("ERROR: could not composite onto {}: {}", path, e),
+            },
+            Err(e) => eprintln!("ERROR: could not load {}: {}", path, e),
+        }
+    }
+
+    if let Some(settings) = &denoise {
+        c.denoise(settings);
+    }
+
+    if auto_exposure {
+        c.set_exposure(c.auto_exposure(0.18));
+    } else {
+        c.set_exposure(cam.exposure_scale());
+    }
     
     c.write_header();
     c.write_pixels();
+    if c.has_aovs() {
+        let written = if aov_layered {
+            c.write_exr(format!("{}.exr", aov_prefix))
+        } else {
+            c.write_aov_pfms(aov_prefix)
+        };
+        if let Err(e) = written {
+            eprintln!("ERROR: could not write the AOV passes: {}", e);
+        }
+    }
+    report_stats(&stats, stats_json);
     let elapsed = time.elapsed();
     eprint!("\nDone in {:.2}s\n", elapsed.as_secs_f32());
 
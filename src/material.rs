@@ -7,9 +7,19 @@
 //              Metal surfaces reflect rays in a single direction with some
 //              fuzz/randomness. Dielectric materials are transparent (glass/ 
 //              water), reflecting and refracting based on index of refraction.
-//              Diffuse light will emmit light based on texture. 
+//              Diffuse light will emmit light based on texture, scaled by an
+//              intensity, from one or both faces and optionally shaped by an
+//              angular falloff profile for spot-like panels. NormalMap and
+//              BumpMap wrap another material and perturb the shading normal,
+//              from a tangent space normal map image or from the slope of a
+//              scalar height texture, before handing the hit to it. Scattering
+//              draws its random numbers from the current sampler, and
+//              scattering_pdf gives the density of the directions it picks.
+//              A materiallist numbers the materials of a scene as they are
+//              added, giving each a material id that is the same every run.
 */
-use crate::{hittable::*, ray::*, texture::*, utils::*, vec3::*};
+use crate::{hittable::*, ray::*, sampler::*, texture::*, utils::*, vec3::*};
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 pub trait Material: Send + Sync {
@@ -20,9 +30,26 @@ pub trait Material: Send + Sync {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
-    fn emitted(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
         Color::new()
     }
+    /// BRDF times the cosine term for light arriving from the unit direction
+    /// `wi`. Delta lights are only reached through this, so perfectly specular
+    /// materials, which can never see them, keep the default.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Color {
+        Color::new()
+    }
+    /// Density over solid angle of `scatter` sending the ray out along the
+    /// unit direction `wi`, whether or not the ray is then absorbed.
+    /// Materials that only scatter into exact directions keep the default.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f32 {
+        0.
+    }
+    /// Id given to the material when it was added to a materiallist, 0
+    /// for materials that never were.
+    fn material_id(&self) -> u32 {
+        0
+    }
 }
 #[derive(Clone)]
 pub struct Isotropic {
@@ -48,11 +75,18 @@ impl Material for Isotropic {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        *scattered = Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        let (u, v) = sample_2d();
+        *scattered = Ray::new(rec.p, Vec3::unit_vector_from(u, v), r_in.time());
+        *attenuation = self.albedo.filtered_value(rec.u, rec.v, &rec.p, rec.footprint);
 
         true
     }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, _wi: Vec3) -> Color {
+        self.albedo.filtered_value(rec.u, rec.v, &rec.p, rec.footprint) / (4. * PI)
+    }
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f32 {
+        1. / (4. * PI)
+    }
 }
 
 #[derive(Clone)]
@@ -77,16 +111,24 @@ impl Material for Lambertian {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let mut scatter_direction = rec.normal + Color::random_unit_vector();
+        let (u, v) = sample_2d();
+        let mut scatter_direction = rec.normal + Vec3::unit_vector_from(u, v);
 
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
         *scattered = Ray::new(rec.p, scatter_direction, r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.albedo.filtered_value(rec.u, rec.v, &rec.p, rec.footprint);
         true
     }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let cos_theta = dot(rec.normal, wi).max(0.);
+        self.albedo.filtered_value(rec.u, rec.v, &rec.p, rec.footprint) * (cos_theta / PI)
+    }
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        dot(rec.normal, wi).max(0.) / PI
+    }
 }
 #[derive(Copy, Clone)]
 pub struct Metal {
@@ -114,14 +156,29 @@ impl Material for Metal {
         scattered: &mut Ray,
     ) -> bool {
         let reflected = reflect(unit_vector(r_in.direction()), rec.normal);
-        *scattered = Ray::new(
-            rec.p,
-            reflected + Vec3::random_in_unit_sphere() * self.fuzz,
-            r_in.time(),
-        );
+        let (u, v) = sample_2d();
+        let fuzz = Vec3::in_unit_sphere_from(u, v, sample_1d());
+        *scattered = Ray::new(rec.p, reflected + fuzz * self.fuzz, r_in.time());
         *attenuation = self.albedo;
         dot(scattered.direction(), rec.normal) > 0.
     }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        if self.fuzz <= 0. {
+            return 0.;
+        }
+        // The scattered direction points at a uniform point of the ball of
+        // radius fuzz around the unit mirror direction, so its density is
+        // the ball's volume along `wi`, s^2 ds between the entry and exit
+        // distances, over the whole volume
+        let reflected = reflect(unit_vector(r_in.direction()), rec.normal);
+        let c = dot(wi, reflected);
+        let h = c * c - (1. - self.fuzz * self.fuzz);
+        if c <= 0. || h < 0. {
+            return 0.;
+        }
+        let (s1, s2) = (c - h.sqrt(), c + h.sqrt());
+        (s2.powi(3) - s1.max(0.).powi(3)) / (4. * PI * self.fuzz.powi(3))
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -158,7 +215,7 @@ impl Material for Dielectric {
         let cannot_refract = refraction_ratio * sin_theta > 1.;
 
         let direction =
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_double(0., 1.) {
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sample_1d() {
                 reflect(unit_direction, rec.normal)
             } else {
                 refract(unit_direction, rec.normal, refraction_ratio)
@@ -175,16 +232,77 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmissionProfile {
+    /// Same radiance in every direction
+    Uniform,
+    /// Radiance falls off as cos^exponent of the angle from the normal
+    Cosine(f32),
+    /// Full radiance within the inner cone, fading smoothly to nothing at the
+    /// outer cone. Stored as cosines of the half angles.
+    Spot { cos_inner: f32, cos_outer: f32 },
+}
+impl EmissionProfile {
+    /// Spot profile from inner and outer half angles in degrees.
+    pub fn spot(inner: f32, outer: f32) -> Self {
+        let outer = outer.max(inner);
+        Self::Spot {
+            cos_inner: degrees_to_radians(inner).cos(),
+            cos_outer: degrees_to_radians(outer).cos(),
+        }
+    }
+    /// Fraction of the radiance sent at `cos_theta` from the normal.
+    pub fn weight(&self, cos_theta: f32) -> f32 {
+        match *self {
+            EmissionProfile::Uniform => 1.,
+            EmissionProfile::Cosine(exponent) => cos_theta.max(0.).powf(exponent),
+            EmissionProfile::Spot {
+                cos_inner,
+                cos_outer,
+            } => {
+                if cos_inner <= cos_outer {
+                    return if cos_theta >= cos_inner { 1. } else { 0. };
+                }
+                let t = clamp((cos_theta - cos_outer) / (cos_inner - cos_outer), 0., 1.);
+                t * t * (3. - 2. * t)
+            }
+        }
+    }
+}
+
+/// `emit` gives the color, `intensity` scales it independently. One sided
+/// lights only emit from the face their outward normal points to; wrap the
+/// object in a FlipFace to emit from the other face instead.
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    pub intensity: f32,
+    pub two_sided: bool,
+    pub profile: EmissionProfile,
 }
 impl DiffuseLight {
     pub fn from(a: Arc<dyn Texture>) -> Self {
-        Self { emit: a }
+        Self {
+            emit: a,
+            intensity: 1.,
+            two_sided: true,
+            profile: EmissionProfile::Uniform,
+        }
     }
     pub fn from_color(c: Color) -> Self {
+        Self::from(Arc::new(SolidColor::from(c)))
+    }
+    pub fn one_sided(a: Arc<dyn Texture>, intensity: f32) -> Self {
+        Self {
+            intensity,
+            two_sided: false,
+            ..Self::from(a)
+        }
+    }
+    /// One sided emitter whose light is confined to a cone around the normal.
+    pub fn spot(c: Color, intensity: f32, inner: f32, outer: f32) -> Self {
         Self {
-            emit: Arc::new(SolidColor::from(c)),
+            profile: EmissionProfile::spot(inner, outer),
+            ..Self::one_sided(Arc::new(SolidColor::from(c)), intensity)
         }
     }
 }
@@ -199,7 +317,214 @@ impl Material for DiffuseLight {
     ) -> bool {
         false
     }
-    fn emitted(&self, u: f32, v: f32, p: &Point3) -> Color {
-        self.emit.value(u, v, p)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if !self.two_sided && !rec.front_face {
+            return Color::new();
+        }
+        let weight = match self.profile {
+            EmissionProfile::Uniform => 1.,
+            profile => profile.weight(dot(unit_vector(r_in.direction()).inv(), rec.normal)),
+        };
+        self.emit.filtered_value(rec.u, rec.v, &rec.p, rec.footprint) * (self.intensity * weight)
+    }
+}
+
+// Orthonormal tangent frame around the shading normal, with the tangent
+// following dpdu and the bitangent on the same side as dpdv
+fn tangent_frame(rec: &HitRecord) -> Option<(Vec3, Vec3)> {
+    let n = rec.normal;
+    let t = rec.dpdu - n * dot(n, rec.dpdu);
+    if t.near_zero() {
+        return None;
+    }
+    let t = unit_vector(t);
+    let mut b = cross(n, t);
+    if dot(b, rec.dpdv) < 0. {
+        b = b.inv();
+    }
+    Some((t, b))
+}
+
+pub struct NormalMap {
+    inner: Arc<dyn Material>,
+    map: Arc<ImageTexture>,
+    strength: f32,
+}
+impl NormalMap {
+    /// `map` should be loaded with `ColorSpace::Linear`, it holds tangent
+    /// space normals encoded as (n + 1) / 2.
+    pub fn from(inner: Arc<dyn Material>, map: Arc<ImageTexture>, strength: f32) -> Self {
+        Self {
+            inner,
+            map,
+            strength,
+        }
+    }
+}
+
+impl NormalMap {
+    fn shade<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        let mut shaded = *rec;
+        if let Some((t, b)) = tangent_frame(rec) {
+            let c = self.map.filtered_value(rec.u, rec.v, &rec.p, rec.footprint);
+            let tx = (2. * c.x() - 1.) * self.strength;
+            let ty = (2. * c.y() - 1.) * self.strength;
+            let tz = 2. * c.z() - 1.;
+            let perturbed = t * tx + b * ty + rec.normal * tz;
+            if !perturbed.near_zero() {
+                shaded.normal = unit_vector(perturbed);
+            }
+        }
+        shaded
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner.scatter(r_in, self.shade(&rec), attenuation, scattered)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
     }
-}
\ No newline at end of file
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, &self.shade(rec), wi)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.inner.scattering_pdf(r_in, &self.shade(rec), wi)
+    }
+    fn material_id(&self) -> u32 {
+        self.inner.material_id()
+    }
+}
+
+pub struct BumpMap {
+    inner: Arc<dyn Material>,
+    bump: Arc<dyn Texture>,
+    scale: f32,
+}
+impl BumpMap {
+    /// The height at a point is the average of `bump`'s channels times `scale`.
+    pub fn from(inner: Arc<dyn Material>, bump: Arc<dyn Texture>, scale: f32) -> Self {
+        Self { inner, bump, scale }
+    }
+    fn height(&self, u: f32, v: f32, p: &Point3) -> f32 {
+        let c = self.bump.value(u, v, p);
+        self.scale * (c.x() + c.y() + c.z()) / 3.
+    }
+    fn shade<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        let mut shaded = *rec;
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return shaded;
+        }
+        // Forward differences of the height along u and v
+        let du = 0.0005;
+        let dv = 0.0005;
+        let d = self.height(rec.u, rec.v, &rec.p);
+        let d_u = self.height(rec.u + du, rec.v, &(rec.p + rec.dpdu * du));
+        let d_v = self.height(rec.u, rec.v + dv, &(rec.p + rec.dpdv * dv));
+
+        let dpdu = rec.dpdu + rec.normal * ((d_u - d) / du);
+        let dpdv = rec.dpdv + rec.normal * ((d_v - d) / dv);
+        let mut n = cross(dpdu, dpdv);
+        if !n.near_zero() {
+            if dot(n, rec.normal) < 0. {
+                n = n.inv();
+            }
+            shaded.normal = unit_vector(n);
+        }
+        shaded
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner.scatter(r_in, self.shade(&rec), attenuation, scattered)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, &self.shade(rec), wi)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.inner.scattering_pdf(r_in, &self.shade(rec), wi)
+    }
+    fn material_id(&self) -> u32 {
+        self.inner.material_id()
+    }
+}
+
+/// Materials of a scene, numbered from 1 in the order they are added. The
+/// numbers are the material ids of the AOVs, the same on every run of the
+/// same scene.
+pub struct MaterialList {
+    pub materials: Vec<Arc<dyn Material>>,
+}
+
+impl MaterialList {
+    pub fn new() -> Self {
+        Self { materials: vec![] }
+    }
+    /// Adds `material`, returning it tagged with its id for the objects
+    /// that use it.
+    pub fn add(&mut self, material: Arc<dyn Material>) -> Arc<dyn Material> {
+        let listed: Arc<dyn Material> = Arc::new(ListedMaterial {
+            id: self.materials.len() as u32 + 1,
+            inner: material,
+        });
+        self.materials.push(listed.clone());
+        listed
+    }
+    pub fn clear(&mut self) {
+        self.materials.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+impl Default for MaterialList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct ListedMaterial {
+    id: u32,
+    inner: Arc<dyn Material>,
+}
+
+impl Material for ListedMaterial {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner.scatter(r_in, rec, attenuation, scattered)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, rec, wi)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.inner.scattering_pdf(r_in, rec, wi)
+    }
+    fn material_id(&self) -> u32 {
+        self.id
+    }
+}
@@ -0,0 +1,131 @@
+/*
+// Description: This file provides a Perlin noise generator for procedural
+//              textures. The perlin struct holds a table of random unit
+//              gradient vectors and three permutation tables, one per axis.
+//              The noise method hashes the lattice corners around a point,
+//              takes the dot product of each corner's gradient with the offset
+//              to the point, and blends them with a Hermite-smoothed trilinear
+//              interpolation. Turbulence sums the absolute value of several
+//              octaves of noise, and fbm (fractional brownian motion) sums
+//              signed octaves with a configurable lacunarity and gain.
+*/
+
+use crate::{utils::*, vec3::*};
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| unit_vector(Vec3::random(-1., 1.)))
+            .collect();
+
+        Self {
+            ranvec,
+            perm_x: Self::generate_perm(),
+            perm_y: Self::generate_perm(),
+            perm_z: Self::generate_perm(),
+        }
+    }
+
+    fn generate_perm() -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+
+        // Fisher-Yates shuffle
+        for i in (1..POINT_COUNT).rev() {
+            let target = random_int(0, i as i32 + 1) as usize;
+            p.swap(i, target);
+        }
+        p
+    }
+
+    /// Gradient noise at `p`, roughly in the range [-1, 1].
+    pub fn noise(&self, p: &Point3) -> f32 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as i32;
+        let j = p.y().floor() as i32;
+        let k = p.z().floor() as i32;
+
+        let mut c = [[[Vec3::new(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.ranvec[self.perm_x[((i + di as i32) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i32) & 255) as usize]];
+                }
+            }
+        }
+
+        perlin_interp(&c, u, v, w)
+    }
+
+    /// Sum of `depth` octaves of absolute noise, each at double the frequency
+    /// and half the weight of the previous one.
+    pub fn turb(&self, p: &Point3, depth: usize) -> f32 {
+        let mut accum = 0.;
+        let mut temp_p = *p;
+        let mut weight = 1.;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = temp_p * 2.;
+        }
+
+        accum.abs()
+    }
+
+    /// Fractional brownian motion: signed octaves of noise where each octave
+    /// scales the frequency by `lacunarity` and the amplitude by `gain`.
+    pub fn fbm(&self, p: &Point3, octaves: usize, lacunarity: f32, gain: f32) -> f32 {
+        let mut accum = 0.;
+        let mut temp_p = *p;
+        let mut amplitude = 1.;
+
+        for _ in 0..octaves {
+            accum += amplitude * self.noise(&temp_p);
+            amplitude *= gain;
+            temp_p = temp_p * lacunarity;
+        }
+
+        accum
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+    let uu = u * u * (3. - 2. * u);
+    let vv = v * v * (3. - 2. * v);
+    let ww = w * w * (3. - 2. * w);
+    let mut accum = 0.;
+
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, corner) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f32, j as f32, k as f32);
+                let weight_v = Vec3::from(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1. - fi) * (1. - uu))
+                    * (fj * vv + (1. - fj) * (1. - vv))
+                    * (fk * ww + (1. - fk) * (1. - ww))
+                    * dot(*corner, weight_v);
+            }
+        }
+    }
+    accum
+}
@@ -5,9 +5,20 @@
 //              a point along the ray's path. The ray_color function calculates the color
 //              as it inetracts with the scene. This method supports recursion to handle
 //              multiple bounces of rays, simulating reflection/refractions/emitted light
-//              and light scattering
+//              and light scattering. Delta lights from the scene's lightlist
+//              are added at every hit with shadow rays towards each light.
+//              ray_color_aov traces the same path but also returns the first
+//              hit's surface data and splits the radiance into emitted,
+//              direct and indirect light for the AOV passes. Both count
+//              the rays they trace and the length of each camera path in the
+//              render statistics. A ray can stand for a cone of directions,
+//              whose width at each hit sets how widely textures are
+//              filtered there.
 */
+use crate::aov::*;
 use crate::hittable::*;
+use crate::light::*;
+use crate::stats::*;
 use crate::vec3::*;
 
 #[derive(Copy, Clone)]
@@ -15,6 +26,10 @@ pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
     pub tm: f32,
+    /// Width of the cone of directions the ray stands for at its origin
+    pub width: f32,
+    /// How much the cone widens per unit of distance along the ray
+    pub spread: f32,
 }
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3, time: f32) -> Self {
@@ -22,8 +37,21 @@ impl Ray {
             orig: origin,
             dir: direction,
             tm: time,
+            width: 0.,
+            spread: 0.,
         }
     }
+    /// The same ray standing for a cone `width` across at its origin and
+    /// widening by `spread` per unit of distance.
+    pub fn with_cone(mut self, width: f32, spread: f32) -> Self {
+        self.width = width;
+        self.spread = spread;
+        self
+    }
+    /// Width of the ray's cone at `at(t)`.
+    pub fn width_at(&self, t: f32) -> f32 {
+        self.width + self.spread * t * self.dir.len()
+    }
     pub fn origin(&self) -> Point3 {
         self.orig
     }
@@ -38,27 +66,139 @@ impl Ray {
     }
 }
 
-pub fn ray_color(r: Ray, background: &Color, world: &impl Hittable, depth: i32) -> Color {
+pub fn ray_color(
+    r: Ray,
+    background: &Color,
+    world: &impl Hittable,
+    lights: &LightList,
+    depth: i32,
+) -> Color {
+    count_ray(RayKind::Primary);
+    let mut bounces = 0;
+    let (emitted, reflected) = trace(r, background, world, lights, depth, &mut bounces);
+    count_path(bounces);
+    emitted + reflected
+}
+
+/// Traces like `ray_color`, keeping the first hit for the AOV passes.
+pub fn ray_color_aov(
+    r: Ray,
+    background: &Color,
+    world: &impl Hittable,
+    lights: &LightList,
+    depth: i32,
+) -> AovSample {
+    let mut rec = HitRecord::void();
+    let mut sample = AovSample::default();
+
+    if depth <= 0 {
+        return sample;
+    }
+
+    count_ray(RayKind::Primary);
+    if !world.hit(&r, 0.001, f32::INFINITY, &mut rec) {
+        count_path(0);
+        sample.emission = *background;
+        return sample;
+    }
+    rec.set_footprint(&r);
+
+    sample.hit = true;
+    sample.depth = rec.t * r.direction().len();
+    sample.position = rec.p;
+    sample.normal = rec.normal;
+    sample.u = rec.u;
+    sample.v = rec.v;
+    sample.material_id = rec.mat_ptr.material_id();
+    sample.object_id = rec.object_id as u32;
+    sample.emission = rec.mat_ptr.emitted(&r, &rec);
+    sample.direct = direct_light(&r, &rec, world, lights);
+
+    let mut scattered = Ray::new(Point3::new(), Vec3::new(), 0.);
+    let mut attenuation = Color::new();
+    if !rec
+        .mat_ptr
+        .scatter(r, rec, &mut attenuation, &mut scattered)
+    {
+        count_path(1);
+        return sample;
+    }
+    sample.albedo = attenuation;
+    let scattered = scattered.with_cone(r.width_at(rec.t), r.spread);
+
+    // Whatever the next surface emits arrived in one bounce
+    count_ray(RayKind::Secondary);
+    let mut bounces = 1;
+    let (emitted, reflected) = trace(scattered, background, world, lights, depth - 1, &mut bounces);
+    count_path(bounces);
+    sample.direct.add(attenuation * emitted);
+    sample.indirect = attenuation * reflected;
+    sample
+}
+
+// Radiance along `r`, split into what the first surface emits (or the
+// background on a miss) and what it reflects, adding the surfaces hit on
+// the way to `bounces`
+fn trace(
+    r: Ray,
+    background: &Color,
+    world: &impl Hittable,
+    lights: &LightList,
+    depth: i32,
+    bounces: &mut u64,
+) -> (Color, Color) {
     let mut rec = HitRecord::void();
 
     if depth <= 0 {
-        return Color::new();
+        return (Color::new(), Color::new());
     }
 
     if !world.hit(&r, 0.001, f32::INFINITY, &mut rec) {
-        return *background;
+        return (*background, Color::new());
     }
-    
+    *bounces += 1;
+    rec.set_footprint(&r);
+
     let mut scattered = Ray::new(Point3::new(), Vec3::new(), 0.);
     let mut attenuation = Color::new();
-    let emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
+    let emitted = rec.mat_ptr.emitted(&r, &rec);
+    let direct = direct_light(&r, &rec, world, lights);
 
     if !rec
         .mat_ptr
         .scatter(r, rec, &mut attenuation, &mut scattered)
     {
-        return emitted;
+        return (emitted, direct);
     }
+    // Carry on the cone, so textures seen after a bounce filter as widely
+    let scattered = scattered.with_cone(r.width_at(rec.t), r.spread);
+
+    count_ray(RayKind::Secondary);
+    let (next_emitted, next_reflected) =
+        trace(scattered, background, world, lights, depth - 1, bounces);
+    (emitted, direct + attenuation * (next_emitted + next_reflected))
+}
 
-    emitted + attenuation * ray_color(scattered, background, world, depth - 1)
-}
\ No newline at end of file
+// Light reaching the hit point straight from the delta lights, each checked
+// for occlusion with a shadow ray
+fn direct_light(r: &Ray, rec: &HitRecord, world: &impl Hittable, lights: &LightList) -> Color {
+    let mut total = Color::new();
+    for light in &lights.lights {
+        let sample = match light.sample_li(&rec.p) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let f = rec.mat_ptr.eval(r, rec, sample.wi);
+        if f.near_zero() {
+            continue;
+        }
+        let shadow_ray = Ray::new(rec.p, sample.wi, r.time());
+        count_ray(RayKind::Shadow);
+        let mut shadow_rec = HitRecord::void();
+        if world.hit(&shadow_ray, 0.001, sample.dist * (1. - 1e-4), &mut shadow_rec) {
+            continue;
+        }
+        total.add(f * sample.radiance);
+    }
+    total
+}
@@ -8,7 +8,7 @@
 //              Yzrect contains fields for the y and z ranges, and x-
 //              coordinate as k.
 */
-use crate::{aabb::*, material::*, hittable::*, ray::*, vec3::*};
+use crate::{aabb::*, material::*, hittable::*, ray::*, stats::*, vec3::*};
 use std::sync::Arc;
 
 fn point_in_quadrilateral(pointz: f32, pointy: f32, quad: &Quadrilateral) -> bool {
@@ -84,7 +84,7 @@ impl Quadrilateral {
     let max_z: f32 = comp5.max(comp6);
     let comp7: f32 = pair0.1.max(pair1.1);
     let comp8: f32 = pair2.1.max(pair3.1);
-    let max_y: f32 = comp7.min(comp8);
+    let max_y: f32 = comp7.max(comp8);
     let z0 = pair0.0;
     let z1 = pair1.0;
     let z2 = pair2.0;
@@ -114,27 +114,22 @@ impl Quadrilateral {
 }
 
 
+impl Default for Quadrilateral {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Hittable for Quadrilateral {
   fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
-    let comp1: f32 = self.z0.min(self.z1);
-    let comp2: f32 = self.z2.min(self.z3);
-    let minz: f32 = comp1.min(comp2);
-    let comp3: f32 = self.y0.min(self.y1);
-    let comp4: f32 = self.y2.min(self.y3);
-    let miny: f32 = comp3.min(comp4);
-    let comp5: f32 = self.z0.max(self.z1);
-    let comp6: f32 = self.z2.max(self.z3);
-    let maxz: f32 = comp5.max(comp6);
-    let comp7: f32 = self.y0.max(self.y1);
-    let comp8: f32 = self.y2.max(self.y3);
-    let maxy: f32 = comp7.min(comp8);
     *output_box = Bb::from(
-      &Point3::from(self.k - 0.0001, miny, minz),
-      &Point3::from(self.k + 0.0001, maxy, maxz),
+      &Point3::from(self.k - 0.0001, self.miny, self.minz),
+      &Point3::from(self.k + 0.0001, self.maxy, self.maxz),
     );
     true
   }
   fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    count_intersection_test(Primitive::Quadrilateral);
     let t = (self.k - r.origin().x()) / r.direction().x();
     
     if t < t_min || t > t_max {
@@ -152,19 +147,23 @@ impl Hittable for Quadrilateral {
     // ********** WARNING *************
     // Possibly buggy wiht non-axis aligned rectangles, and numerically unstable at edges
     let s = (z - self.minz) / (self.maxz - self.minz); // Simplified linear interpolation
-    let t = (y - self.miny) / (self.maxy - self.miny); // Simplified linear interpolation
+    let w = (y - self.miny) / (self.maxy - self.miny); // Simplified linear interpolation
 
-    rec.u = (1.0 - t) * ((1.0 - s) * 0.0 + s * 1.0) + t * ((1.0 - s) * 0.0 + s * 1.0);
-    rec.v = (1.0 - t) * ((1.0 - s) * 0.0 + s * 0.0) + t * ((1.0 - s) * 1.0 + s * 1.0);
+    rec.u = (1.0 - w) * ((1.0 - s) * 0.0 + s * 1.0) + w * ((1.0 - s) * 0.0 + s * 1.0);
+    rec.v = (1.0 - w) * ((1.0 - s) * 0.0 + s * 0.0) + w * ((1.0 - s) * 1.0 + s * 1.0);
     // *******************************
 
+    rec.dpdu = Vec3::from(0., 0., self.maxz - self.minz);
+    rec.dpdv = Vec3::from(0., self.maxy - self.miny, 0.);
+
     rec.t = t;
 
-    let outward_normal = Vec3::from(0., 0., 1.);
+    let outward_normal = Vec3::from(1., 0., 0.);
     rec.set_face_normal(*r, outward_normal);
     rec.mat_ptr = &*self.mp;
     rec.p = r.at(t);
 
+    count_intersection_hit(Primitive::Quadrilateral);
     true
   }
 }
@@ -201,6 +200,12 @@ impl XyRect {
   }
 }
 
+impl Default for XyRect {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Hittable for XyRect {
   fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
     *output_box = Bb::from(
@@ -210,6 +215,7 @@ impl Hittable for XyRect {
     true
   }
   fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    count_intersection_test(Primitive::XyRect);
     let t = (self.k - r.origin().z()) / r.direction().z();
     if t < t_min || t > t_max {
       return false;
@@ -222,6 +228,8 @@ impl Hittable for XyRect {
 
     rec.u = (x - self.x0) / (self.x1 - self.x0);
     rec.v = (y - self.y0) / (self.y1 - self.y0);
+    rec.dpdu = Vec3::from(self.x1 - self.x0, 0., 0.);
+    rec.dpdv = Vec3::from(0., self.y1 - self.y0, 0.);
 
     rec.t = t;
 
@@ -230,6 +238,7 @@ impl Hittable for XyRect {
     rec.mat_ptr = &*self.mp;
     rec.p = r.at(t);
 
+    count_intersection_hit(Primitive::XyRect);
     true
   }
 }
@@ -266,6 +275,12 @@ impl XzRect {
   }
 }
 
+impl Default for XzRect {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Hittable for XzRect {
   fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
     *output_box = Bb::from(
@@ -275,6 +290,7 @@ impl Hittable for XzRect {
     true
   }
   fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    count_intersection_test(Primitive::XzRect);
     let t = (self.k - r.origin().y()) / r.direction().y();
     if t < t_min || t > t_max {
       return false;
@@ -287,14 +303,17 @@ impl Hittable for XzRect {
 
     rec.u = (x - self.x0) / (self.x1 - self.x0);
     rec.v = (z - self.z0) / (self.z1 - self.z0);
+    rec.dpdu = Vec3::from(self.x1 - self.x0, 0., 0.);
+    rec.dpdv = Vec3::from(0., 0., self.z1 - self.z0);
 
     rec.t = t;
 
-    let outward_normal = Vec3::from(0., 0., 1.);
+    let outward_normal = Vec3::from(0., 1., 0.);
     rec.set_face_normal(*r, outward_normal);
     rec.mat_ptr = &*self.mp;
     rec.p = r.at(t);
 
+    count_intersection_hit(Primitive::XzRect);
     true
   }
 }
@@ -331,6 +350,12 @@ impl YzRect {
   }
 }
 
+impl Default for YzRect {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Hittable for YzRect {
   fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
     *output_box = Bb::from(
@@ -340,6 +365,7 @@ impl Hittable for YzRect {
     true
   }
   fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    count_intersection_test(Primitive::YzRect);
     let t = (self.k - r.origin().x()) / r.direction().x();
     if t < t_min || t > t_max {
       return false;
@@ -352,14 +378,17 @@ impl Hittable for YzRect {
 
     rec.u = (y - self.y0) / (self.y1 - self.y0);
     rec.v = (z - self.z0) / (self.z1 - self.z0);
+    rec.dpdu = Vec3::from(0., self.y1 - self.y0, 0.);
+    rec.dpdv = Vec3::from(0., 0., self.z1 - self.z0);
 
     rec.t = t;
 
-    let outward_normal = Vec3::from(0., 0., 1.);
+    let outward_normal = Vec3::from(1., 0., 0.);
     rec.set_face_normal(*r, outward_normal);
     rec.mat_ptr = &*self.mp;
     rec.p = r.at(t);
 
+    count_intersection_hit(Primitive::YzRect);
     true
   }
 }
\ No newline at end of file
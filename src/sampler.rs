@@ -0,0 +1,409 @@
+/*
+// Description: This file provides the samplers that pick the random numbers of
+//              a path. A sampler maps a pixel, a sample index and a dimension
+//              to a value in [0, 1), so any sample can be drawn in any order,
+//              on any thread, and a resumed render carries on with the same
+//              sequence. A seed per render picks independent sequences, so
+//              partial renders can be merged. The independent sampler is plain
+//              random numbers, the stratified one jitters samples in shuffled
+//              strata (correlated multi-jitter in 2D), the Halton one uses
+//              radical inverses in prime bases with digits scrambled per
+//              pixel, the Sobol one shuffles and Owen scrambles the first two
+//              Sobol dimensions with hashing, and the blue-noise one shifts
+//              one scrambled Sobol sequence per pixel by a void-and-cluster
+//              mask so the error between neighbouring pixels is spread as blue
+//              noise. The canvas starts a sample on the rendering thread, and
+//              the camera and materials draw their dimensions in turn from it
+//              through sample_1d and sample_2d, which fall back to random
+//              numbers outside of a sample.
+*/
+
+use crate::utils::{random_double, reseed_random};
+use lazy_static::*;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+pub trait Sampler: Send + Sync + Debug {
+    /// Value in [0, 1) for dimension `dim` of sample `index` of a pixel.
+    /// `seed` picks one of many independent sequences, so renders of the
+    /// same frame with different seeds draw different samples.
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> f32;
+    /// Point in [0, 1)^2 for dimensions `dim` and `dim + 1`.
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> (f32, f32) {
+        (
+            self.get_1d(pixel, index, dim, seed),
+            self.get_1d(pixel, index, dim + 1, seed),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&self, _pixel: (u32, u32), _index: u32, _dim: u32, _seed: u32) -> f32 {
+        random_double(0., 1.)
+    }
+}
+
+/// Jittered strata for `spp` samples per pixel. Later samples start new sets
+/// of strata, so rendering past `spp` stays stratified per set.
+#[derive(Clone, Copy, Debug)]
+pub struct StratifiedSampler {
+    spp: u32,
+}
+
+impl StratifiedSampler {
+    pub fn from(spp: usize) -> Self {
+        Self {
+            spp: (spp as u32).max(1),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> f32 {
+        let seed = hash3(pixel.0, pixel.1, dim) ^ seed ^ hash(index / self.spp);
+        let stratum = permute(index % self.spp, self.spp, seed);
+        let jitter = to_unit(hash(index ^ seed));
+        ((stratum as f32 + jitter) / self.spp as f32).min(ONE_MINUS_EPSILON)
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> (f32, f32) {
+        // Correlated multi-jittered sampling (Kensler 2013)
+        let n = self.spp;
+        let m = ((n as f32).sqrt().ceil() as u32).max(1);
+        let rows = n.div_ceil(m);
+        let p = hash3(pixel.0, pixel.1, dim) ^ seed ^ hash(index / n);
+        let s = permute(index % n, n, p.wrapping_mul(0x51633e2d));
+        let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+        let sy = permute(s / m, rows, p.wrapping_mul(0x02e5be93));
+        let jx = to_unit(hash(s ^ p.wrapping_mul(0x967a889b)));
+        let jy = to_unit(hash(s ^ p.wrapping_mul(0x368cc8b7)));
+        let x = ((s % m) as f32 + (sy as f32 + jx) / rows as f32) / m as f32;
+        let y = ((s / m) as f32 + (sx as f32 + jy) / m as f32) / rows as f32;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence with Owen scrambled digits, seeded per pixel. Dimensions
+/// past the prime table get independent random values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HaltonSampler;
+
+impl Sampler for HaltonSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> f32 {
+        let seed = hash3(pixel.0, pixel.1, dim) ^ seed;
+        let Some(&base) = PRIMES.get(dim as usize) else {
+            return to_unit(hash(index ^ seed));
+        };
+        (scrambled_radical_inverse(base, index, seed) as f32).min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Owen scrambled Sobol points. Every pair of dimensions is the first two
+/// Sobol dimensions with its own shuffle of the sample order and scramble,
+/// as in Burley's "Practical Hash-based Owen Scrambling".
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SobolSampler;
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> f32 {
+        owen_sobol_1d(index, hash3(pixel.0, pixel.1, dim) ^ seed)
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> (f32, f32) {
+        owen_sobol_2d(index, hash3(pixel.0, pixel.1, dim) ^ seed)
+    }
+}
+
+/// The same scrambled Sobol sequence in every pixel, toroidally shifted by
+/// a blue-noise mask value that differs per dimension.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlueNoiseSampler;
+
+impl Sampler for BlueNoiseSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> f32 {
+        let shift = blue_noise(pixel, hash(dim) ^ seed);
+        let x = owen_sobol_1d(index, hash(dim ^ 0x5bd1e995) ^ seed);
+        (x + shift).fract().min(ONE_MINUS_EPSILON)
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32, seed: u32) -> (f32, f32) {
+        let (x, y) = owen_sobol_2d(index, hash(dim ^ 0x5bd1e995) ^ seed);
+        let seed = hash(dim) ^ seed;
+        let (shift_x, shift_y) = (blue_noise(pixel, seed), blue_noise(pixel, hash(seed)));
+        (
+            (x + shift_x).fract().min(ONE_MINUS_EPSILON),
+            (y + shift_y).fract().min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+struct SampleState {
+    sampler: Arc<dyn Sampler>,
+    pixel: (u32, u32),
+    index: u32,
+    dim: u32,
+    seed: u32,
+}
+
+thread_local! {
+    static CURRENT_SAMPLE: RefCell<Option<SampleState>> = const { RefCell::new(None) };
+}
+
+/// Makes sample `index` of `pixel` in the render seeded with `seed` the
+/// source of `sample_1d` and `sample_2d` on this thread, starting from its
+/// first dimension. The seeded random numbers restart from the same values.
+pub fn start_sample(sampler: &Arc<dyn Sampler>, pixel: (usize, usize), index: usize, seed: u32) {
+    // Seed 0 hashes to 0, leaving the sequences of unseeded renders as they are
+    let seed = hash(seed);
+    let stream = hash3(pixel.0 as u32, pixel.1 as u32, 0) ^ seed;
+    reseed_random(((stream as u64) << 32) | index as u64);
+    CURRENT_SAMPLE.with(|current| {
+        *current.borrow_mut() = Some(SampleState {
+            sampler: Arc::clone(sampler),
+            pixel: (pixel.0 as u32, pixel.1 as u32),
+            index: index as u32,
+            dim: 0,
+            seed,
+        });
+    });
+}
+pub fn end_sample() {
+    CURRENT_SAMPLE.with(|current| *current.borrow_mut() = None);
+}
+
+/// Next dimension of the current sample.
+pub fn sample_1d() -> f32 {
+    CURRENT_SAMPLE.with(|current| match current.borrow_mut().as_mut() {
+        Some(state) => {
+            let value = state.sampler.get_1d(state.pixel, state.index, state.dim, state.seed);
+            state.dim += 1;
+            value
+        }
+        None => random_double(0., 1.),
+    })
+}
+/// Next two dimensions of the current sample.
+pub fn sample_2d() -> (f32, f32) {
+    CURRENT_SAMPLE.with(|current| match current.borrow_mut().as_mut() {
+        Some(state) => {
+            let value = state.sampler.get_2d(state.pixel, state.index, state.dim, state.seed);
+            state.dim += 2;
+            value
+        }
+        None => (random_double(0., 1.), random_double(0., 1.)),
+    })
+}
+
+// Integer hash (lowbias32)
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+fn hash3(a: u32, b: u32, c: u32) -> u32 {
+    hash(a ^ hash(b ^ hash(c)))
+}
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// Hash based permutation of 0..len (Kensler 2013)
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    if len <= 1 {
+        return 0;
+    }
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | (p >> 27));
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+// Radical inverse with every digit permuted depending on the digits below
+// it, carried on past the last non zero digit to full f32 precision
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u32) -> f64 {
+    let inv_base = 1. / base as f64;
+    let (mut value, mut scale, mut prefix) = (0., inv_base, seed);
+    while scale > 1e-8 {
+        let digit = index % base;
+        index /= base;
+        value += permute(digit, base, hash(prefix)) as f64 * scale;
+        prefix = hash(prefix ^ digit.wrapping_add(1).wrapping_mul(0x9e3779b9));
+        scale *= inv_base;
+    }
+    value
+}
+
+// First two Sobol dimensions: the van der Corput sequence and the one from
+// the polynomial x + 1, as 32-bit fractions
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let x = index.reverse_bits();
+    let (mut y, mut v, mut i) = (0u32, 1u32 << 31, index);
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn owen_sobol_1d(index: u32, seed: u32) -> f32 {
+    let index = nested_uniform_scramble(index, seed);
+    to_unit(nested_uniform_scramble(index.reverse_bits(), hash(seed)))
+}
+fn owen_sobol_2d(index: u32, seed: u32) -> (f32, f32) {
+    let (x, y) = sobol_2d(nested_uniform_scramble(index, seed));
+    let seed_x = hash(seed);
+    let seed_y = hash(seed_x);
+    (
+        to_unit(nested_uniform_scramble(x, seed_x)),
+        to_unit(nested_uniform_scramble(y, seed_y)),
+    )
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+lazy_static! {
+    static ref BLUE_NOISE: Vec<f32> = void_and_cluster(BLUE_NOISE_SIZE, 1.5);
+}
+
+// Mask value at the pixel, with the tile offset by `seed`
+fn blue_noise(pixel: (u32, u32), seed: u32) -> f32 {
+    let n = BLUE_NOISE_SIZE as u32;
+    let x = (pixel.0.wrapping_add(seed)) % n;
+    let y = (pixel.1.wrapping_add(seed >> 16)) % n;
+    BLUE_NOISE[(y * n + x) as usize]
+}
+
+// Tileable size x size blue-noise mask with values evenly spread over
+// [0, 1), built with Ulichney's void-and-cluster method
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+    // Gaussian splat for every toroidal offset
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+    let update = |energy: &mut [f32], at: usize, sign: f32| {
+        let (ax, ay) = (at % size, at / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - ax) % size;
+            let dy = (i / size + size - ay) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // Tightest cluster among the set points, largest void among the rest
+    let extreme = |energy: &[f32], set: &[bool], want: bool, largest: bool| {
+        (0..n)
+            .filter(|&i| set[i] == want)
+            .max_by(|&a, &b| {
+                let order = energy[a].total_cmp(&energy[b]);
+                if largest {
+                    order
+                } else {
+                    order.reverse()
+                }
+            })
+            .unwrap()
+    };
+
+    // Initial pattern: a tenth of the points, evened out by moving the
+    // tightest cluster into the largest void until that changes nothing
+    let mut set = vec![false; n];
+    let mut energy = vec![0.; n];
+    let mut state = 0x2545f491u32;
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        state = hash(state);
+        let i = state as usize % n;
+        if !set[i] {
+            set[i] = true;
+            update(&mut energy, i, 1.);
+            placed += 1;
+        }
+    }
+    for _ in 0..n {
+        let cluster = extreme(&energy, &set, true, true);
+        set[cluster] = false;
+        update(&mut energy, cluster, -1.);
+        let void = extreme(&energy, &set, false, false);
+        set[void] = true;
+        update(&mut energy, void, 1.);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+    // Rank the initial points by removing the tightest clusters first
+    let (mut proto_set, mut proto_energy) = (set.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = extreme(&proto_energy, &proto_set, true, true);
+        proto_set[cluster] = false;
+        update(&mut proto_energy, cluster, -1.);
+        rank[cluster] = r;
+    }
+    // Then the rest by filling the largest voids
+    for r in initial..n {
+        let void = extreme(&energy, &set, false, false);
+        set[void] = true;
+        update(&mut energy, void, 1.);
+        rank[void] = r;
+    }
+
+    rank.into_iter().map(|r| (r as f32 + 0.5) / n as f32).collect()
+}
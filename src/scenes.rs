@@ -0,0 +1,160 @@
+/*
+// Description: This file holds the scenes the renderer can draw, picked by
+//              number in scene_driver: spheres with an emissive sphere and
+//              rect light (0), a sunlit landscape (1), a mosaic of
+//              quadrilaterals under a wall light (2) and spheres on a floor
+//              lit only by point, spot and directional lights (3). The
+//              mosaic's random colors come from random_double, so they repeat
+//              when a seed is set.
+*/
+
+use std::sync::Arc;
+
+use crate::{hittable_list::*, light::*, material::*, rect::*, sphere::*, utils::*, vec3::*};
+
+/// Number of scenes `scene_driver` knows.
+pub const SCENE_COUNT: i32 = 4;
+
+pub fn scene_driver(select: i32) -> (HittableList, LightList) {
+    let mut objects = HittableList::new();
+    let mut lights = LightList::new();
+    // Numbers the materials for the material id AOV
+    let mut materials = MaterialList::new();
+    
+    //create matte colors and light source
+    let light = materials.add(Arc::new(DiffuseLight::from_color(Color::from(2.5, 2.5, 2.5))));
+    //let light = Arc::new(DiffuseLight::from_color(Color::from(20., 20., 20.)));
+    let red = materials.add(Arc::new(Lambertian::from(Color::from(0.65, 0.05, 0.05))));
+    let white = materials.add(Arc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73))));
+    let green = materials.add(Arc::new(Lambertian::from(Color::from(0.12, 0.45, 0.15))));
+    let yellow = materials.add(Arc::new(Lambertian::from(Color::from(0.65, 0.65, 0.05))));
+    let blue = materials.add(Arc::new(Lambertian::from(Color::from(0.05, 0.05, 0.65))));
+    let skyblue = materials.add(Arc::new(Lambertian::from(Color::from(0.53, 0.80, 0.92))));
+    let brown = materials.add(Arc::new(Lambertian::from(Color::from(0.47, 0.20, 0.08))));
+    let darkbrown = materials.add(Arc::new(Lambertian::from(Color::from(0.345, 0.17, 0.08))));
+    
+    
+    if select == 0 {
+
+        objects.add(Arc::new(Sphere::new(
+            Point3::from(4., -0.5, 0.),
+            1.,
+            white.clone(),
+        )));
+        objects.add(Arc::new(Sphere::new(
+            Point3::from(0., 2., 0.),
+            2.,
+            green.clone(),
+        )));
+        objects.add(Arc::new(Sphere::new(
+            Point3::from(-3., 1., -1.5),
+            1.,
+            white.clone(),
+        )));
+        objects.add(Arc::new(Sphere::new(
+            Point3::from(3., -1., -1.2),
+            1.2,
+            yellow.clone(),
+        )));
+
+        let difflight = materials.add(Arc::new(DiffuseLight::from_color(Color::from(7., 7., 7.))));
+        objects.add(Arc::new(Sphere::new(
+            Point3::from(-2., -1., -3.),
+            1.2,
+            difflight.clone()
+        )));
+            
+        objects.add(Arc::new(YzRect::from(-1.5, -0.5, 1., 4., -5., light.clone())));
+        objects.add(Arc::new(YzRect::from(0., 10., 0., 10., 0., red.clone())));
+        objects.add(Arc::new(XyRect::from(-10., 4., -2.5, 2.5, -3.75, blue.clone())));
+
+    }
+    else if select == 1 {
+        let brightlight = materials.add(Arc::new(DiffuseLight::from_color(Color::from(20., 20., 20.))));
+        objects.add(Arc::new(YzRect::from(-5., 5., -5., 5., -16., brightlight.clone())));
+
+        objects.add(Arc::new(YzRect::from(0., 10., -10., 10., 0., skyblue.clone())));
+        objects.add(Arc::new(YzRect::from(-10., 0., -10., 10., -1., brown.clone())));
+        
+        objects.add(Arc::new(YzRect::from(-0.5, 1.5, -0.3, 0.3, -5., darkbrown.clone())));
+        objects.add(Arc::new(Sphere::new(Point3::from(-5., 1.8, 0.), 0.6, green.clone())));
+        objects.add(Arc::new(Sphere::new(Point3::from(1., 0., -4.), 2., yellow.clone())));
+    }
+    else if select == 2 {
+        objects.add(Arc::new(YzRect::from(-10., 10., -10., 10., -15., light.clone())));
+        
+        let mut randlist = Vec::new();
+        for _ in 0..100 {
+            randlist.push(materials.add(Arc::new(Lambertian::from(Color::from(random_double(0.10, 0.95), random_double(0.0, 1.0), random_double(0.0, 1.0))))));
+        }
+        
+        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.2, 0.4), (0.4, 0.6), (0.2, -0.2), -8., red.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.2, 0.4), (-0.6, 0.3), (-0.9, -0.2), -8., blue.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-1.2, -0.3), (-1.1, 0.3), (-0.6, 0.3), (-0.9, -0.2), -8., green.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.9, -0.2), (-1.2, -0.3), (-1.5, -0.9), -8., brown.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-1.5, -0.9), (-0.8, -1.25), (-0.6, -0.6), -8., skyblue.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.8, -0.4), (-0.6, -0.6), (0., -0.4), (0.2, -0.2), -8., yellow.clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.6, -0.6), (-0.8, -1.25), (-0.1, -0.6), (0., -0.4), -8., randlist[0].clone())));
+        objects.add(Arc::new(Quadrilateral::from((0., -0.4), (-0.1, -0.6), (0.7, -1.0), (0.2, -0.2), -8., randlist[1].clone())));
+        objects.add(Arc::new(Quadrilateral::from((0.4, 0.6), (0.8, 0.7), (0.6, 0.), (0.2, -0.2), -8., randlist[2].clone())));
+        objects.add(Arc::new(Quadrilateral::from((0.2, -0.2), (0.6, 0.), (1.2, -0.3), (0.7, -1.0), -8., randlist[3].clone())));
+        objects.add(Arc::new(Quadrilateral::from((1.2, 0.3), (1.4, 0.8), (0.8, 0.7), (0.6, 0.),  -8., randlist[4].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.1, -0.6), (0.7, -1.0), (0.4, -1.25), (-0.8, -1.25),  -8., randlist[5].clone())));
+        objects.add(Arc::new(Quadrilateral::from((1.2, -0.3), (0.6, 0.), (1.2, 0.3), (1.6, 0.4),  -8., randlist[6].clone())));
+        objects.add(Arc::new(Quadrilateral::from((1.2, 0.3), (1.4, 0.8), (1.5, 1.25), (1.6, 0.4),  -8., randlist[7].clone()))); //top point
+
+        //Sorted after this point; order:(top left, top right, botom right, bottom left)
+        objects.add(Arc::new(Quadrilateral::from((1.5, 1.25), (1.8, 1.25), (2.0, 0.5), (1.6, 0.4),  -8., randlist[8].clone())));
+        objects.add(Arc::new(Quadrilateral::from((1.8, 1.25), (2.25, 1.25), (2.25, 0.4), (2.0, 0.5),  -8., randlist[9].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((1.6, 0.4), (2.0, 0.5), (2.25, 0.4), (2.25, 0.1),  -8., randlist[10].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((1.6, 0.4), (2.25, 0.1), (2.25, -0.6), (1.2, -0.3),  -8., randlist[11].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((1.2, -0.3), (2.25, -0.6), (1.8, -0.8), (0.7, -1.0),  -8., randlist[12].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((1.8, -0.8), (2.25, -0.6), (2.25, -1.25), (1.6, -1.1),  -8., randlist[13].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((0.7, -1.0), (1.8, -0.8), (1.6, -1.1), (0.4, -1.25),  -8., randlist[14].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((1.6, -1.1), (1.6, -1.1), (2.25, -1.25), (0.4, -1.25),  -8., randlist[15].clone()))); //end of the bottom closure
+
+        objects.add(Arc::new(Quadrilateral::from((1.3, 1.25), (1.5, 1.25), (1.4, 0.8), (0.8, 0.7),  -8., randlist[16].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((0.9, 1.1), (1.3, 1.25), (0.8, 0.7), (0.4, 0.6), -8., randlist[17].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((0.7, 1.25), (1.3, 1.25), (0.9, 1.1), (0.3, 0.8), -8., randlist[18].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((0.3, 0.8), (1.3, 1.25), (0.4, 0.6), (-0.2, 0.4), -8., randlist[19].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((0.1, 1.25), (0.7, 1.25), (0.3, 0.8),(-0.5, 0.9), -8., randlist[20].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((-0.5, 0.9), (0.3, 0.8), (-0.2, 0.4), (-0.6, 0.3), -8., randlist[21].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-0.4, 1.25), (0.1, 1.25), (-0.5, 0.9), (-0.8, 0.8), -8., randlist[22].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((-0.9, 1.25), (-0.4, 1.25), (-0.8, 0.8), (-1.1, 0.9), -8., randlist[23].clone()))); 
+        objects.add(Arc::new(Quadrilateral::from((-1.1, 0.9), (-0.8, 0.8), (-0.8, 0.8), (-1.1, 0.3), -8., randlist[99].clone()))); //triangle repair
+        objects.add(Arc::new(Quadrilateral::from((-0.8, 0.8), (-0.5, 0.9), (-0.6, 0.3), (-1.1, 0.3), -8., randlist[24].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-1.9, 0.9), (-1.1, 0.9), (-1.1, 0.3), (-1.7, 0.5), -8., randlist[25].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-1.5, 1.25), (-0.9, 1.25), (-1.1, 0.9), (-1.9, 0.9), -8., randlist[26].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-2.25, 1.25), (-1.5, 1.25), (-1.9, 0.9), (-2.25, 0.8), -8., randlist[27].clone()))); //top left
+        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.8), (-1.9, 0.9), (-1.7, 0.5), (-2.25, 0.1), -8., randlist[28].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.1), (-1.7, 0.5), (-1.1, 0.3), (-1.9, -0.1), -8., randlist[29].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-1.9, -0.1), (-1.1, 0.3), (-1.2, -0.3), (-1.8, -0.4), -8., randlist[30].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-1.8, -0.4), (-1.2, -0.3), (-1.5, -0.9), (-2.0, -0.8), -8., randlist[31].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-2.0, -0.8), (-1.5, -0.9), (-0.8, -1.25), (-1.7, -1.25), -8., randlist[32].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-2.25, -0.7), (-2.0, -0.8), (-1.7, -1.25), (-2.25, -1.25), -8., randlist[33].clone()))); //bottom left
+        objects.add(Arc::new(Quadrilateral::from((-2.25, -0.4), (-1.8, -0.4), (-2.0, -0.8), (-2.25, -0.7), -8., randlist[34].clone())));
+        objects.add(Arc::new(Quadrilateral::from((-2.25, 0.1), (-1.9, -0.1), (-1.8, -0.4), (-2.25, -0.4), -8., randlist[35].clone())));
+        
+
+    }
+    else if select == 3 {
+        //lit only by delta lights
+        objects.add(Arc::new(XzRect::from(-10., 10., -10., 10., -2., white.clone())));
+        objects.add(Arc::new(Sphere::new(Point3::from(0., -1., 0.), 1., green.clone())));
+        objects.add(Arc::new(Sphere::new(Point3::from(1., -1.4, -2.), 0.6, red.clone())));
+        objects.add(Arc::new(Sphere::new(Point3::from(1., -1.4, 2.), 0.6, blue.clone())));
+
+        lights.add(Arc::new(PointLight::from(Point3::from(-4., 3., 3.), Color::from(15., 15., 15.))));
+        lights.add(Arc::new(SpotLight::from(
+            Point3::from(-2., 5., -3.),
+            Point3::from(1., -2., -2.),
+            Color::from(60., 55., 40.),
+            10.,
+            20.,
+        )));
+        lights.add(Arc::new(DirectionalLight::from(Vec3::from(1., -1., 0.), Color::from(0.2, 0.2, 0.3))));
+    }
+
+
+        (objects, lights)
+}
@@ -4,12 +4,13 @@
 //              with a center, radius, and a material. It is designed to be hittable by rays.
 //              The get_sphere_uv method defines the spherical coordinates (u, v) for a point
 //              on the sphere’s surface, which are used for texture mapping (mapping a 3d
-//              position to a 2d texture coordinate). The hit method determines if a ray
+//              position to a 2d texture coordinate). get_sphere_tangents gives
+//              the derivatives of the surface point along u and v. The hit method determines if a ray
 //              intersects the sphere, calculates the intersection point, surface normal,
 //              and updates the hit record with these details. The bounding box method provides
 //              the axis-aligned bounding box, for the sphere.
 */
-use crate::{aabb::*, material::*, hittable::*, ray::*, vec3::*};
+use crate::{aabb::*, material::*, hittable::*, ray::*, stats::*, vec3::*};
 use std::f32::consts::PI;
 use std::sync::Arc;
 
@@ -28,33 +29,56 @@ impl Sphere {
     }
 }
 pub fn get_sphere_uv(p: &Point3, u: &mut f32, v: &mut f32) {
-    let theta = p.inv().y().acos();
+    let theta = p.inv().y().clamp(-1., 1.).acos();
     let phi = p.inv().z().atan2(p.x()) + PI;
 
     *u = phi / (2. * PI);
     *v = theta / PI;
 }
 
+// Derivatives of the surface point with respect to u and v, for a point with
+// unit normal `n` on a sphere of the given radius
+pub fn get_sphere_tangents(n: &Vec3, radius: f32) -> (Vec3, Vec3) {
+    let sin_theta = (1. - n.y() * n.y()).max(0.).sqrt().max(1e-6);
+    let dpdu = Vec3::from(n.z(), 0., -n.x()) * (2. * PI * radius);
+    let dpdv = Vec3::from(
+        -n.x() * n.y() / sin_theta,
+        sin_theta,
+        -n.y() * n.z() / sin_theta,
+    ) * (PI * radius);
+    (dpdu, dpdv)
+}
+
 impl Hittable for Sphere {
     fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+        count_intersection_test(Primitive::Sphere);
         let oc = r.origin() - self.center;
         let a = r.direction().len_squared();
         let half_b = dot(oc, r.direction());
         let c = oc.len_squared() - self.radius * self.radius;
         //let mut front_face = false;
 
-        let discriminant = half_b * half_b - a * c;
+        // half_b^2 - a*c taken from the ray's closest approach to the center,
+        // which keeps its precision for small or far away spheres
+        let closest = oc - r.direction() * (half_b / a);
+        let discriminant = a * (self.radius * self.radius - closest.len_squared());
 
         if discriminant < 0. {
             return false;
         }
 
         let sqrtd = discriminant.sqrt();
+        let q = -half_b - sqrtd.copysign(half_b);
+        if q == 0. {
+            return false;
+        }
 
-        // Find the nearest root that lies in the acceptable range
-        let mut root = (-half_b - sqrtd) / a;
+        // Find the nearest root that lies in the acceptable range, both
+        // computed without subtracting nearly equal numbers
+        let (near, far) = if c / q < q / a { (c / q, q / a) } else { (q / a, c / q) };
+        let mut root = near;
         if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
+            root = far;
             if root < t_min || t_max < root {
                 return false;
             }
@@ -65,12 +89,14 @@ impl Hittable for Sphere {
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(*r, outward_normal);
         get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = get_sphere_tangents(&outward_normal, self.radius);
         rec.mat_ptr = &*self.mat_ptr;
+        count_intersection_hit(Primitive::Sphere);
 
         true
     }
 
-    fn bounding_box<'a>(&'a self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
+    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut Bb) -> bool {
         *output_box = Bb::from(
             &(self.center - Vec3::from(self.radius, self.radius, self.radius)),
             &(self.center + Vec3::from(self.radius, self.radius, self.radius)),
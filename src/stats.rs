@@ -0,0 +1,273 @@
+/*
+// Description: This file collects render statistics: primary, secondary
+//              (scattered) and shadow rays, intersection tests against hits
+//              for each primitive type, objects visited by hittablelist
+//              hits, path lengths in bounces, and samples taken on each
+//              thread. There is no bounding volume hierarchy yet, so a ray
+//              visits every object of the lists it is traced against. Every thread counts
+//              into its own set of counters, registered the first time it
+//              counts anything, so rendering threads never wait on each
+//              other; collect_stats adds them up into a renderstats report
+//              that prints as a summary table or is written out as JSON.
+*/
+
+use std::fmt;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    /// Leaves the camera
+    Primary,
+    /// Scattered off a surface or a medium
+    Secondary,
+    /// Checks whether a light is visible
+    Shadow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Sphere,
+    XyRect,
+    XzRect,
+    YzRect,
+    Quadrilateral,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 5] = [
+        Primitive::Sphere,
+        Primitive::XyRect,
+        Primitive::XzRect,
+        Primitive::YzRect,
+        Primitive::Quadrilateral,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Sphere => "sphere",
+            Primitive::XyRect => "xy_rect",
+            Primitive::XzRect => "xz_rect",
+            Primitive::YzRect => "yz_rect",
+            Primitive::Quadrilateral => "quadrilateral",
+        }
+    }
+}
+
+const PRIMITIVES: usize = Primitive::ALL.len();
+
+// Counters of one thread. Only the owning thread writes them, so plain
+// loads and stores are enough, readers just see a slightly old value.
+#[derive(Default)]
+struct ThreadCounters {
+    rays: [AtomicU64; 3],
+    tests: [AtomicU64; PRIMITIVES],
+    hits: [AtomicU64; PRIMITIVES],
+    object_visits: AtomicU64,
+    paths: AtomicU64,
+    bounces: AtomicU64,
+    samples: AtomicU64,
+}
+
+impl ThreadCounters {
+    fn counters(&self) -> impl Iterator<Item = &AtomicU64> {
+        self.rays
+            .iter()
+            .chain(&self.tests)
+            .chain(&self.hits)
+            .chain([&self.object_visits, &self.paths, &self.bounces, &self.samples])
+    }
+}
+
+static REGISTRY: Mutex<Vec<Arc<ThreadCounters>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static COUNTERS: Arc<ThreadCounters> = {
+        let counters = Arc::new(ThreadCounters::default());
+        REGISTRY.lock().unwrap().push(Arc::clone(&counters));
+        counters
+    };
+}
+
+fn bump(counter: impl FnOnce(&ThreadCounters) -> &AtomicU64, n: u64) {
+    COUNTERS.with(|counters| {
+        let c = counter(counters);
+        c.store(c.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+    });
+}
+
+pub fn count_ray(kind: RayKind) {
+    bump(|c| &c.rays[kind as usize], 1);
+}
+pub fn count_intersection_test(primitive: Primitive) {
+    bump(|c| &c.tests[primitive as usize], 1);
+}
+pub fn count_intersection_hit(primitive: Primitive) {
+    bump(|c| &c.hits[primitive as usize], 1);
+}
+/// Counts `n` objects of a hittablelist tried against one ray.
+pub fn count_object_visits(n: u64) {
+    bump(|c| &c.object_visits, n);
+}
+/// Counts a finished camera path that hit `bounces` surfaces.
+pub fn count_path(bounces: u64) {
+    bump(|c| &c.paths, 1);
+    bump(|c| &c.bounces, bounces);
+}
+pub fn count_samples(n: u64) {
+    bump(|c| &c.samples, n);
+}
+
+/// Zeroes the counters of every thread, to start a new report.
+pub fn reset_stats() {
+    for counters in REGISTRY.lock().unwrap().iter() {
+        for c in counters.counters() {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PrimitiveStats {
+    pub tests: u64,
+    pub hits: u64,
+}
+
+/// Totals of the counters over every thread, for a render that took
+/// `elapsed`.
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    pub elapsed: Duration,
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    pub shadow_rays: u64,
+    /// Tests and hits of each primitive, in the order of `Primitive::ALL`
+    pub primitives: Vec<PrimitiveStats>,
+    /// Objects tried by hittablelist hits
+    pub object_visits: u64,
+    pub paths: u64,
+    pub bounces: u64,
+    /// Samples taken by each thread that took any
+    pub thread_samples: Vec<u64>,
+}
+
+/// Adds up the counters of every thread.
+pub fn collect_stats(elapsed: Duration) -> RenderStats {
+    let registry = REGISTRY.lock().unwrap();
+    let total = |counter: &dyn Fn(&ThreadCounters) -> &AtomicU64| -> u64 {
+        registry.iter().map(|c| counter(c).load(Ordering::Relaxed)).sum()
+    };
+    RenderStats {
+        elapsed,
+        primary_rays: total(&|c| &c.rays[RayKind::Primary as usize]),
+        secondary_rays: total(&|c| &c.rays[RayKind::Secondary as usize]),
+        shadow_rays: total(&|c| &c.rays[RayKind::Shadow as usize]),
+        primitives: (0..PRIMITIVES)
+            .map(|i| PrimitiveStats {
+                tests: total(&|c| &c.tests[i]),
+                hits: total(&|c| &c.hits[i]),
+            })
+            .collect(),
+        object_visits: total(&|c| &c.object_visits),
+        paths: total(&|c| &c.paths),
+        bounces: total(&|c| &c.bounces),
+        thread_samples: registry
+            .iter()
+            .map(|c| c.samples.load(Ordering::Relaxed))
+            .filter(|&n| n > 0)
+            .collect(),
+    }
+}
+
+impl RenderStats {
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+    pub fn samples(&self) -> u64 {
+        self.thread_samples.iter().sum()
+    }
+    /// Surfaces hit per camera path.
+    pub fn average_path_length(&self) -> f64 {
+        self.bounces as f64 / self.paths.max(1) as f64
+    }
+    fn per_second(&self, n: u64) -> f64 {
+        n as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn to_json(&self) -> String {
+        let primitives: Vec<String> = Primitive::ALL
+            .iter()
+            .zip(&self.primitives)
+            .map(|(p, s)| format!("\"{}\": {{\"tests\": {}, \"hits\": {}}}", p.name(), s.tests, s.hits))
+            .collect();
+        let threads: Vec<String> = self
+            .thread_samples
+            .iter()
+            .map(|&n| format!("{{\"samples\": {}, \"samples_per_second\": {:.1}}}", n, self.per_second(n)))
+            .collect();
+        format!(
+            "{{\n  \"elapsed_seconds\": {:.3},\n  \"rays\": {{\"primary\": {}, \"secondary\": {}, \"shadow\": {}, \"total\": {}, \"per_second\": {:.1}}},\n  \"intersections\": {{{}}},\n  \"object_visits\": {},\n  \"paths\": {},\n  \"average_path_length\": {:.4},\n  \"samples\": {},\n  \"samples_per_second\": {:.1},\n  \"threads\": [{}]\n}}\n",
+            self.elapsed.as_secs_f64(),
+            self.primary_rays,
+            self.secondary_rays,
+            self.shadow_rays,
+            self.rays(),
+            self.per_second(self.rays()),
+            primitives.join(", "),
+            self.object_visits,
+            self.paths,
+            self.average_path_length(),
+            self.samples(),
+            self.per_second(self.samples()),
+            threads.join(", "),
+        )
+    }
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_json())
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Render statistics ({:.2}s)", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "  Rays                 {:>14} ({:.2} M/s)",
+            self.rays(),
+            self.per_second(self.rays()) / 1e6
+        )?;
+        writeln!(f, "    primary            {:>14}", self.primary_rays)?;
+        writeln!(f, "    secondary          {:>14}", self.secondary_rays)?;
+        writeln!(f, "    shadow             {:>14}", self.shadow_rays)?;
+        writeln!(f, "  Average path length  {:>14.3} bounces", self.average_path_length())?;
+        writeln!(f, "  Intersections                 tests           hits  hit rate")?;
+        for (p, s) in Primitive::ALL.iter().zip(&self.primitives) {
+            if s.tests == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "    {:<18} {:>14} {:>14} {:>8.2}%",
+                p.name(),
+                s.tests,
+                s.hits,
+                100. * s.hits as f64 / s.tests as f64
+            )?;
+        }
+        writeln!(f, "  Objects visited      {:>14}", self.object_visits)?;
+        writeln!(
+            f,
+            "  Samples              {:>14} ({:.0}/s)",
+            self.samples(),
+            self.per_second(self.samples())
+        )?;
+        for (i, &n) in self.thread_samples.iter().enumerate() {
+            writeln!(f, "    thread {:<11} {:>14} ({:.0}/s)", i, n, self.per_second(n))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+/*
+// Description: This file provides a stereo camera rig for rendering left and
+//              right eye views in a single pass. Both eyes are laid out in one
+//              frame, side by side or over-under, and get_ray(s, t) picks the
+//              eye from where (s, t) falls, so the frame renders into a single
+//              canvas with the usual loop. Planar rigs hold two cameras split
+//              by the interocular distance, either parallel, toed-in to meet at
+//              the focus distance, or parallel with an off-axis frustum shift
+//              that puts zero parallax at the focus distance. The
+//              omnidirectional rig renders 360 degree equirectangular stereo,
+//              sending each ray from the point on the eye circle tangent to it.
+//              Both rigs take the height of one eye's image, which sets the
+//              width of each ray's cone for texture filtering.
+*/
+
+use crate::{camera::*, ray::*, sampler::*, utils::*, vec3::*};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Convergence {
+    /// Eyes look straight ahead, converging at infinity
+    Parallel,
+    /// Eyes rotate inwards to look at the point at the focus distance
+    ToeIn,
+    /// Parallel eyes with frustums shifted to converge at the focus distance
+    OffAxis,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye in the left half of the frame
+    SideBySide,
+    /// Left eye in the top half of the frame
+    OverUnder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
+enum Rig {
+    Planar {
+        left: Box<Camera>,
+        right: Box<Camera>,
+    },
+    Omnidirectional {
+        center: Point3,
+        u: Vec3,
+        v: Vec3,
+        w: Vec3,
+        radius: f32,
+        pixel_size: f32,
+        time0: f32,
+        time1: f32,
+    },
+}
+
+#[derive(Debug)]
+pub struct StereoCamera {
+    rig: Rig,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    /// Takes the same arguments as `Camera::new`, with `aspect_ratio` being
+    /// that of a single eye. `focus_dist` doubles as the convergence distance
+    /// and `eye_height` is the height in pixels of each eye's image.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
+        time0: f32,
+        time1: f32,
+        interocular: f32,
+        convergence: Convergence,
+        layout: StereoLayout,
+        eye_height: usize,
+    ) -> Self {
+        let w = unit_vector(lookfrom - lookat);
+        let u = unit_vector(cross(vup, w));
+        let half = u * (interocular / 2.);
+        let converge_at = lookfrom - w * focus_dist;
+
+        let eye = |offset: Vec3| {
+            let from = lookfrom + offset;
+            let at = match convergence {
+                Convergence::ToeIn => converge_at,
+                Convergence::Parallel | Convergence::OffAxis => lookat + offset,
+            };
+            let mut cam = Camera::new(
+                from,
+                at,
+                vup,
+                vfov,
+                aspect_ratio,
+                aperture,
+                focus_dist,
+                time0,
+                time1,
+            );
+            cam.set_image_height(eye_height);
+            cam
+        };
+        let mut left = eye(half.inv());
+        let mut right = eye(half);
+
+        if convergence == Convergence::OffAxis {
+            let frame_width =
+                2. * (degrees_to_radians(vfov) / 2.).tan() * aspect_ratio * focus_dist;
+            let shift = interocular / 2. / frame_width;
+            left.set_tilt_shift(0., 0., shift, 0.);
+            right.set_tilt_shift(0., 0., -shift, 0.);
+        }
+
+        Self {
+            rig: Rig::Planar {
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            layout,
+        }
+    }
+
+    /// 360 degree stereo panorama with eyes on a circle of diameter
+    /// `interocular` around `lookfrom`, each eye's image `eye_height` pixels
+    /// high.
+    #[allow(clippy::too_many_arguments)]
+    pub fn omnidirectional(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        interocular: f32,
+        time0: f32,
+        time1: f32,
+        layout: StereoLayout,
+        eye_height: usize,
+    ) -> Self {
+        let w = unit_vector(lookfrom - lookat);
+        let u = unit_vector(cross(vup, w));
+        let v = cross(w, u);
+        Self {
+            rig: Rig::Omnidirectional {
+                center: lookfrom,
+                u,
+                v,
+                w,
+                radius: interocular / 2.,
+                pixel_size: 1. / eye_height.max(1) as f32,
+                time0,
+                time1,
+            },
+            layout,
+        }
+    }
+
+    /// The two eye cameras of a planar rig, to set projection, aperture or
+    /// exposure on each.
+    pub fn eyes_mut(&mut self) -> Option<(&mut Camera, &mut Camera)> {
+        match &mut self.rig {
+            Rig::Planar { left, right } => Some((left, right)),
+            Rig::Omnidirectional { .. } => None,
+        }
+    }
+
+    /// Size of the combined frame for eyes of the given size.
+    pub fn frame_size(&self, eye_width: usize, eye_height: usize) -> (usize, usize) {
+        match self.layout {
+            StereoLayout::SideBySide => (2 * eye_width, eye_height),
+            StereoLayout::OverUnder => (eye_width, 2 * eye_height),
+        }
+    }
+
+    /// Which eye (s, t) in the combined frame belongs to, and the matching
+    /// coordinates within that eye's view.
+    pub fn split(&self, s: f32, t: f32) -> (Eye, f32, f32) {
+        match self.layout {
+            StereoLayout::SideBySide => {
+                if s < 0.5 {
+                    (Eye::Left, 2. * s, t)
+                } else {
+                    (Eye::Right, 2. * s - 1., t)
+                }
+            }
+            StereoLayout::OverUnder => {
+                if t >= 0.5 {
+                    (Eye::Left, s, 2. * t - 1.)
+                } else {
+                    (Eye::Right, s, 2. * t)
+                }
+            }
+        }
+    }
+
+    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
+        let (eye, s, t) = self.split(s, t);
+        match &self.rig {
+            Rig::Planar { left, right } => match eye {
+                Eye::Left => left.get_ray(s, t),
+                Eye::Right => right.get_ray(s, t),
+            },
+            Rig::Omnidirectional {
+                center,
+                u,
+                v,
+                w,
+                radius,
+                pixel_size,
+                time0,
+                time1,
+            } => {
+                let longitude = (s - 0.5) * 2. * PI;
+                let latitude = (t - 0.5) * PI;
+                let forward = *u * longitude.sin() - *w * longitude.cos();
+                let right = *u * longitude.cos() + *w * longitude.sin();
+                let direction = forward * latitude.cos() + *v * latitude.sin();
+                let side = match eye {
+                    Eye::Left => -1.,
+                    Eye::Right => 1.,
+                };
+
+                Ray::new(
+                    *center + right * (side * radius),
+                    direction,
+                    *time0 + sample_1d() * (*time1 - *time0),
+                )
+                .with_cone(0., PI * pixel_size)
+            }
+        }
+    }
+}
@@ -4,17 +4,35 @@
 //              a value method to give the color at a given point. The solid color texture returns a
 //              constant color, it provides constructors for creating colors from individual rgb values
 //              or a color object. The checkertexture generates a pattern which alternates based on 3d 
-//              position. The imagetexture loads in and uses an image as a texture, mapping them onto
-//              3d objects.
+//              position, or on u,v coordinates with a configurable frequency. The texture transform
+//              wraps any other texture to scale, offset, rotate and tile its u,v coordinates. The
+//              imagetexture loads in and uses an image as a texture, mapping them onto 3d objects.
+//              Images are decoded to linear color and kept as a mip chain so they can be sampled
+//              with nearest, bilinear or trilinear filtering under repeat/mirror/clamp wrapping.
+//              Trilinear lookups through filtered_value take the mip level from the footprint of
+//              the hit, the u,v area the ray's pixel covers there.
+//              Loading returns a TextureError on failure and understands grey, grey+alpha, rgb
+//              and rgba images in 8-bit, 16-bit or float formats, keeping alpha for cutouts.
+//              The noise based textures (noise, marble, wood and clouds) are procedural, built on
+//              the perlin generator, and take a scale for the pattern frequency and an octave count
+//              for how much fine detail is layered on top.
 */
 
-use crate::{utils::*, vec3::*};
-use stb_image::{image::load, image::LoadResult::*};
+use crate::{perlin::*, utils::*, vec3::*};
+use stb_image::{image::load, image::LoadResult::*, stb_image as stb};
+use std::ffi::CString;
+use std::fmt;
+use std::ops::{Add, Mul};
 use std::path::Path;
 use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
   fn value(&self, u: f32, v: f32, p: &Point3) -> Color;
+  /// Value averaged over `footprint` u,v units around (u, v), the point
+  /// value for textures that aren't filtered.
+  fn filtered_value(&self, u: f32, v: f32, p: &Point3, _footprint: f32) -> Color {
+    self.value(u, v, p)
+  }
 }
 #[derive(Clone)]
 pub struct SolidColor {
@@ -36,15 +54,31 @@ impl SolidColor {
   }
 }
 
+impl Default for SolidColor {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Texture for SolidColor {
   fn value(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
     self.color_value
   }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckerMode {
+  /// Checks from the sign of sin(f*x)*sin(f*y)*sin(f*z) at the world position
+  Solid,
+  /// Checks laid out in texture space, `frequency` squares per unit of u and v
+  Uv,
+}
+
 pub struct CheckerTexture {
   odd: Arc<dyn Texture>,
   even: Arc<dyn Texture>,
+  mode: CheckerMode,
+  frequency: f32,
 }
 
 impl CheckerTexture {
@@ -52,102 +86,719 @@ impl CheckerTexture {
     Self {
       odd: _odd,
       even: _even,
+      mode: CheckerMode::Solid,
+      frequency: 10.,
     }
   }
   pub fn from_colors(c1: Color, c2: Color) -> Self {
+    Self::from_texture(
+      Arc::new(SolidColor::from(c1)),
+      Arc::new(SolidColor::from(c2)),
+    )
+  }
+  pub fn from_texture_mode(
+    _even: Arc<dyn Texture>,
+    _odd: Arc<dyn Texture>,
+    mode: CheckerMode,
+    frequency: f32,
+  ) -> Self {
     Self {
-      even: Arc::new(SolidColor::from(c1)),
-      odd: Arc::new(SolidColor::from(c2)),
+      odd: _odd,
+      even: _even,
+      mode,
+      frequency,
+    }
+  }
+  pub fn from_colors_mode(c1: Color, c2: Color, mode: CheckerMode, frequency: f32) -> Self {
+    Self::from_texture_mode(
+      Arc::new(SolidColor::from(c1)),
+      Arc::new(SolidColor::from(c2)),
+      mode,
+      frequency,
+    )
+  }
+}
+
+impl CheckerTexture {
+  fn is_odd(&self, u: f32, v: f32, p: &Point3) -> bool {
+    match self.mode {
+      CheckerMode::Solid => {
+        let f = self.frequency;
+        (f * p.x()).sin() * (f * p.y()).sin() * (f * p.z()).sin() < 0.
+      }
+      CheckerMode::Uv => {
+        let iu = (u * self.frequency).floor() as i64;
+        let iv = (v * self.frequency).floor() as i64;
+        (iu + iv) % 2 != 0
+      }
     }
   }
 }
 
 impl Texture for CheckerTexture {
   fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
-    let sines = (10. * p.x()).sin() * (10. * p.y()).sin() * (10. * p.z()).sin();
-    if sines < 0. {
-      return self.odd.value(u, v, p);
+    if self.is_odd(u, v, p) {
+      self.odd.value(u, v, p)
     } else {
-      return self.even.value(u, v, p);
+      self.even.value(u, v, p)
+    }
+  }
+  fn filtered_value(&self, u: f32, v: f32, p: &Point3, footprint: f32) -> Color {
+    if self.is_odd(u, v, p) {
+      self.odd.filtered_value(u, v, p, footprint)
+    } else {
+      self.even.filtered_value(u, v, p, footprint)
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+  Repeat,
+  Mirror,
+  Clamp,
+}
+
+impl WrapMode {
+  /// Folds a texture coordinate back into [0, 1].
+  pub fn apply(self, x: f32) -> f32 {
+    match self {
+      WrapMode::Repeat => x - x.floor(),
+      WrapMode::Mirror => {
+        let m = x.rem_euclid(2.);
+        if m > 1. {
+          2. - m
+        } else {
+          m
+        }
+      }
+      WrapMode::Clamp => clamp(x, 0., 1.),
     }
   }
-}pub struct ImageTexture {
-  pub bytes_per_pixel: usize,
-  data: Vec<u8>,
+}
+
+/// Wraps another texture and remaps the u,v it is looked up with. The
+/// incoming coordinates are scaled (tiling), rotated about the origin by
+/// `rotation` degrees, shifted by `offset`, then wrapped back into [0, 1].
+pub struct TextureTransform {
+  texture: Arc<dyn Texture>,
+  scale: (f32, f32),
+  offset: (f32, f32),
+  sin_theta: f32,
+  cos_theta: f32,
+  wrap_u: WrapMode,
+  wrap_v: WrapMode,
+}
+
+impl TextureTransform {
+  pub fn from(
+    texture: Arc<dyn Texture>,
+    scale: (f32, f32),
+    offset: (f32, f32),
+    rotation: f32,
+    wrap_u: WrapMode,
+    wrap_v: WrapMode,
+  ) -> Self {
+    let radians = degrees_to_radians(rotation);
+    Self {
+      texture,
+      scale,
+      offset,
+      sin_theta: radians.sin(),
+      cos_theta: radians.cos(),
+      wrap_u,
+      wrap_v,
+    }
+  }
+  /// Repeats `texture` `tiles_u` by `tiles_v` times across the surface.
+  pub fn tiled(texture: Arc<dyn Texture>, tiles_u: f32, tiles_v: f32) -> Self {
+    Self::from(
+      texture,
+      (tiles_u, tiles_v),
+      (0., 0.),
+      0.,
+      WrapMode::Repeat,
+      WrapMode::Repeat,
+    )
+  }
+  pub fn transform_uv(&self, u: f32, v: f32) -> (f32, f32) {
+    let su = u * self.scale.0;
+    let sv = v * self.scale.1;
+    let ru = self.cos_theta * su - self.sin_theta * sv + self.offset.0;
+    let rv = self.sin_theta * su + self.cos_theta * sv + self.offset.1;
+    (self.wrap_u.apply(ru), self.wrap_v.apply(rv))
+  }
+}
+
+impl Texture for TextureTransform {
+  fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
+    let (tu, tv) = self.transform_uv(u, v);
+    self.texture.value(tu, tv, p)
+  }
+  fn filtered_value(&self, u: f32, v: f32, p: &Point3, footprint: f32) -> Color {
+    let (tu, tv) = self.transform_uv(u, v);
+    let stretch = self.scale.0.abs().max(self.scale.1.abs());
+    self.texture.filtered_value(tu, tv, p, footprint * stretch)
+  }
+}
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFilter {
+  Nearest,
+  Bilinear,
+  /// Bilinear lookups in the two mip levels around the footprint, blended
+  Trilinear,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+  /// 8-bit color images, decoded to linear when loaded
+  Srgb,
+  /// Data textures (normal maps, masks) that are used as stored
+  Linear,
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+/// What mip levels hold, colors for the image and single values for alpha.
+trait Texel: Copy + Default + Add<Output = Self> + Mul<f32, Output = Self> {}
+impl<T> Texel for T where T: Copy + Default + Add<Output = T> + Mul<f32, Output = T> {}
+
+struct MipLevel<T> {
   width: usize,
   height: usize,
-  bytes_per_scanline: usize,
+  texels: Vec<T>,
+}
+
+// Texels of a row of `n` under texel `i` of the row shrunk to `m`, with
+// the share of the smaller texel each one covers
+fn box_weights(i: usize, n: usize, m: usize) -> Vec<(usize, f32)> {
+  let ratio = n as f32 / m as f32;
+  let (start, end) = (i as f32 * ratio, (i + 1) as f32 * ratio);
+  (start.floor() as usize..(end.ceil() as usize).min(n))
+    .map(|k| {
+      let overlap = end.min(k as f32 + 1.) - start.max(k as f32);
+      (k, overlap / ratio)
+    })
+    .collect()
+}
+
+impl<T: Texel> MipLevel<T> {
+  // Box filters this level into the next smaller one. Odd sizes don't
+  // halve evenly, so each smaller texel takes in parts of three texels
+  // along that axis and the last row and column are kept.
+  fn downsample(&self) -> MipLevel<T> {
+    let width = (self.width / 2).max(1);
+    let height = (self.height / 2).max(1);
+    let mut texels = Vec::with_capacity(width * height);
+    for j in 0..height {
+      let rows = box_weights(j, self.height, height);
+      for i in 0..width {
+        let mut sum = T::default();
+        for &(x, wx) in &box_weights(i, self.width, width) {
+          for &(y, wy) in &rows {
+            sum = sum + self.texels[y * self.width + x] * (wx * wy);
+          }
+        }
+        texels.push(sum);
+      }
+    }
+    MipLevel {
+      width,
+      height,
+      texels,
+    }
+  }
+}
+
+fn wrap_index(mode: WrapMode, i: i64, n: usize) -> usize {
+  let n = n as i64;
+  let wrapped = match mode {
+    WrapMode::Repeat => i.rem_euclid(n),
+    WrapMode::Mirror => {
+      let m = i.rem_euclid(2 * n);
+      if m >= n {
+        2 * n - 1 - m
+      } else {
+        m
+      }
+    }
+    WrapMode::Clamp => i.clamp(0, n - 1),
+  };
+  wrapped as usize
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+  NotFound(String),
+  Decode { path: String, reason: String },
+  UnsupportedChannels { path: String, channels: usize },
+}
+
+impl fmt::Display for TextureError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TextureError::NotFound(path) => write!(f, "texture file `{}` doesn't exist", path),
+      TextureError::Decode { path, reason } => {
+        write!(f, "could not decode texture `{}`: {}", path, reason)
+      }
+      TextureError::UnsupportedChannels { path, channels } => write!(
+        f,
+        "texture `{}` has {} channels, expected 1 to 4",
+        path, channels
+      ),
+    }
+  }
+}
+
+impl std::error::Error for TextureError {}
+
+// Decoded pixels before they are split into color and alpha. Values are
+// normalized to [0, 1] for integer formats and left as stored for float ones.
+struct RawImage {
+  width: usize,
+  height: usize,
+  channels: usize,
+  data: Vec<f32>,
+  is_float: bool,
+}
+
+fn load_raw(filename: &str) -> Result<RawImage, TextureError> {
+  if let Some(raw) = load_raw_16(filename) {
+    return raw;
+  }
+
+  match load(filename) {
+    ImageU8(img) => Ok(RawImage {
+      width: img.width,
+      height: img.height,
+      channels: img.depth,
+      data: img.data.iter().map(|&b| b as f32 / 255.).collect(),
+      is_float: false,
+    }),
+    ImageF32(img) => Ok(RawImage {
+      width: img.width,
+      height: img.height,
+      channels: img.depth,
+      data: img.data,
+      is_float: true,
+    }),
+    Error(reason) => Err(TextureError::Decode {
+      path: filename.to_string(),
+      reason,
+    }),
+  }
+}
+
+// The safe stb_image wrapper narrows 16-bit images to 8 bits, so those go
+// through the 16-bit entry point directly.
+fn load_raw_16(filename: &str) -> Option<Result<RawImage, TextureError>> {
+  let path = CString::new(filename).ok()?;
+  let (mut w, mut h, mut c) = (0, 0, 0);
+  unsafe {
+    if stb::stbi_is_16_bit(path.as_ptr()) == 0 {
+      return None;
+    }
+    let buffer = stb::stbi_load_16(path.as_ptr(), &mut w, &mut h, &mut c, 0);
+    if buffer.is_null() {
+      return None;
+    }
+    let (width, height, channels) = (w.max(0) as usize, h.max(0) as usize, c.max(0) as usize);
+    let len = width
+      .checked_mul(height)
+      .and_then(|n| n.checked_mul(channels));
+    let raw = match len {
+      Some(len) => Ok(RawImage {
+        width,
+        height,
+        channels,
+        data: std::slice::from_raw_parts(buffer, len)
+          .iter()
+          .map(|&s| s as f32 / 65535.)
+          .collect(),
+        is_float: false,
+      }),
+      None => Err(TextureError::Decode {
+        path: filename.to_string(),
+        reason: format!("{}x{} image with {} channels is too large", width, height, channels),
+      }),
+    };
+    stb::stbi_image_free(buffer as *mut std::os::raw::c_void);
+    Some(raw)
+  }
+}
+
+fn build_mip_chain<T: Texel>(width: usize, height: usize, texels: Vec<T>) -> Vec<MipLevel<T>> {
+  let mut levels = vec![];
+  let mut level = MipLevel {
+    width,
+    height,
+    texels,
+  };
+  while level.width > 1 || level.height > 1 {
+    let next = level.downsample();
+    levels.push(level);
+    level = next;
+  }
+  levels.push(level);
+  levels
+}
+
+/// Texels are stored as linear colors in a mip chain, level 0 being the full
+/// resolution image, with a matching chain for alpha when the image has one.
+/// Lookups through `filtered_value` pick the mip level from the footprint
+/// of the hit when filtering is trilinear, `value` always reads level 0.
+pub struct ImageTexture {
+  pub channels: usize,
+  levels: Vec<MipLevel<Color>>,
+  alpha_levels: Vec<MipLevel<f32>>,
+  pub filter: TextureFilter,
+  pub wrap_u: WrapMode,
+  pub wrap_v: WrapMode,
 }
 
 impl ImageTexture {
   pub fn new() -> Self {
     Self {
-      data: vec![],
-      bytes_per_pixel: 3,
-      width: 0,
-      height: 0,
-      bytes_per_scanline: 0,
+      levels: vec![],
+      alpha_levels: vec![],
+      channels: 3,
+      filter: TextureFilter::Bilinear,
+      wrap_u: WrapMode::Clamp,
+      wrap_v: WrapMode::Clamp,
     }
   }
-  pub fn from(filename: &str) -> Self {
-    let bytes_per_pixel = 3;
-
+  /// Loads an sRGB encoded color image.
+  pub fn from(filename: &str) -> Result<Self, TextureError> {
+    Self::from_color_space(filename, ColorSpace::Srgb)
+  }
+  /// Loads a 1 (grey), 2 (grey, alpha), 3 (rgb) or 4 (rgba) channel image in
+  /// 8-bit, 16-bit or float format. Float images are taken as linear whatever
+  /// the color space, and alpha is never gamma decoded.
+  pub fn from_color_space(filename: &str, color_space: ColorSpace) -> Result<Self, TextureError> {
     if !Path::new(filename).exists() {
-      eprintln!("ERROR: file `{}` doesn't exist !", filename);
-      return Self {
-        bytes_per_pixel,
-        data: vec![],
-        width: 0,
-        height: 0,
-        bytes_per_scanline: 0,
-      };
+      return Err(TextureError::NotFound(filename.to_string()));
+    }
+
+    let raw = load_raw(filename)?;
+    let channels = raw.channels;
+    if !(1..=4).contains(&channels) {
+      return Err(TextureError::UnsupportedChannels {
+        path: filename.to_string(),
+        channels,
+      });
+    }
+    let len = raw.width.checked_mul(raw.height).and_then(|n| n.checked_mul(channels));
+    if raw.width == 0 || raw.height == 0 || len.is_none_or(|len| raw.data.len() < len) {
+      return Err(TextureError::Decode {
+        path: filename.to_string(),
+        reason: "image has no pixel data".to_string(),
+      });
     }
 
-    let (width, height, data) = match load(filename) {
-      ImageU8(v) => (v.width, v.height, v.data),
-      _ => {
-        eprintln!("ERROR: Could not load texture image file {}", filename);
-        (0, 0, vec![])
+    let decode = |c: f32| {
+      if raw.is_float || color_space == ColorSpace::Linear {
+        c
+      } else {
+        srgb_to_linear(c)
       }
     };
-    let bytes_per_scanline = bytes_per_pixel * width;
-    Self {
-      bytes_per_pixel,
-      data,
-      width,
-      height,
-      bytes_per_scanline,
+    let has_alpha = channels == 2 || channels == 4;
+
+    let mut texels = Vec::with_capacity(raw.width * raw.height);
+    let mut alpha = vec![];
+    for px in raw.data.chunks_exact(channels) {
+      let color = if channels < 3 {
+        let grey = decode(px[0]);
+        Color::from(grey, grey, grey)
+      } else {
+        Color::from(decode(px[0]), decode(px[1]), decode(px[2]))
+      };
+      texels.push(color);
+      if has_alpha {
+        alpha.push(px[channels - 1]);
+      }
+    }
+
+    let mut tex = Self::from_texels(raw.width, raw.height, texels);
+    tex.channels = channels;
+    if has_alpha {
+      tex.set_alpha(alpha);
+    }
+    Ok(tex)
+  }
+  /// Builds a texture from linear colors, stored top row first.
+  pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Self {
+    let mut tex = Self::new();
+    if width == 0 || height == 0 || texels.len() < width * height {
+      return tex;
+    }
+    tex.levels = build_mip_chain(width, height, texels);
+    tex
+  }
+  /// Attaches an alpha channel with one value per texel, top row first.
+  pub fn set_alpha(&mut self, alpha: Vec<f32>) {
+    let (width, height) = self.dimensions();
+    if width == 0 || alpha.len() < width * height {
+      return;
+    }
+    self.alpha_levels = build_mip_chain(width, height, alpha);
+  }
+  pub fn has_alpha(&self) -> bool {
+    !self.alpha_levels.is_empty()
+  }
+
+  pub fn dimensions(&self) -> (usize, usize) {
+    self
+      .levels
+      .first()
+      .map_or((0, 0), |l| (l.width, l.height))
+  }
+  pub fn mip_levels(&self) -> usize {
+    self.levels.len()
+  }
+
+  fn texel<T: Texel>(&self, level: &MipLevel<T>, i: i64, j: i64) -> T {
+    let x = wrap_index(self.wrap_u, i, level.width);
+    let y = wrap_index(self.wrap_v, j, level.height);
+    level.texels[y * level.width + x]
+  }
+
+  fn nearest<T: Texel>(&self, level: &MipLevel<T>, u: f32, v: f32) -> T {
+    let i = (u * level.width as f32).floor() as i64;
+    let j = ((1. - v) * level.height as f32).floor() as i64;
+    self.texel(level, i, j)
+  }
+
+  fn bilinear<T: Texel>(&self, level: &MipLevel<T>, u: f32, v: f32) -> T {
+    // Texel centers sit at half integer coordinates
+    let x = u * level.width as f32 - 0.5;
+    let y = (1. - v) * level.height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (i, j) = (x0 as i64, y0 as i64);
+
+    let top = lerp(self.texel(level, i, j), self.texel(level, i + 1, j), fx);
+    let bottom = lerp(
+      self.texel(level, i, j + 1),
+      self.texel(level, i + 1, j + 1),
+      fx,
+    );
+    lerp(top, bottom, fy)
+  }
+
+  fn filtered<T: Texel>(&self, levels: &[MipLevel<T>], u: f32, v: f32, footprint: f32) -> T {
+    if levels.is_empty() {
+      return T::default();
+    }
+
+    match self.filter {
+      TextureFilter::Nearest => self.nearest(&levels[0], u, v),
+      TextureFilter::Bilinear => self.bilinear(&levels[0], u, v),
+      TextureFilter::Trilinear => {
+        let texels = footprint * levels[0].width.max(levels[0].height) as f32;
+        let max_lod = (levels.len() - 1) as f32;
+        let lod = if texels > 1. {
+          clamp(texels.log2(), 0., max_lod)
+        } else {
+          0.
+        };
+        let lo = lod.floor() as usize;
+        let hi = (lo + 1).min(levels.len() - 1);
+        lerp(
+          self.bilinear(&levels[lo], u, v),
+          self.bilinear(&levels[hi], u, v),
+          lod - lo as f32,
+        )
+      }
     }
   }
+
+  /// Looks up the texture covering `footprint` u,v units around (u, v).
+  pub fn sample(&self, u: f32, v: f32, footprint: f32) -> Color {
+    self.filtered(&self.levels, u, v, footprint)
+  }
+
+  /// Opacity over `footprint` u,v units at (u, v), filtered like the
+  /// color. Images without an alpha channel are fully opaque.
+  pub fn alpha(&self, u: f32, v: f32, footprint: f32) -> f32 {
+    if !self.has_alpha() {
+      return 1.;
+    }
+    self.filtered(&self.alpha_levels, u, v, footprint)
+  }
+}
+
+impl Default for ImageTexture {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Sums up the image instead of listing every texel
+impl fmt::Debug for ImageTexture {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (width, height) = self.dimensions();
+    f.debug_struct("ImageTexture")
+      .field("width", &width)
+      .field("height", &height)
+      .field("channels", &self.channels)
+      .field("alpha", &self.has_alpha())
+      .field("filter", &self.filter)
+      .field("wrap_u", &self.wrap_u)
+      .field("wrap_v", &self.wrap_v)
+      .finish_non_exhaustive()
+  }
 }
 
 impl Texture for ImageTexture {
   fn value(&self, u: f32, v: f32, _p: &Point3) -> Color {
-    if self.data.len() == 0 {
-      return Color::new();
+    self.sample(u, v, 0.)
+  }
+  fn filtered_value(&self, u: f32, v: f32, _p: &Point3, footprint: f32) -> Color {
+    self.sample(u, v, footprint)
+  }
+}
+
+fn lerp<T: Texel>(a: T, b: T, t: f32) -> T {
+  a * (1. - t) + b * t
+}
+
+pub struct NoiseTexture {
+  noise: Perlin,
+  scale: f32,
+  octaves: usize,
+}
+
+impl NoiseTexture {
+  pub fn new(scale: f32) -> Self {
+    Self::from(scale, 1)
+  }
+  pub fn from(scale: f32, octaves: usize) -> Self {
+    Self {
+      noise: Perlin::new(),
+      scale,
+      octaves: octaves.max(1),
     }
+  }
+}
 
-    let uu = clamp(u, 0., 1.);
-    let vv = 1. - clamp(v, 0., 1.);
+impl Texture for NoiseTexture {
+  fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+    let n = self.noise.fbm(&(*p * self.scale), self.octaves, 2., 0.5);
+    Color::from(1., 1., 1.) * clamp(0.5 * (1. + n), 0., 1.)
+  }
+}
 
-    let mut i = (uu * self.width as f32) as usize;
-    let mut j = (vv * self.height as f32) as usize;
+pub struct MarbleTexture {
+  noise: Perlin,
+  scale: f32,
+  octaves: usize,
+  base: Color,
+  vein: Color,
+}
 
-    if i >= self.width {
-      i = self.width - 1;
+impl MarbleTexture {
+  pub fn new(scale: f32) -> Self {
+    Self::from_colors(scale, 7, Color::from(1., 1., 1.), Color::new())
+  }
+  pub fn from_colors(scale: f32, octaves: usize, base: Color, vein: Color) -> Self {
+    Self {
+      noise: Perlin::new(),
+      scale,
+      octaves: octaves.max(1),
+      base,
+      vein,
     }
-    if j >= self.height {
-      j = self.height - 1;
+  }
+}
+
+impl Texture for MarbleTexture {
+  fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+    let phase = self.scale * p.z() + 10. * self.noise.turb(p, self.octaves);
+    lerp(self.vein, self.base, 0.5 * (1. + phase.sin()))
+  }
+}
+
+pub struct WoodTexture {
+  noise: Perlin,
+  scale: f32,
+  octaves: usize,
+  light: Color,
+  dark: Color,
+}
+
+impl WoodTexture {
+  pub fn new(scale: f32) -> Self {
+    Self::from_colors(
+      scale,
+      4,
+      Color::from(0.79, 0.6, 0.4),
+      Color::from(0.47, 0.28, 0.13),
+    )
+  }
+  pub fn from_colors(scale: f32, octaves: usize, light: Color, dark: Color) -> Self {
+    Self {
+      noise: Perlin::new(),
+      scale,
+      octaves: octaves.max(1),
+      light,
+      dark,
     }
+  }
+}
 
-    let color_scale = 1. / 255.;
-    let offset = j * self.bytes_per_scanline + i * self.bytes_per_pixel;
-    let pixel = &self.data[offset..];
+impl Texture for WoodTexture {
+  fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+    // Rings are concentric around the y axis, wobbled by turbulence
+    let radius = (p.x() * p.x() + p.z() * p.z()).sqrt() * self.scale;
+    let rings = radius + 2. * self.noise.turb(&(*p * self.scale), self.octaves);
+    lerp(self.light, self.dark, rings - rings.floor())
+  }
+}
+
+pub struct CloudTexture {
+  noise: Perlin,
+  scale: f32,
+  octaves: usize,
+  coverage: f32,
+  sky: Color,
+  cloud: Color,
+}
 
-    Color::from(
-      color_scale * pixel[0] as f32,
-      color_scale * pixel[1] as f32,
-      color_scale * pixel[2] as f32,
+impl CloudTexture {
+  pub fn new(scale: f32) -> Self {
+    Self::from_colors(
+      scale,
+      6,
+      0.5,
+      Color::from(0.53, 0.80, 0.92),
+      Color::from(1., 1., 1.),
     )
   }
-}
\ No newline at end of file
+  /// `coverage` in [0, 1] is the fraction of the sky covered by cloud.
+  pub fn from_colors(scale: f32, octaves: usize, coverage: f32, sky: Color, cloud: Color) -> Self {
+    Self {
+      noise: Perlin::new(),
+      scale,
+      octaves: octaves.max(1),
+      coverage: clamp(coverage, 0., 1.),
+      sky,
+      cloud,
+    }
+  }
+}
+
+impl Texture for CloudTexture {
+  fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+    let density = 0.5 * (1. + self.noise.fbm(&(*p * self.scale), self.octaves, 2., 0.5));
+    let edge = 1. - self.coverage;
+    let t = clamp((density - edge) / 0.2, 0., 1.);
+    lerp(self.sky, self.cloud, t * t * (3. - 2. * t))
+  }
+}
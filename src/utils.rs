@@ -2,11 +2,25 @@
 // Description: This file contains different utility functions to be used
 //              throughout the ray tracing application including a method to
 //              convert degrees to radians, clamp points to an edge, or randomly
-//              generate numbers. 
+//              generate numbers. Random numbers come from the thread's own
+//              generator unless a seed is set with seed_random, after which
+//              every thread draws from a seeded generator and each camera
+//              sample reseeds it from its pixel and index, so renders repeat
+//              exactly however the work is split between threads.
 */
 
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
 
 pub fn degrees_to_radians(degrees: f32) -> f32 {
     degrees.to_radians()
@@ -20,10 +34,42 @@ pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
         x
     }
 }
+
+/// Makes random numbers repeatable from `seed`, or random again with None.
+pub fn seed_random(seed: Option<u64>) {
+    SEED.store(seed.unwrap_or(0), Ordering::SeqCst);
+    SEEDED.store(seed.is_some(), Ordering::SeqCst);
+    reseed_random(0);
+}
+
+/// Restarts this thread's seeded generator on its own `stream`, doing
+/// nothing when no seed is set.
+pub fn reseed_random(stream: u64) {
+    SEEDED_RNG.with(|rng| {
+        *rng.borrow_mut() = SEEDED.load(Ordering::SeqCst).then(|| {
+            let seed = SEED.load(Ordering::SeqCst);
+            StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e3779b97f4a7c15))
+        });
+    });
+}
+
+fn with_rng<T>(f: impl FnOnce(&mut dyn rand::RngCore) -> T) -> T {
+    SEEDED_RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        if SEEDED.load(Ordering::Relaxed) {
+            // Threads that haven't started a sample yet begin on stream 0
+            let seed = SEED.load(Ordering::SeqCst);
+            f(rng.get_or_insert_with(|| StdRng::seed_from_u64(seed)))
+        } else {
+            f(&mut rand::thread_rng())
+        }
+    })
+}
+
 pub fn random_double(min: f32, max: f32) -> f32 {
-    rand::thread_rng().gen_range(min..max)
+    with_rng(|rng| rng.gen_range(min..max))
 }
 
 pub fn random_int(min: i32, max: i32) -> i32 {
-    rand::thread_rng().gen_range(min..max)
-}
\ No newline at end of file
+    with_rng(|rng| rng.gen_range(min..max))
+}
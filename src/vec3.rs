@@ -5,11 +5,12 @@
 //              It can be accessed as vec3, point3 or color. Each point in the 3-wide vector is 
 //              a f32 data type. Methods have been created for operations on and between different
 //              3-wide vectors, as well as different initializations methods for randomly generating
-//              points.
+//              points, either by rejection or by mapping a sample from [0, 1)^2.
 */
 
 
 use crate::utils::random_double;
+use std::f32::consts::PI;
 use std::ops;
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -17,9 +18,9 @@ pub struct Vec3 {
     pub e: [f32; 3],
 }
 
-impl Into<(f32, f32, f32)> for Vec3 {
-    fn into(self) -> (f32, f32, f32) {
-        (self.x(), self.y(), self.z())
+impl From<Vec3> for (f32, f32, f32) {
+    fn from(v: Vec3) -> Self {
+        (v.x(), v.y(), v.z())
     }
 }
 
@@ -54,9 +55,9 @@ impl Vec3 {
     pub fn random_in_hemisphere(normal: Vec3) -> Self {
         let in_unit_sphere = Self::random_in_unit_sphere();
         if dot(in_unit_sphere, normal) > 0. {
-            return in_unit_sphere;
+            in_unit_sphere
         } else {
-            return in_unit_sphere.inv();
+            in_unit_sphere.inv()
         }
     }
     pub fn random_in_unit_disk() -> Self {
@@ -69,6 +70,32 @@ impl Vec3 {
         }
     }
 
+    /// Uniform point on the unit sphere for a sample in [0, 1)^2.
+    pub fn unit_vector_from(u: f32, v: f32) -> Self {
+        let z = 1. - 2. * u;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * v;
+        Self::from(r * phi.cos(), r * phi.sin(), z)
+    }
+    /// Uniform point in the unit ball for a sample in [0, 1)^3.
+    pub fn in_unit_sphere_from(u: f32, v: f32, w: f32) -> Self {
+        Self::unit_vector_from(u, v) * w.cbrt()
+    }
+    /// Uniform point on the unit disk for a sample in [0, 1)^2, with the
+    /// concentric mapping so strata stay compact.
+    pub fn in_unit_disk_from(u: f32, v: f32) -> Self {
+        let (a, b) = (2. * u - 1., 2. * v - 1.);
+        if a == 0. && b == 0. {
+            return Self::new();
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4. * (b / a))
+        } else {
+            (b, PI / 2. - PI / 4. * (a / b))
+        };
+        Self::from(r * theta.cos(), r * theta.sin(), 0.)
+    }
+
     pub fn near_zero(&self) -> bool {
         let s: f32 = 1e-8;
         (self.e[0].abs() < s) && (self.e[1].abs() < s) && (self.e[2].abs() < s)
@@ -0,0 +1,229 @@
+/*
+// Description: Tests of keyframe animation. Tracks are checked to pass
+//              through their keys, hold outside them, interpolate linearly,
+//              and follow the uniform Catmull-Rom spline; animated objects
+//              to be hit where their tracks put them at each ray's time and
+//              to stay inside their bounding box over the shutter; cameras
+//              to send each ray from where their tracks put them at its
+//              time, with their own projection and aperture; and the frame
+//              timing, shutter intervals and file numbering.
+*/
+
+use ray_trace::*;
+use std::sync::Arc;
+
+const EPS: f32 = 1e-4;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < EPS
+}
+
+fn close_vec(a: Vec3, b: Vec3) -> bool {
+    (0..3).all(|c| close(a[c], b[c]))
+}
+
+#[test]
+fn tracks_pass_through_their_keys_and_hold_outside_them() {
+    let keys = [(0., 1.), (1., 3.), (3., -2.), (4., 0.5)];
+    for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+        let track = Track::from_keys(interpolation, &keys);
+        for &(time, value) in &keys {
+            assert!(close(track.value_at(time), value), "{:?} at {}", interpolation, time);
+        }
+        assert!(close(track.value_at(-1.), 1.));
+        assert!(close(track.value_at(10.), 0.5));
+    }
+    let still = Track::from(Vec3::from(1., 2., 3.));
+    assert!(still.is_constant());
+    assert!(close_vec(still.value_at(5.), Vec3::from(1., 2., 3.)));
+}
+
+#[test]
+fn keys_are_kept_in_time_order() {
+    let mut track = Track::from_keys(Interpolation::Linear, &[(2., 20.), (0., 0.)]);
+    track.add(1., 5.);
+    track.add(2., 30.);
+    let times: Vec<f32> = track.keys().iter().map(|k| k.time).collect();
+    assert_eq!(times, vec![0., 1., 2.]);
+    assert!(close(track.value_at(1.5), 17.5));
+}
+
+#[test]
+fn linear_tracks_interpolate_linearly() {
+    let track = Track::from_keys(
+        Interpolation::Linear,
+        &[(0., Vec3::new()), (2., Vec3::from(2., -4., 6.))],
+    );
+    assert!(close_vec(track.value_at(0.5), Vec3::from(0.5, -1., 1.5)));
+}
+
+#[test]
+fn catmull_rom_matches_the_uniform_spline() {
+    let p = [0., 2., 1., 4., 3.];
+    let keys: Vec<(f32, f32)> = p.iter().enumerate().map(|(i, &v)| (i as f32, v)).collect();
+    let track = Track::from_keys(Interpolation::CatmullRom, &keys);
+    // Textbook uniform Catmull-Rom between p1 and p2
+    let (p0, p1, p2, p3) = (p[0], p[1], p[2], p[3]);
+    for step in 0..=10 {
+        let s = step as f32 / 10.;
+        let expected = 0.5
+            * (2. * p1
+                + (p2 - p0) * s
+                + (2. * p0 - 5. * p1 + 4. * p2 - p3) * s * s
+                + (3. * p1 - p0 - 3. * p2 + p3) * s * s * s);
+        assert!(close(track.value_at(1. + s), expected), "at {}", 1. + s);
+    }
+
+    // With only two keys the curve is the straight line between them
+    let line = Track::from_keys(Interpolation::CatmullRom, &[(0., 1.), (4., 9.)]);
+    assert!(close(line.value_at(1.), 3.));
+}
+
+fn hit_distance(object: &dyn Hittable, r: &Ray) -> Option<(f32, Point3, Vec3)> {
+    let mut rec = HitRecord::void();
+    object
+        .hit(r, 0.001, f32::INFINITY, &mut rec)
+        .then_some((rec.t, rec.p, rec.normal))
+}
+
+#[test]
+fn animated_objects_are_hit_where_their_tracks_put_them() {
+    let grey: Arc<dyn Material> = Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    let sphere = Arc::new(Sphere::new(Point3::new(), 1., grey.clone()));
+    let translation = Track::from_keys(
+        Interpolation::Linear,
+        &[(0., Vec3::new()), (1., Vec3::from(4., 0., 0.))],
+    );
+    let moving = Animated::from(sphere, translation, Track::from(0.));
+
+    for (time, x) in [(0., 0.), (0.5, 2.), (1., 4.)] {
+        let r = Ray::new(Point3::from(x, 0., -5.), Vec3::from(0., 0., 1.), time);
+        let (t, p, normal) = hit_distance(&moving, &r).expect("hits the moved sphere");
+        assert!(close(t, 4.), "t {} at time {}", t, time);
+        assert!(close_vec(p, Point3::from(x, 0., -1.)));
+        assert!(close_vec(normal, Vec3::from(0., 0., -1.)));
+    }
+    let r = Ray::new(Point3::from(0., 0., -5.), Vec3::from(0., 0., 1.), 1.);
+    assert!(hit_distance(&moving, &r).is_none());
+
+    // A quarter turn around Y carries the rect's +z normal to +x
+    let rect = Arc::new(XyRect::from(-1., 1., -1., 1., 0., grey));
+    let turning = Animated::from(
+        rect,
+        Track::from(Vec3::from(0., 0., 3.)),
+        Track::from_keys(Interpolation::Linear, &[(0., 0.), (1., 90.)]),
+    );
+    let r = Ray::new(Point3::from(-5., 0.5, 3.), Vec3::from(1., 0., 0.), 1.);
+    let (t, p, normal) = hit_distance(&turning, &r).expect("hits the turned rect");
+    assert!(close(t, 5.));
+    assert!(close_vec(p, Point3::from(0., 0.5, 3.)));
+    assert!(close_vec(normal, Vec3::from(-1., 0., 0.)));
+    let r = Ray::new(Point3::from(0., 0.5, -5.), Vec3::from(0., 0., 1.), 0.);
+    assert!(hit_distance(&turning, &r).is_some());
+}
+
+#[test]
+fn animated_bounding_box_covers_the_shutter() {
+    let grey: Arc<dyn Material> = Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    let sphere = Arc::new(Sphere::new(Point3::new(), 1., grey));
+    let path = Track::from_keys(
+        Interpolation::CatmullRom,
+        &[
+            (0., Vec3::new()),
+            (1., Vec3::from(3., 2., 0.)),
+            (2., Vec3::from(0., 4., 1.)),
+        ],
+    );
+    let moving = Animated::from(sphere, path.clone(), Track::from(30.));
+    let mut bbox = Bb::new();
+    assert!(moving.bounding_box(0.25, 1.75, &mut bbox));
+    for step in 0..=100 {
+        let time = 0.25 + 1.5 * step as f32 / 100.;
+        let center = path.value_at(time);
+        for c in 0..3 {
+            assert!(bbox.min()[c] <= center[c] - 1. + 1e-2, "min at {}", time);
+            assert!(bbox.max()[c] >= center[c] + 1. - 1e-2, "max at {}", time);
+        }
+    }
+}
+
+#[test]
+fn cameras_follow_their_tracks_within_the_shutter() {
+    let mut animation = CameraAnimation::from(
+        Point3::from(0., 0., -10.),
+        Point3::new(),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.,
+        0.,
+        10.,
+    );
+    animation.lookfrom = Track::from_keys(
+        Interpolation::Linear,
+        &[(0., Point3::from(0., 0., -10.)), (2., Point3::from(0., 4., -10.))],
+    );
+    // Each ray leaves from where the camera is at its own time, so the
+    // origins spread over the path taken while the shutter is open
+    let cam = animation.camera_at(0.5, 1.5);
+    let (mut lowest, mut highest) = (f32::INFINITY, -f32::INFINITY);
+    for _ in 0..256 {
+        let r = cam.get_ray(0.5, 0.5);
+        assert!((0.5..=1.5).contains(&r.time()));
+        assert!(close_vec(r.origin(), Point3::from(0., 2. * r.time(), -10.)));
+        lowest = lowest.min(r.origin().y());
+        highest = highest.max(r.origin().y());
+    }
+    assert!(lowest < 1.2 && highest > 2.8, "origins only span {} to {}", lowest, highest);
+}
+
+#[test]
+fn posed_cameras_keep_their_projection_and_aperture() {
+    let mut animation = CameraAnimation::from(
+        Point3::from(0., 0., -10.),
+        Point3::new(),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.,
+        2.,
+        10.,
+    );
+    animation.lookfrom = Track::from_keys(
+        Interpolation::Linear,
+        &[(0., Point3::from(0., 0., -10.)), (2., Point3::from(0., 4., -10.))],
+    );
+    let posed_from = |time: f32| Point3::from(0., 2. * time, -10.);
+
+    // A quarter turn across an equirectangular frame is square to the view
+    let mut cam = animation.camera_at(0.5, 1.5);
+    cam.set_projection(Projection::Equirectangular);
+    for _ in 0..64 {
+        let r = cam.get_ray(0.75, 0.5);
+        assert!(close_vec(r.origin(), posed_from(r.time())));
+        let forward = unit_vector(Point3::new() - r.origin());
+        assert!(close(dot(unit_vector(r.direction()), forward), 0.));
+    }
+
+    // Only texel (1, 0) of the mask is open, right of center and up, and
+    // the camera's right is -x looking down +z
+    let mut texels = vec![Color::new(); 4];
+    texels[1] = Color::from(1., 1., 1.);
+    let mut cam = animation.camera_at(0.5, 1.5);
+    cam.set_aperture_shape(ApertureShape::Mask(Arc::new(ImageTexture::from_texels(2, 2, texels))));
+    for _ in 0..64 {
+        let r = cam.get_ray(0.5, 0.5);
+        let offset = r.origin() - posed_from(r.time());
+        assert!(offset.x() <= 0. && offset.y() >= 0., "{:?}", offset);
+        assert!(offset.len() <= 1.0001 * 2f32.sqrt(), "{:?}", offset);
+    }
+}
+
+#[test]
+fn frames_are_timed_and_numbered() {
+    let animation = Animation::from(24., 3, 5);
+    assert_eq!(animation.frames().collect::<Vec<_>>(), vec![3, 4, 5]);
+    let (open, close_time) = animation.shutter_interval(12);
+    assert!(close(open, 0.5));
+    assert!(close(close_time - open, 0.5 / 24.));
+    assert_eq!(frame_path("out/shot_", 7, "ppm"), "out/shot_0007.ppm");
+    assert_eq!(frame_path("f", 12345, "exr"), "f12345.exr");
+}
@@ -0,0 +1,77 @@
+/*
+// Description: Tests of the camera lens. Ray origins of a camera focused one
+//              unit away are points on its aperture, which are checked to
+//              cover mask openings evenly and to stay inside the cat-eye
+//              clipped aperture without collapsing onto the lens center.
+*/
+
+use ray_trace::*;
+use std::sync::Arc;
+
+// A lens of radius 1 at the origin looking down -z, so ray origins are the
+// aperture points themselves
+fn lens() -> Camera {
+    Camera::new(
+        Point3::new(),
+        Point3::from(0., 0., -1.),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.,
+        2.,
+        1.,
+        0.,
+        0.,
+    )
+}
+
+// 8x8 mask open only at texel (i, j), top row first
+fn one_texel_mask(i: usize, j: usize) -> ApertureShape {
+    let mut texels = vec![Color::new(); 64];
+    texels[j * 8 + i] = Color::from(1., 1., 1.);
+    ApertureShape::Mask(Arc::new(ImageTexture::from_texels(8, 8, texels)))
+}
+
+fn lens_points(cam: &Camera, s: f32, t: f32, n: usize) -> Vec<Point3> {
+    (0..n).map(|_| cam.get_ray(s, t).origin()).collect()
+}
+
+fn mean(points: &[Point3]) -> (f32, f32) {
+    let n = points.len() as f32;
+    (
+        points.iter().map(|p| p.x()).sum::<f32>() / n,
+        points.iter().map(|p| p.y()).sum::<f32>() / n,
+    )
+}
+
+#[test]
+fn masks_are_sampled_over_their_opening() {
+    let mut cam = lens();
+    cam.set_aperture_shape(one_texel_mask(5, 2));
+    let points = lens_points(&cam, 0.5, 0.5, 2000);
+    // Texel (5, 2) covers x and y from 0.25 to 0.5
+    for p in &points {
+        assert!((0.25..=0.5).contains(&p.x()) && (0.25..=0.5).contains(&p.y()), "{:?}", p);
+    }
+    let (x, y) = mean(&points);
+    assert!((x - 0.375).abs() < 0.01 && (y - 0.375).abs() < 0.01, "mean {} {}", x, y);
+}
+
+#[test]
+fn cat_eye_clipping_keeps_points_inside_the_clipped_aperture() {
+    let mut cam = lens();
+    cam.set_cat_eye(1.);
+    // At the corner the pupil is the unit circle around (1, 1)
+    let points = lens_points(&cam, 1., 1., 4000);
+    for p in &points {
+        assert!(p.len() <= 1.01, "{:?}", p);
+        assert!((*p - Vec3::from(1., 1., 0.)).len() <= 1.01, "{:?}", p);
+    }
+    let (x, y) = mean(&points);
+    assert!((x - 0.5).abs() < 0.03 && (y - 0.5).abs() < 0.03, "mean {} {}", x, y);
+
+    // A mask opening the pupil hides entirely is left unclipped
+    cam.set_aperture_shape(one_texel_mask(0, 7));
+    for p in lens_points(&cam, 1., 1., 200) {
+        assert!(p.x() <= -0.75 && p.y() <= -0.75, "{:?}", p);
+    }
+}
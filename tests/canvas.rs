@@ -0,0 +1,229 @@
+/*
+// Description: Tests of the canvas bookkeeping around the samples. Adding
+//              samples to a denoised canvas, by merging, compositing,
+//              splatting or rendering more passes, must bring the output
+//              back to the samples instead of the stale denoised image.
+//              Checkpoints must be refused when their file is too short for
+//              the size they claim, and keep the scene fingerprint. A crop
+//              composited over another render must match a full render,
+//              and is refused over a render of another frame or scene.
+//              Renders with different seeds take different samples, and
+//              only those can be merged.
+*/
+
+use ray_trace::*;
+
+const SIZE: usize = 8;
+
+fn flat_render(value: f32, spp: usize, seed: u32) -> Canvas {
+    let settings = AdaptiveSettings {
+        min_spp: spp,
+        max_spp: spp,
+        pass_spp: spp,
+        threshold: 0.,
+        seed,
+        ..AdaptiveSettings::default()
+    };
+    Canvas::from_fn_progressive(SIZE, SIZE, settings, |_, _| Color::from(value, value, value))
+}
+
+fn mean_luminance(canvas: &Canvas) -> f32 {
+    let (w, h) = canvas.dimensions();
+    (0..w * h).map(|idx| luminance(canvas.pixel_color(idx))).sum::<f32>() / (w * h) as f32
+}
+
+#[test]
+fn adding_samples_after_denoising_shows_them() {
+    let mut canvas = flat_render(1., 4, 0);
+    canvas.denoise(&DenoiseSettings::default());
+    assert!((mean_luminance(&canvas) - 1.).abs() < 1e-3);
+
+    canvas.merge(&flat_render(3., 4, 1)).unwrap();
+    assert!((mean_luminance(&canvas) - 2.).abs() < 1e-3);
+
+    canvas.denoise(&DenoiseSettings::default());
+    let full = flat_render(5., 4, 2);
+    canvas.composite(&full).unwrap();
+    assert!((mean_luminance(&canvas) - 5.).abs() < 1e-3);
+
+    canvas.denoise(&DenoiseSettings::default());
+    let settings = AdaptiveSettings {
+        min_spp: 8,
+        max_spp: 8,
+        pass_spp: 4,
+        threshold: 0.,
+        ..AdaptiveSettings::default()
+    };
+    canvas.render_progressive(&settings, |_, _| Color::from(7., 7., 7.));
+    assert!((mean_luminance(&canvas) - 6.).abs() < 1e-3);
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ray_trace_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn checkpoints_larger_than_their_file_are_refused() {
+    // A header claiming a 2^20 x 2^20 frame with no pixels behind it
+    let path = temp_path("huge.ckpt");
+    let mut bytes = b"RTCK".to_vec();
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    for word in [1u64 << 20, 1 << 20, 0, 0, 0, 1 << 20, 1 << 20, 0] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    std::fs::write(&path, &bytes).unwrap();
+    let err = Canvas::load_checkpoint(&path).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // Sizes whose product overflows are refused too
+    bytes[8..24].copy_from_slice(&[0xff; 16]);
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(Canvas::load_checkpoint(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn checkpoints_keep_the_scene_fingerprint() {
+    let scene = fingerprint("scene 1");
+    assert_eq!(scene, fingerprint("scene 1"));
+    assert_ne!(scene, fingerprint("scene 2"));
+
+    let settings = AdaptiveSettings {
+        min_spp: 2,
+        max_spp: 2,
+        pass_spp: 2,
+        threshold: 0.,
+        fingerprint: scene,
+        ..AdaptiveSettings::default()
+    };
+    let canvas = Canvas::from_fn_progressive(SIZE, SIZE, settings.clone(), |_, _| Color::from(1., 1., 1.));
+    let path = temp_path("scene.ckpt");
+    canvas.save_checkpoint(&path).unwrap();
+    let mut loaded = Canvas::load_checkpoint(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.fingerprint(), scene);
+
+    // Samples of another scene can't be merged in
+    assert!(loaded.merge(&flat_render(1., 2, 1)).is_err());
+    let other = Canvas::from_fn_progressive(
+        SIZE,
+        SIZE,
+        AdaptiveSettings {
+            seed: 1,
+            ..settings
+        },
+        |_, _| Color::from(1., 1., 1.),
+    );
+    assert!(loaded.merge(&other).is_ok());
+}
+
+#[test]
+fn composited_crops_match_a_full_render() {
+    const FRAME: usize = 16;
+    // Stripes a few pixels wide, so every pixel's filter sees an edge
+    let stripes = |x: f32, y: f32| {
+        let v = if ((x + 0.5 * y) / 3.).floor() as i32 % 2 == 0 { 1. } else { 0.1 };
+        Color::from(v, v, v)
+    };
+    let settings = AdaptiveSettings {
+        min_spp: 4,
+        max_spp: 4,
+        pass_spp: 4,
+        threshold: 0.,
+        filter: PixelFilter::mitchell(2.),
+        sampler: std::sync::Arc::new(SobolSampler),
+        ..AdaptiveSettings::default()
+    };
+    let full = Canvas::from_fn_progressive(FRAME, FRAME, settings.clone(), stripes);
+
+    let crop = CropWindow::from(4, 5, 11, 12);
+    let region = Canvas::from_fn_progressive(
+        FRAME,
+        FRAME,
+        AdaptiveSettings {
+            crop: Some(crop),
+            ..settings.clone()
+        },
+        stripes,
+    );
+    let mut composite = Canvas::from_fn_progressive(FRAME, FRAME, settings, |_, _| Color::new());
+    composite.composite(&region).unwrap();
+
+    let ((ox, oy), (w, h)) = crop.canvas_rect(FRAME, FRAME);
+    for y in oy..oy + h {
+        for x in ox..ox + w {
+            let idx = y * FRAME + x;
+            let (a, b) = (full.pixel_color(idx), composite.pixel_color(idx));
+            assert!((a - b).len() < 1e-4, "pixel {},{}: {:?} != {:?}", x, y, a, b);
+        }
+    }
+}
+
+#[test]
+fn composites_of_mismatched_renders_are_refused() {
+    let render = |frame: usize, crop: Option<CropWindow>, fingerprint: u64, aovs: bool| {
+        let settings = AdaptiveSettings {
+            min_spp: 1,
+            max_spp: 1,
+            pass_spp: 1,
+            threshold: 0.,
+            crop,
+            fingerprint,
+            aovs,
+            ..AdaptiveSettings::default()
+        };
+        Canvas::from_fn_progressive(frame, frame, settings, |_, _| AovSample::default())
+    };
+    let crop = Some(CropWindow::from(2, 2, 6, 6));
+    let region = render(SIZE, crop, 1, false);
+    assert!(render(SIZE, None, 1, false).composite(&region).is_ok());
+
+    // Another frame size, another scene, or onto a crop rather than a frame
+    assert!(render(2 * SIZE, None, 1, false).composite(&region).is_err());
+    assert!(render(SIZE, None, 2, false).composite(&region).is_err());
+    assert!(render(SIZE, crop, 1, false).composite(&region).is_err());
+
+    // The frame's AOVs would be left stale under the new pixels
+    assert!(render(SIZE, None, 1, true).composite(&region).is_err());
+    assert!(render(SIZE, None, 1, true).composite(&render(SIZE, crop, 1, true)).is_ok());
+}
+
+#[test]
+fn seeds_pick_independent_samples() {
+    let render = |seed: u32| {
+        let settings = AdaptiveSettings {
+            min_spp: 4,
+            max_spp: 4,
+            pass_spp: 4,
+            threshold: 0.,
+            sampler: std::sync::Arc::new(SobolSampler),
+            seed,
+            ..AdaptiveSettings::default()
+        };
+        // The jitter within the pixel, and a number drawn along the path
+        Canvas::from_fn_progressive(SIZE, SIZE, settings, |x, y| {
+            Color::from(x.fract(), y.fract(), sample_1d())
+        })
+    };
+    let (first, again, second) = (render(0), render(0), render(1));
+    let differing = (0..SIZE * SIZE)
+        .filter(|&idx| (first.pixel_mean(idx) - second.pixel_mean(idx)).len() > 1e-4)
+        .count();
+    assert_eq!(differing, SIZE * SIZE);
+    for idx in 0..SIZE * SIZE {
+        assert!((first.pixel_mean(idx) - again.pixel_mean(idx)).len() < 1e-6);
+    }
+
+    // The same seed again only repeats the samples
+    let mut merged = render(0);
+    assert!(merged.merge(&again).is_err());
+    merged.merge(&second).unwrap();
+    assert!(merged.merge(&render(1)).is_err());
+    assert_eq!(merged.seeds(), &[0, 1]);
+
+    let path = temp_path("seeds.ckpt");
+    merged.save_checkpoint(&path).unwrap();
+    let loaded = Canvas::load_checkpoint(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.seeds(), &[0, 1]);
+}
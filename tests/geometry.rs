@@ -0,0 +1,491 @@
+/*
+// Description: Property-based tests of ray intersection for the geometry:
+//              spheres, the axis aligned rects, quadrilaterals, Translate,
+//              RotateY, bounding boxes and the hittable list. Rays are aimed
+//              at random points on or inside each shape, so the hit distance
+//              and texture coords are known exactly, and every hit is checked
+//              to lie inside the shape's bounding box, with a unit normal
+//              facing the ray and texture coords in [0, 1].
+*/
+
+use proptest::prelude::*;
+use ray_trace::*;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+const EPS: f32 = 1e-3;
+
+// What a hit record holds, without the borrow of the object hit
+#[derive(Debug)]
+struct Hit {
+    t: f32,
+    p: Point3,
+    normal: Vec3,
+    u: f32,
+    v: f32,
+    front_face: bool,
+}
+
+fn grey() -> Arc<dyn Material> {
+    Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)))
+}
+
+fn hit_of(object: &dyn Hittable, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    let mut rec = HitRecord::void();
+    object.hit(r, t_min, t_max, &mut rec).then_some(Hit {
+        t: rec.t,
+        p: rec.p,
+        normal: rec.normal,
+        u: rec.u,
+        v: rec.v,
+        front_face: rec.front_face,
+    })
+}
+
+fn bbox_of(object: &dyn Hittable) -> Bb {
+    let mut bbox = Bb::new();
+    assert!(object.bounding_box(0., 1., &mut bbox));
+    bbox
+}
+
+fn contains(bbox: &Bb, p: Point3) -> bool {
+    (0..3).all(|i| {
+        let slack = EPS * (1. + p[i].abs());
+        bbox.min()[i] - slack <= p[i] && p[i] <= bbox.max()[i] + slack
+    })
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= EPS * (1. + a.abs().max(b.abs()))
+}
+
+fn close_vec(a: Vec3, b: Vec3) -> bool {
+    (0..3).all(|i| close(a[i], b[i]))
+}
+
+fn unit(theta: f32, phi: f32) -> Vec3 {
+    Vec3::from(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
+
+fn rotate_y(p: Point3, degrees: f32) -> Point3 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Point3::from(cos * p.x() + sin * p.z(), p.y(), -sin * p.x() + cos * p.z())
+}
+
+// What every hit must satisfy, whatever the shape
+fn check_hit(object: &dyn Hittable, r: &Ray, hit: &Hit) -> Result<(), TestCaseError> {
+    prop_assert!(close_vec(hit.p, r.at(hit.t)), "hit point {:?} is off the ray", hit.p);
+    prop_assert!(close(hit.normal.len(), 1.), "normal {:?} is not unit length", hit.normal);
+    prop_assert!(
+        dot(hit.normal, r.direction()) <= EPS * r.direction().len(),
+        "normal {:?} faces away from the ray",
+        hit.normal
+    );
+    prop_assert!((0. ..=1.).contains(&hit.u), "u = {} is outside [0, 1]", hit.u);
+    prop_assert!((0. ..=1.).contains(&hit.v), "v = {} is outside [0, 1]", hit.v);
+    let bbox = bbox_of(object);
+    prop_assert!(
+        contains(&bbox, hit.p),
+        "hit point {:?} is outside the bounding box {:?} to {:?}",
+        hit.p,
+        bbox.min(),
+        bbox.max()
+    );
+    Ok(())
+}
+
+fn point(range: f32) -> impl Strategy<Value = Point3> {
+    (-range..range, -range..range, -range..range).prop_map(|(x, y, z)| Point3::from(x, y, z))
+}
+
+fn direction() -> impl Strategy<Value = Vec3> {
+    (0.01f32..PI - 0.01, 0f32..2. * PI).prop_map(|(theta, phi)| unit(theta, phi))
+}
+
+// A rect and, in its own axes, the plane's axis and the axes u and v run along
+fn axis_rect(axis: usize, a0: f32, a1: f32, b0: f32, b1: f32, k: f32) -> (Arc<dyn Hittable>, [usize; 3]) {
+    match axis {
+        0 => (Arc::new(YzRect::from(a0, a1, b0, b1, k, grey())), [0, 1, 2]),
+        1 => (Arc::new(XzRect::from(a0, a1, b0, b1, k, grey())), [1, 0, 2]),
+        _ => (Arc::new(XyRect::from(a0, a1, b0, b1, k, grey())), [2, 0, 1]),
+    }
+}
+
+// A convex quadrilateral in the plane x = k, its corners in order around a
+// circle, as (z, y) pairs
+fn quad_corners(center: (f32, f32), radius: f32, angles: [f32; 4]) -> [(f32, f32); 4] {
+    let mut angles = angles;
+    angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    angles.map(|a| (center.0 + radius * a.cos(), center.1 + radius * a.sin()))
+}
+
+proptest! {
+    #[test]
+    fn sphere_hit_matches_analytic_root(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        origin in point(20.),
+        (theta, phi) in (0f32..PI, 0f32..2. * PI),
+    ) {
+        prop_assume!((origin - center).len() > radius * 1.01);
+        let sphere = Sphere::new(center, radius, grey());
+        let target = center + unit(theta, phi) * (radius * 0.999);
+        let r = Ray::new(origin, target - origin, 0.);
+
+        let hit = hit_of(&sphere, &r, 0.001, f32::INFINITY);
+        prop_assert!(hit.is_some(), "ray aimed at the sphere missed");
+        let hit = hit.unwrap();
+
+        // Nearer root of |o + t d - c|^2 = r^2, in double precision
+        let (o, d, c) = (origin, r.direction(), center);
+        let oc = [(o.x() - c.x()) as f64, (o.y() - c.y()) as f64, (o.z() - c.z()) as f64];
+        let dd = [d.x() as f64, d.y() as f64, d.z() as f64];
+        let a: f64 = dd.iter().map(|v| v * v).sum();
+        let half_b: f64 = (0..3).map(|i| oc[i] * dd[i]).sum();
+        let cc: f64 = oc.iter().map(|v| v * v).sum::<f64>() - (radius as f64).powi(2);
+        let root = (-half_b - (half_b * half_b - a * cc).max(0.).sqrt()) / a;
+
+        prop_assert!(close(hit.t, root as f32), "t = {}, expected {}", hit.t, root);
+        prop_assert!(hit.front_face);
+        prop_assert!(close((hit.p - center).len(), radius));
+        check_hit(&sphere, &r, &hit)?;
+    }
+
+    #[test]
+    fn sphere_hit_from_inside_is_a_back_face(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        offset in direction(),
+        depth in 0f32..0.9,
+        dir in direction(),
+    ) {
+        let sphere = Sphere::new(center, radius, grey());
+        let r = Ray::new(center + offset * (radius * depth), dir, 0.);
+        let hit = hit_of(&sphere, &r, 0.001, f32::INFINITY);
+        prop_assert!(hit.is_some(), "ray from inside the sphere missed");
+        let hit = hit.unwrap();
+        prop_assert!(!hit.front_face);
+        prop_assert!(close_vec(hit.normal, (center - hit.p) / radius));
+        check_hit(&sphere, &r, &hit)?;
+    }
+
+    #[test]
+    fn sphere_missed_by_rays_passing_outside(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        dir in direction(),
+        side in direction(),
+        gap in 1.01f32..3.,
+        back in 1f32..20.,
+    ) {
+        // Perpendicular to the ray, so the ray's closest approach is the gap
+        let across = cross(dir, side);
+        prop_assume!(across.len() > 0.1);
+        let nearest = center + unit_vector(across) * (radius * gap);
+        let r = Ray::new(nearest - dir * back, dir, 0.);
+        prop_assert!(hit_of(&Sphere::new(center, radius, grey()), &r, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn axis_rect_hit_matches_target(
+        axis in 0usize..3,
+        (a0, b0) in (-5f32..5., -5f32..5.),
+        (wa, wb) in (0.1f32..5., 0.1f32..5.),
+        k in -5f32..5.,
+        (fa, fb) in (0.001f32..0.999, 0.001f32..0.999),
+        origin in point(20.),
+    ) {
+        let (rect, [n, ua, va]) = axis_rect(axis, a0, a0 + wa, b0, b0 + wb, k);
+        prop_assume!((origin[n] - k).abs() > 0.1);
+        let mut target = Point3::new();
+        target[n] = k;
+        target[ua] = a0 + fa * wa;
+        target[va] = b0 + fb * wb;
+        // Aimed so the plane is reached at t = 1
+        let r = Ray::new(origin, target - origin, 0.);
+
+        let hit = hit_of(&*rect, &r, 0.001, f32::INFINITY);
+        prop_assert!(hit.is_some(), "ray aimed inside the rect missed");
+        let hit = hit.unwrap();
+        prop_assert!(close(hit.t, 1.), "t = {}, expected 1", hit.t);
+        prop_assert!(close_vec(hit.p, target));
+        prop_assert!(close(hit.u, fa) && close(hit.v, fb), "uv ({}, {}), expected ({}, {})", hit.u, hit.v, fa, fb);
+        prop_assert_eq!(hit.front_face, origin[n] > k);
+        check_hit(&*rect, &r, &hit)?;
+    }
+
+    #[test]
+    fn axis_rect_missed_outside_its_edges(
+        axis in 0usize..3,
+        (a0, b0) in (-5f32..5., -5f32..5.),
+        (wa, wb) in (0.1f32..5., 0.1f32..5.),
+        k in -5f32..5.,
+        (fa, fb) in (1.01f32..3., 0f32..1.),
+        swap in any::<bool>(),
+        origin in point(20.),
+    ) {
+        let (rect, [n, ua, va]) = axis_rect(axis, a0, a0 + wa, b0, b0 + wb, k);
+        prop_assume!((origin[n] - k).abs() > 0.1);
+        let (fa, fb) = if swap { (fb, fa) } else { (fa, fb) };
+        let mut target = Point3::new();
+        target[n] = k;
+        target[ua] = a0 + fa * wa;
+        target[va] = b0 + fb * wb;
+        let r = Ray::new(origin, target - origin, 0.);
+        prop_assert!(hit_of(&*rect, &r, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn quadrilateral_hit_matches_target(
+        center in (-5f32..5., -5f32..5.),
+        radius in 0.5f32..5.,
+        angles in [0f32..2. * PI, 0f32..2. * PI, 0f32..2. * PI, 0f32..2. * PI],
+        k in -5f32..5.,
+        (wa, wb) in (0.05f32..1., 0.05f32..1.),
+        origin in point(20.),
+    ) {
+        let corners = quad_corners(center, radius, angles);
+        let quad = Quadrilateral::from(corners[0], corners[1], corners[2], corners[3], k, grey());
+        prop_assume!((origin.x() - k).abs() > 0.1);
+
+        // Inside the triangle of the first three corners, pulled towards the
+        // middle so it isn't on an edge
+        let (w0, w1) = (wa / (1. + wa + wb), wb / (1. + wa + wb));
+        let w2 = 1. - w0 - w1;
+        let mid = (
+            corners.iter().map(|c| c.0).sum::<f32>() / 4.,
+            corners.iter().map(|c| c.1).sum::<f32>() / 4.,
+        );
+        let inside = |i: usize, m: f32| {
+            let c = [corners[0], corners[1], corners[2]].map(|c| if i == 0 { c.0 } else { c.1 });
+            0.9 * (w0 * c[0] + w1 * c[1] + w2 * c[2]) + 0.1 * m
+        };
+        let target = Point3::from(k, inside(1, mid.1), inside(0, mid.0));
+        // The quad must be big enough that the target is well inside it
+        let area = (0..4)
+            .map(|i| {
+                let (p, q) = (corners[i], corners[(i + 1) % 4]);
+                p.0 * q.1 - q.0 * p.1
+            })
+            .sum::<f32>()
+            .abs()
+            / 2.;
+        prop_assume!(area > 0.05 * radius * radius);
+        let r = Ray::new(origin, target - origin, 0.);
+
+        let hit = hit_of(&quad, &r, 0.001, f32::INFINITY);
+        prop_assert!(hit.is_some(), "ray aimed inside the quadrilateral missed");
+        let hit = hit.unwrap();
+        prop_assert!(close(hit.t, 1.), "t = {}, expected 1", hit.t);
+        prop_assert!(close_vec(hit.p, target));
+        check_hit(&quad, &r, &hit)?;
+    }
+
+    #[test]
+    fn quadrilateral_missed_outside_its_circle(
+        center in (-5f32..5., -5f32..5.),
+        radius in 0.5f32..5.,
+        angles in [0f32..2. * PI, 0f32..2. * PI, 0f32..2. * PI, 0f32..2. * PI],
+        k in -5f32..5.,
+        (angle, distance) in (0f32..2. * PI, 1.05f32..3.),
+        origin in point(20.),
+    ) {
+        let corners = quad_corners(center, radius, angles);
+        let quad = Quadrilateral::from(corners[0], corners[1], corners[2], corners[3], k, grey());
+        prop_assume!((origin.x() - k).abs() > 0.1);
+        let target = Point3::from(
+            k,
+            center.1 + radius * distance * angle.sin(),
+            center.0 + radius * distance * angle.cos(),
+        );
+        let r = Ray::new(origin, target - origin, 0.);
+        prop_assert!(hit_of(&quad, &r, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn translated_sphere_hits_like_a_moved_sphere(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        offset in point(5.),
+        origin in point(20.),
+        (theta, phi) in (0f32..PI, 0f32..2. * PI),
+    ) {
+        let moved = Translate::from(Arc::new(Sphere::new(center, radius, grey())), &offset);
+        let reference = Sphere::new(center + offset, radius, grey());
+        let target = center + offset + unit(theta, phi) * (radius * 0.999);
+        let r = Ray::new(origin, target - origin, 0.);
+
+        let (hit, expected) = (hit_of(&moved, &r, 0.001, f32::INFINITY), hit_of(&reference, &r, 0.001, f32::INFINITY));
+        prop_assert_eq!(hit.is_some(), expected.is_some());
+        if let (Some(hit), Some(expected)) = (hit, expected) {
+            prop_assert!(close(hit.t, expected.t));
+            prop_assert!(close_vec(hit.p, expected.p));
+            prop_assert!(close_vec(hit.normal, expected.normal));
+            prop_assert_eq!(hit.front_face, expected.front_face);
+            check_hit(&moved, &r, &hit)?;
+        }
+    }
+
+    #[test]
+    fn translated_sphere_hit_from_inside_is_a_back_face(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        offset in point(5.),
+        dir in direction(),
+    ) {
+        let moved = Translate::from(Arc::new(Sphere::new(center, radius, grey())), &offset);
+        let r = Ray::new(center + offset, dir, 0.);
+        let hit = hit_of(&moved, &r, 0.001, f32::INFINITY);
+        prop_assert!(hit.is_some());
+        let hit = hit.unwrap();
+        prop_assert!(!hit.front_face);
+        check_hit(&moved, &r, &hit)?;
+    }
+
+    #[test]
+    fn rotated_sphere_hits_like_a_moved_sphere(
+        center in point(5.),
+        radius in 0.1f32..5.,
+        angle in -360f32..360.,
+        origin in point(20.),
+        (theta, phi) in (0f32..PI, 0f32..2. * PI),
+        inside in any::<bool>(),
+    ) {
+        let rotated = RotateY::from(Arc::new(Sphere::new(center, radius, grey())), angle);
+        let moved_center = rotate_y(center, angle);
+        let reference = Sphere::new(moved_center, radius, grey());
+        let target = moved_center + unit(theta, phi) * (radius * 0.999);
+        let origin = if inside { moved_center } else { origin };
+        let r = Ray::new(origin, target - origin, 0.);
+
+        let (hit, expected) = (hit_of(&rotated, &r, 0.001, f32::INFINITY), hit_of(&reference, &r, 0.001, f32::INFINITY));
+        prop_assert_eq!(hit.is_some(), expected.is_some());
+        if let (Some(hit), Some(expected)) = (hit, expected) {
+            prop_assert!(close(hit.t, expected.t), "t = {}, expected {}", hit.t, expected.t);
+            prop_assert!(close_vec(hit.p, expected.p));
+            prop_assert!(close_vec(hit.normal, expected.normal));
+            prop_assert_eq!(hit.front_face, expected.front_face);
+            check_hit(&rotated, &r, &hit)?;
+        }
+    }
+
+    #[test]
+    fn rotated_bounding_box_bounds_the_rotated_corners(
+        min in point(5.),
+        size in (0.1f32..5., 0.1f32..5.),
+        angle in -360f32..360.,
+    ) {
+        let inner: Arc<dyn Hittable> = Arc::new(Translate::from(
+            Arc::new(XzRect::from(0., size.0, 0., size.1, 0., grey())),
+            &min,
+        ));
+        let rotated = RotateY::from(inner.clone(), angle);
+        let bbox = bbox_of(&rotated);
+        let inner_box = bbox_of(&*inner);
+
+        let (mut lo, mut hi) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+        for i in 0..8 {
+            let pick = |axis: usize| if i >> axis & 1 == 1 { inner_box.max()[axis] } else { inner_box.min()[axis] };
+            let corner = rotate_y(Point3::from(pick(0), pick(1), pick(2)), angle);
+            prop_assert!(contains(&bbox, corner), "corner {:?} is outside the rotated box", corner);
+            for axis in 0..3 {
+                lo[axis] = lo[axis].min(corner[axis]);
+                hi[axis] = hi[axis].max(corner[axis]);
+            }
+        }
+        // and no bigger than it needs to be
+        for axis in 0..3 {
+            prop_assert!(close(bbox.min()[axis], lo[axis]) && close(bbox.max()[axis], hi[axis]));
+        }
+    }
+
+    #[test]
+    fn box_hit_by_rays_aimed_inside(
+        min in point(5.),
+        size in (0.1f32..5., 0.1f32..5., 0.1f32..5.),
+        (fx, fy, fz) in (0.01f32..0.99, 0.01f32..0.99, 0.01f32..0.99),
+        origin in point(20.),
+    ) {
+        let max = min + Vec3::from(size.0, size.1, size.2);
+        let bbox = Bb::from(&min, &max);
+        let target = min + Vec3::from(fx * size.0, fy * size.1, fz * size.2);
+        let r = Ray::new(origin, target - origin, 0.);
+        prop_assert!(bbox.hit(&r, 0.001, f32::INFINITY));
+
+        if !contains(&bbox, origin) {
+            // Pointing away, or stopping short, misses
+            let away = Ray::new(origin, origin - target, 0.);
+            prop_assert!(!bbox.hit(&away, 0.001, f32::INFINITY));
+            let entry = (0..3)
+                .map(|i| {
+                    let inv = 1. / r.direction()[i];
+                    ((min[i] - origin[i]) * inv).min((max[i] - origin[i]) * inv)
+                })
+                .fold(f32::NEG_INFINITY, f32::max);
+            prop_assert!(!bbox.hit(&r, 0.001, entry * 0.99));
+        }
+    }
+
+    #[test]
+    fn surrounding_box_is_the_union(
+        (min0, min1) in (point(5.), point(5.)),
+        (size0, size1) in (point(5.), point(5.)),
+    ) {
+        let abs = |v: Vec3| Vec3::from(v.x().abs(), v.y().abs(), v.z().abs());
+        let b0 = Bb::from(&min0, &(min0 + abs(size0)));
+        let b1 = Bb::from(&min1, &(min1 + abs(size1)));
+        let both = surrounding_box(&b0, &b1);
+        for axis in 0..3 {
+            prop_assert_eq!(both.min()[axis], b0.min()[axis].min(b1.min()[axis]));
+            prop_assert_eq!(both.max()[axis], b0.max()[axis].max(b1.max()[axis]));
+        }
+    }
+
+    #[test]
+    fn hittable_list_reports_the_closest_hit(
+        centers in prop::collection::vec(point(10.), 1..8),
+        origin in point(20.),
+        dir in direction(),
+    ) {
+        let mut list = HittableList::new();
+        for c in &centers {
+            list.add(Arc::new(Sphere::new(*c, 1., grey())));
+        }
+        let r = Ray::new(origin, dir, 0.);
+        let nearest = centers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| hit_of(&Sphere::new(*c, 1., grey()), &r, 0.001, f32::INFINITY).map(|h| (i, h.t)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        let mut rec = HitRecord::void();
+        let hit = list.hit(&r, 0.001, f32::INFINITY, &mut rec);
+        prop_assert_eq!(hit, nearest.is_some());
+        if let Some((_, t)) = nearest {
+            prop_assert!(close(rec.t, t), "t = {}, nearest is {}", rec.t, t);
+            // Spheres can overlap, so the id only has to be of one hit there
+            let own = hit_of(&Sphere::new(centers[rec.object_id], 1., grey()), &r, 0.001, f32::INFINITY);
+            prop_assert!(own.is_some_and(|h| close(h.t, rec.t)));
+        }
+    }
+}
+
+#[test]
+fn rotated_box_at_90_degrees() {
+    let inner = Arc::new(Translate::from(
+        Arc::new(XzRect::from(0., 2., 0., 1., 0., grey())),
+        &Vec3::from(1., 3., 1.),
+    ));
+    let bbox = bbox_of(&RotateY::from(inner, 90.));
+    // x in [1, 3] and z in [1, 2] turn into x in [1, 2] and z in [-3, -1]
+    assert!(close_vec(bbox.min(), Point3::from(1., 3. - 0.0001, -3.)));
+    assert!(close_vec(bbox.max(), Point3::from(2., 3. + 0.0001, -1.)));
+}
+
+#[test]
+fn quadrilateral_bounding_box_spans_all_corners() {
+    let quad = Quadrilateral::from((0., 0.), (1., 2.), (3., 1.), (2., -1.), 5., grey());
+    let bbox = bbox_of(&quad);
+    assert!(close_vec(bbox.min(), Point3::from(5. - 0.0001, -1., 0.)));
+    assert!(close_vec(bbox.max(), Point3::from(5. + 0.0001, 2., 3.)));
+}
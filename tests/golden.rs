@@ -0,0 +1,183 @@
+/*
+// Description: Golden-image regression tests. Each scene from scene_driver
+//              is rendered small, at a low sample count and with a fixed
+//              seed, and checked against a converged reference image in
+//              tests/golden. Low sample counts are noisy, so instead of
+//              asking for an exact match the difference in each 4x4 block
+//              of pixels is measured against its own standard error; the test
+//              fails when the differences are larger than the noise explains
+//              or the image got brighter or darker overall. On failure the
+//              render, the reference and a false-color difference image are
+//              written to the cargo target tmp directory.
+//
+//              References are regenerated, after an intended change in
+//              output, with
+//                  UPDATE_GOLDEN=1 cargo test --release --test golden
+*/
+
+use ray_trace::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 36;
+const SPP: usize = 16;
+const REFERENCE_SPP: usize = 1024;
+const MAX_DEPTH: i32 = 50;
+const SEED: u64 = 0x5eed;
+
+/// Side of the pixel blocks compared, single pixels have too few samples
+/// for their standard error to be trusted.
+const BLOCK: usize = 4;
+/// Largest mean squared difference of the blocks in standard errors. Noise
+/// alone gives about 1, less with the stratified Sobol samples.
+const MAX_MEAN_SQUARED_Z: f32 = 2.;
+/// Largest change in overall brightness, relative.
+const MAX_MEAN_BIAS: f32 = 0.03;
+
+// Renders a scene the way the ray_trace binary does, with a box filter so
+// each pixel only sees its own samples
+fn render(scene: i32, spp: usize) -> Canvas {
+    seed_random(Some(SEED));
+    let (world, lights) = scene_driver(scene);
+    let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
+    let cam = Camera::new(
+        Point3::from(-15., 0., 0.),
+        Point3::from(0., 0., 0.),
+        Vec3::from(0., 1., 0.),
+        20.,
+        aspect_ratio,
+        0.,
+        5.,
+        0.,
+        1.,
+    );
+    let background = Color::new();
+    let settings = AdaptiveSettings {
+        min_spp: spp,
+        max_spp: spp,
+        pass_spp: spp,
+        threshold: 0.,
+        sampler: Arc::new(SobolSampler),
+        ..AdaptiveSettings::default()
+    };
+    Canvas::from_fn_progressive(WIDTH, HEIGHT, settings, |x, y| {
+        let r = cam.get_ray(x / (WIDTH - 1) as f32, y / (HEIGHT - 1) as f32);
+        ray_color(r, &background, &world, &lights, MAX_DEPTH)
+    })
+}
+
+fn reference_path(scene: i32) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("scene{}.pfm", scene))
+}
+
+// Variance of each pixel's luminance, top row first like an image
+fn variance(canvas: &Canvas) -> Vec<f32> {
+    let (w, h) = canvas.dimensions();
+    (0..h)
+        .rev()
+        .flat_map(|y| (0..w).map(move |x| y * w + x))
+        .map(|idx| canvas.luminance_variance(idx))
+        .collect()
+}
+
+fn check_scene(scene: i32) {
+    let path = reference_path(scene);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let reference = render(scene, REFERENCE_SPP);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        Image::from_canvas(&reference).save_pfm(&path).unwrap();
+        return;
+    }
+
+    let canvas = render(scene, SPP);
+    let test = Image::from_canvas(&canvas);
+    let reference = Image::load(&path).unwrap_or_else(|e| {
+        panic!("{}; regenerate with UPDATE_GOLDEN=1 cargo test --release --test golden", e)
+    });
+    let comparison = compare_images(&test, &reference).unwrap();
+
+    // The reference has its own, smaller, noise
+    let noise_scale = 1. + SPP as f32 / REFERENCE_SPP as f32;
+    let variance = variance(&canvas);
+    let (mut squared_z, mut blocks) = (0., 0);
+    for by in (0..HEIGHT).step_by(BLOCK) {
+        for bx in (0..WIDTH).step_by(BLOCK) {
+            let (mut d, mut var, mut lr, mut n) = (0., 0., 0., 0.);
+            for y in by..(by + BLOCK).min(HEIGHT) {
+                for x in bx..(bx + BLOCK).min(WIDTH) {
+                    let idx = y * WIDTH + x;
+                    d += luminance(test.pixels[idx]) - luminance(reference.pixels[idx]);
+                    var += variance[idx] * noise_scale;
+                    lr += luminance(reference.pixels[idx]);
+                    n += 1.;
+                }
+            }
+            // Floored for blocks whose few samples happened to all agree
+            let var = var / (n * n) + (0.02 * lr / n).powi(2) + 1e-4 / n;
+            squared_z += (d / n).powi(2) / var;
+            blocks += 1;
+        }
+    }
+    let mean_squared_z = squared_z / blocks as f32;
+    let sum_test: f32 = test.pixels.iter().map(|c| luminance(*c)).sum();
+    let sum_reference: f32 = reference.pixels.iter().map(|c| luminance(*c)).sum();
+    let bias = (sum_test - sum_reference).abs() / sum_reference.max(1e-6);
+
+    if mean_squared_z > MAX_MEAN_SQUARED_Z || bias > MAX_MEAN_BIAS {
+        let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out).unwrap();
+        let test_path = out.join(format!("scene{}_test.pfm", scene));
+        let reference_copy = out.join(format!("scene{}_reference.pfm", scene));
+        let diff_path = out.join(format!("scene{}_diff.ppm", scene));
+        test.save_pfm(&test_path).unwrap();
+        reference.save_pfm(&reference_copy).unwrap();
+        comparison.save_false_color(&diff_path).unwrap();
+        panic!(
+            "scene {} differs from its reference: mean squared z {:.3} (max {}), \
+             brightness bias {:.4} (max {})\n{}\nrender: {}\ndiff: {}",
+            scene,
+            mean_squared_z,
+            MAX_MEAN_SQUARED_Z,
+            bias,
+            MAX_MEAN_BIAS,
+            comparison,
+            test_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+#[test]
+fn scene_0_matches_reference() {
+    check_scene(0);
+}
+
+#[test]
+fn scene_1_matches_reference() {
+    check_scene(1);
+}
+
+#[test]
+fn scene_2_matches_reference() {
+    check_scene(2);
+}
+
+#[test]
+fn scene_3_matches_reference() {
+    check_scene(3);
+}
+
+#[test]
+fn every_scene_has_a_reference() {
+    for scene in 0..SCENE_COUNT {
+        assert!(
+            reference_path(scene).exists() || std::env::var_os("UPDATE_GOLDEN").is_some(),
+            "scene {} has no golden image",
+            scene
+        );
+    }
+}
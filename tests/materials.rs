@@ -0,0 +1,537 @@
+/*
+// Description: Energy conservation, reciprocity and sampling tests for the
+//              materials. The white furnace renders a sphere of each material
+//              under a uniform white environment, where no pixel may come out
+//              brighter than the environment and lossless materials must come
+//              out exactly as bright. The sampling tests histogram the
+//              directions scatter picks over the sphere of directions and
+//              check them against scattering_pdf with a chi-square test;
+//              dielectrics, which only pick the mirror or refracted
+//              direction, are checked to reflect as often as their Fresnel
+//              reflectance says. A one-sided ceiling light, flipped to face
+//              the room, must light the floor and leave the space above it
+//              dark, and one-sided lights must be black from behind and
+//              fall off with their emission profile in front. Material ids
+//              come from the order materials are added to a scene, so they
+//              match between builds of the same scene. Random numbers are
+//              seeded, so the tests give the same answer every run.
+*/
+
+use ray_trace::*;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+const SEED: u64 = 0x3a7e;
+/// p-value below which a chi-square test fails.
+const SIGNIFICANCE: f64 = 1e-3;
+
+const THETA_BINS: usize = 16;
+const PHI_BINS: usize = 32;
+const SAMPLES: usize = 200_000;
+
+// A front facing hit at the origin of a surface facing +z
+fn surface_hit() -> HitRecord<'static> {
+    let mut rec = HitRecord::void();
+    rec.normal = Vec3::from(0., 0., 1.);
+    rec.front_face = true;
+    rec
+}
+
+// Ray arriving at the origin at `degrees` from the normal
+fn incoming(degrees: f32) -> Ray {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Ray::new(Point3::from(sin, 0., cos), Vec3::from(-sin, 0., -cos), 0.)
+}
+
+fn scatter(material: &dyn Material, r_in: &Ray, rec: &HitRecord) -> (bool, Color, Vec3) {
+    let mut attenuation = Color::new();
+    let mut scattered = Ray::new(Point3::new(), Vec3::new(), 0.);
+    let kept = material.scatter(*r_in, *rec, &mut attenuation, &mut scattered);
+    (kept, attenuation, unit_vector(scattered.direction()))
+}
+
+fn direction(cos_theta: f32, phi: f32) -> Vec3 {
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    Vec3::from(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// Natural log of the gamma function (Lanczos)
+fn ln_gamma(x: f64) -> f64 {
+    const C: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series: f64 = 1.000000000190015
+        + C.iter().enumerate().map(|(j, c)| c / (x + 1. + j as f64)).sum::<f64>();
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// Regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0. {
+        return 1.;
+    }
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1. {
+        // Series for P
+        let (mut term, mut sum, mut n) = (1. / a, 1. / a, a);
+        while term.abs() > sum.abs() * 1e-15 {
+            n += 1.;
+            term *= x / n;
+            sum += term;
+        }
+        1. - sum * front
+    } else {
+        // Continued fraction for Q (modified Lentz)
+        let tiny = 1e-300;
+        let mut b = x + 1. - a;
+        let mut c = 1. / tiny;
+        let mut d = 1. / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.;
+            d = an * d + b;
+            d = if d.abs() < tiny { tiny } else { d };
+            c = b + an / c;
+            c = if c.abs() < tiny { tiny } else { c };
+            d = 1. / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.).abs() < 1e-15 {
+                break;
+            }
+        }
+        front * h
+    }
+}
+
+// p-value of observed counts against expected ones, pooling bins expected
+// to see fewer than 5 samples
+fn chi_square_p_value(observed: &[f64], expected: &[f64]) -> f64 {
+    let (mut chi2, mut bins) = (0., 0);
+    let (mut pooled_observed, mut pooled_expected) = (0., 0.);
+    for (o, e) in observed.iter().zip(expected) {
+        if *e < 5. {
+            pooled_observed += o;
+            pooled_expected += e;
+            continue;
+        }
+        chi2 += (o - e) * (o - e) / e;
+        bins += 1;
+    }
+    if pooled_expected > 0. {
+        chi2 += (pooled_observed - pooled_expected).powi(2) / pooled_expected.max(5.);
+        bins += 1;
+    }
+    gamma_q((bins - 1).max(1) as f64 / 2., chi2 / 2.)
+}
+
+// Bins the directions `scatter` picks by cos theta and phi, equal solid
+// angle each, and compares them with `scattering_pdf`
+fn check_pdf(name: &str, material: &dyn Material, r_in: &Ray) {
+    seed_random(Some(SEED));
+    let rec = surface_hit();
+    let bin_of = |d: Vec3| {
+        let t = (((d.z() + 1.) / 2. * THETA_BINS as f32) as usize).min(THETA_BINS - 1);
+        let phi = d.y().atan2(d.x()).rem_euclid(2. * PI);
+        let p = ((phi / (2. * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+        t * PHI_BINS + p
+    };
+
+    let mut observed = vec![0.; THETA_BINS * PHI_BINS];
+    for _ in 0..SAMPLES {
+        let (_, _, d) = scatter(material, r_in, &rec);
+        observed[bin_of(d)] += 1.;
+    }
+
+    // Integrate the pdf over each bin, d omega = d cos theta d phi
+    const SUB: usize = 16;
+    let (d_cos, d_phi) = (2. / THETA_BINS as f32, 2. * PI / PHI_BINS as f32);
+    let mut expected = vec![0.; THETA_BINS * PHI_BINS];
+    for (bin, e) in expected.iter_mut().enumerate() {
+        let (t, p) = (bin / PHI_BINS, bin % PHI_BINS);
+        let mut integral = 0.;
+        for i in 0..SUB {
+            for j in 0..SUB {
+                let cos_theta = -1. + (t as f32 + (i as f32 + 0.5) / SUB as f32) * d_cos;
+                let phi = (p as f32 + (j as f32 + 0.5) / SUB as f32) * d_phi;
+                integral += material.scattering_pdf(r_in, &rec, direction(cos_theta, phi));
+            }
+        }
+        *e = (integral * d_cos * d_phi / (SUB * SUB) as f32) as f64 * SAMPLES as f64;
+    }
+
+    let total: f64 = expected.iter().sum::<f64>() / SAMPLES as f64;
+    assert!(
+        (total - 1.).abs() < 0.01,
+        "{}: scattering_pdf integrates to {}, not 1",
+        name,
+        total
+    );
+    let p = chi_square_p_value(&observed, &expected);
+    assert!(
+        p > SIGNIFICANCE,
+        "{}: sampled directions don't follow scattering_pdf, p = {:e}",
+        name,
+        p
+    );
+}
+
+#[test]
+fn lambertian_samples_follow_its_pdf() {
+    let material = Lambertian::from(Color::from(0.8, 0.8, 0.8));
+    for angle in [0., 45., 80.] {
+        check_pdf(&format!("lambertian at {}", angle), &material, &incoming(angle));
+    }
+}
+
+#[test]
+fn isotropic_samples_follow_its_pdf() {
+    let material = Isotropic::from_color(Color::from(0.8, 0.8, 0.8));
+    check_pdf("isotropic", &material, &incoming(30.));
+}
+
+#[test]
+fn metal_samples_follow_its_pdf() {
+    for fuzz in [0.3, 0.6, 1.] {
+        let material = Metal::from(Color::from(0.9, 0.9, 0.9), fuzz);
+        for angle in [0., 40., 75.] {
+            let name = format!("metal with fuzz {} at {}", fuzz, angle);
+            check_pdf(&name, &material, &incoming(angle));
+        }
+    }
+}
+
+#[test]
+fn dielectric_reflects_as_often_as_its_fresnel_reflectance() {
+    seed_random(Some(SEED));
+    let ir = 1.5;
+    let material = Dielectric::new(ir);
+    const TRIALS: usize = 20_000;
+    for front_face in [true, false] {
+        for angle in [0., 20., 40., 41.5, 60., 85.] {
+            let r_in = incoming(angle);
+            let mut rec = surface_hit();
+            rec.front_face = front_face;
+            let eta = if front_face { 1. / ir } else { ir };
+            let d = unit_vector(r_in.direction());
+            let cos_theta = -d.z();
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            let mirror = reflect(d, rec.normal);
+            let r0 = ((1. - eta) / (1. + eta)).powi(2);
+            let reflectance = if eta * sin_theta > 1. {
+                1.
+            } else {
+                r0 + (1. - r0) * (1. - cos_theta).powi(5)
+            };
+
+            let mut reflections = 0;
+            for _ in 0..TRIALS {
+                let (kept, attenuation, out) = scatter(&material, &r_in, &rec);
+                assert!(kept);
+                assert!((attenuation - Color::from(1., 1., 1.)).near_zero());
+                if (out - mirror).len() < 1e-4 {
+                    reflections += 1;
+                    continue;
+                }
+                // Snell's law for everything that isn't reflected
+                let sin_out = (1. - out.z() * out.z()).sqrt();
+                assert!(out.z() < 0., "refracted ray stayed on the incoming side");
+                assert!((sin_out - eta * sin_theta).abs() < 1e-3, "refraction breaks Snell's law");
+            }
+
+            let expected = reflectance as f64 * TRIALS as f64;
+            if reflectance >= 1. {
+                assert_eq!(reflections, TRIALS, "total internal reflection let rays through");
+                continue;
+            }
+            let observed = [reflections as f64, (TRIALS - reflections) as f64];
+            let p = chi_square_p_value(&observed, &[expected, TRIALS as f64 - expected]);
+            assert!(
+                p > SIGNIFICANCE,
+                "dielectric ({} face, {} degrees) reflected {} of {} rays, expected {:.0}",
+                if front_face { "front" } else { "back" },
+                angle,
+                reflections,
+                TRIALS,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn eval_matches_scattering_for_non_specular_materials() {
+    // Delta lights reach materials through eval and the rest of the light
+    // through scatter, so both have to describe the same reflectance
+    let materials: Vec<(&str, Arc<dyn Material>)> = vec![
+        ("lambertian", Arc::new(Lambertian::from(Color::from(0.2, 0.5, 0.8)))),
+        ("isotropic", Arc::new(Isotropic::from_color(Color::from(0.2, 0.5, 0.8)))),
+    ];
+    let rec = surface_hit();
+    for (name, material) in materials {
+        let r_in = incoming(35.);
+        let (_, attenuation, _) = scatter(&*material, &r_in, &rec);
+        for i in 0..64 {
+            let wi = direction(-1. + (i as f32 + 0.5) / 32., i as f32 * 2.4);
+            let eval = material.eval(&r_in, &rec, wi);
+            let expected = attenuation * material.scattering_pdf(&r_in, &rec, wi);
+            assert!(
+                (eval - expected).len() < 1e-5,
+                "{}: eval {:?} but scatter gives {:?}",
+                name,
+                eval,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn eval_is_reciprocal() {
+    // Surfaces include the cosine at the incoming side in eval, which is
+    // taken out before comparing, the phase function of a medium has none.
+    // Metal and dielectric scatter into exact directions only and keep the
+    // zero eval, so there is nothing of theirs to compare
+    let materials: Vec<(&str, Arc<dyn Material>, bool)> = vec![
+        ("lambertian", Arc::new(Lambertian::from(Color::from(0.7, 0.7, 0.7))), true),
+        ("isotropic", Arc::new(Isotropic::from_color(Color::from(0.7, 0.7, 0.7))), false),
+    ];
+    let rec = surface_hit();
+    for (name, material, surface) in materials {
+        let f = |wo: Vec3, wi: Vec3| {
+            let eval = material.eval(&Ray::new(wo, wo.inv(), 0.), &rec, wi);
+            if surface {
+                eval / wi.z()
+            } else {
+                eval
+            }
+        };
+        for i in 0..32 {
+            for j in 0..32 {
+                let wo = direction((i as f32 + 0.5) / 32., i as f32 * 0.7);
+                let wi = direction((j as f32 + 0.5) / 32., j as f32 * 1.3);
+                // Swapping where the light comes from and where it leaves to
+                let (forward, backward) = (f(wo, wi), f(wi, wo));
+                assert!(!forward.near_zero(), "{}: eval is zero", name);
+                assert!(
+                    (forward - backward).len() <= 1e-5 * (1. + forward.len()),
+                    "{}: eval isn't reciprocal, {:?} against {:?}",
+                    name,
+                    forward,
+                    backward
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn eval_reflects_no_more_than_arrives() {
+    // Integral of eval over incoming directions, on a grid uniform in
+    // cos theta and phi
+    let materials: Vec<(&str, Arc<dyn Material>)> = vec![
+        ("lambertian", Arc::new(Lambertian::from(Color::from(1., 1., 1.)))),
+        ("isotropic", Arc::new(Isotropic::from_color(Color::from(1., 1., 1.)))),
+    ];
+    let rec = surface_hit();
+    const N: usize = 128;
+    for (name, material) in materials {
+        for angle in [0., 45., 85.] {
+            let r_in = incoming(angle);
+            let mut total = Color::new();
+            for i in 0..N {
+                for j in 0..N {
+                    let cos_theta = -1. + 2. * (i as f32 + 0.5) / N as f32;
+                    let phi = 2. * PI * (j as f32 + 0.5) / N as f32;
+                    total.add(material.eval(&r_in, &rec, direction(cos_theta, phi)));
+                }
+            }
+            let albedo = total * (4. * PI / (N * N) as f32);
+            for c in 0..3 {
+                assert!(albedo[c] <= 1.001, "{} at {}: reflects {} of the light", name, angle, albedo[c]);
+            }
+        }
+    }
+}
+
+// Renders a sphere of `material` in a uniform environment of radiance 1,
+// returning the pixel values
+fn furnace(material: Arc<dyn Material>) -> Vec<Color> {
+    seed_random(Some(SEED));
+    const SIZE: usize = 24;
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(Point3::new(), 1., material)));
+    let lights = LightList::new();
+    let environment = Color::from(1., 1., 1.);
+    let cam = Camera::new(
+        Point3::from(0., 0., -4.),
+        Point3::new(),
+        Vec3::from(0., 1., 0.),
+        35.,
+        1.,
+        0.,
+        4.,
+        0.,
+        1.,
+    );
+    let settings = AdaptiveSettings {
+        min_spp: 32,
+        max_spp: 32,
+        pass_spp: 32,
+        threshold: 0.,
+        ..AdaptiveSettings::default()
+    };
+    let canvas = Canvas::from_fn_progressive(SIZE, SIZE, settings, |x, y| {
+        let r = cam.get_ray(x / (SIZE - 1) as f32, y / (SIZE - 1) as f32);
+        ray_color(r, &environment, &world, &lights, 50)
+    });
+    (0..SIZE * SIZE).map(|idx| canvas.pixel_color(idx)).collect()
+}
+
+#[test]
+fn white_furnace() {
+    // Materials that lose no energy come out as bright as the environment
+    let cases: Vec<(&str, Arc<dyn Material>, bool)> = vec![
+        ("white lambertian", Arc::new(Lambertian::from(Color::from(1., 1., 1.))), true),
+        ("grey lambertian", Arc::new(Lambertian::from(Color::from(0.6, 0.6, 0.6))), false),
+        ("white isotropic", Arc::new(Isotropic::from_color(Color::from(1., 1., 1.))), true),
+        ("grey isotropic", Arc::new(Isotropic::from_color(Color::from(0.6, 0.6, 0.6))), false),
+        ("mirror", Arc::new(Metal::from(Color::from(1., 1., 1.), 0.)), true),
+        ("fuzzy metal", Arc::new(Metal::from(Color::from(1., 1., 1.), 0.5)), false),
+        ("glass", Arc::new(Dielectric::new(1.5)), true),
+    ];
+    for (name, material, lossless) in cases {
+        let pixels = furnace(material);
+        for (idx, c) in pixels.iter().enumerate() {
+            for channel in 0..3 {
+                assert!(
+                    c[channel] <= 1. + 1e-4,
+                    "{}: pixel {} is brighter than the environment, {:?}",
+                    name,
+                    idx,
+                    c
+                );
+            }
+        }
+        if lossless {
+            let mean = pixels.iter().map(|c| luminance(*c)).sum::<f32>() / pixels.len() as f32;
+            assert!(mean > 0.99, "{}: lost energy, mean {}", name, mean);
+        }
+    }
+}
+
+// Mean radiance along `r` over `n` paths
+fn mean_radiance(r: Ray, world: &HittableList, n: usize) -> f32 {
+    let mut total = Color::new();
+    for _ in 0..n {
+        total.add(ray_color(r, &Color::new(), world, &LightList::new(), 8));
+    }
+    luminance(total) / n as f32
+}
+
+#[test]
+fn flipped_one_sided_ceiling_light_shines_down() {
+    seed_random(Some(SEED));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::from(Color::from(0.8, 0.8, 0.8)));
+    let light: Arc<dyn Material> =
+        Arc::new(DiffuseLight::one_sided(Arc::new(SolidColor::from(Color::from(1., 1., 1.))), 4.));
+    // An xz rect faces +y, so a ceiling panel has to be flipped to face the room
+    let panel = || Arc::new(XzRect::from(-1., 1., -1., 1., 5., light.clone()));
+    let ceiling = Arc::new(FlipFace::from(panel()));
+    let floor = Arc::new(XzRect::from(-5., 5., -5., 5., 0., white.clone()));
+    let roof = Arc::new(XzRect::from(-5., 5., -5., 5., 10., white));
+
+    let at_floor = Ray::new(Point3::from(0.5, 3., 0.), Vec3::from(0., -1., 0.), 0.);
+    let at_roof = Ray::new(Point3::from(0.5, 7., 0.), Vec3::from(0., 1., 0.), 0.);
+    let up_at_panel = Ray::new(Point3::from(0.5, 3., 0.), Vec3::from(0., 1., 0.), 0.);
+    let down_at_panel = Ray::new(Point3::from(0.5, 7., 0.), Vec3::from(0., -1., 0.), 0.);
+
+    let mut room = HittableList::new();
+    room.add(ceiling.clone());
+    room.add(floor);
+    assert!(mean_radiance(up_at_panel, &room, 1) > 1.);
+    assert!(mean_radiance(at_floor, &room, 4000) > 0.05);
+
+    let mut above = HittableList::new();
+    above.add(ceiling);
+    above.add(roof.clone());
+    assert_eq!(mean_radiance(down_at_panel, &above, 1), 0.);
+    assert_eq!(mean_radiance(at_roof, &above, 4000), 0.);
+
+    // Unflipped, the same panel lights the roof instead
+    let mut unflipped = HittableList::new();
+    unflipped.add(panel());
+    unflipped.add(roof);
+    assert!(mean_radiance(at_roof, &unflipped, 4000) > 0.05);
+}
+
+#[test]
+fn one_sided_lights_are_black_from_behind_and_follow_their_profile() {
+    let white = Color::from(1., 1., 1.);
+    // An xz rect faces +y; rays come at it from 1 unit away, `degrees` off
+    // the normal, on the front or the back side
+    let radiance = |light: DiffuseLight, degrees: f32, front: bool| {
+        let mut world = HittableList::new();
+        world.add(Arc::new(XzRect::from(-5., 5., -5., 5., 0., Arc::new(light))));
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        let side = if front { 1. } else { -1. };
+        let from = Point3::from(sin, side * cos, 0.);
+        mean_radiance(Ray::new(from, from.inv(), 0.), &world, 1)
+    };
+
+    let panel = || DiffuseLight::one_sided(Arc::new(SolidColor::from(white)), 4.);
+    assert!((radiance(panel(), 30., true) - 4.).abs() < 1e-4);
+    assert_eq!(radiance(panel(), 0., false), 0.);
+    assert_eq!(radiance(panel(), 30., false), 0.);
+    let two_sided = || DiffuseLight::from_color(white);
+    assert!((radiance(two_sided(), 30., false) - 1.).abs() < 1e-4);
+
+    // Full inside the inner cone, none outside the outer one, and the
+    // profile's smooth step between them
+    let spot = || DiffuseLight::spot(white, 1., 20., 40.);
+    let profile = EmissionProfile::spot(20., 40.);
+    assert!((radiance(spot(), 10., true) - 1.).abs() < 1e-4);
+    assert_eq!(radiance(spot(), 50., true), 0.);
+    let between = radiance(spot(), 30., true);
+    assert!(between > 0. && between < 1.);
+    assert!((between - profile.weight(degrees_to_radians(30.).cos())).abs() < 1e-3);
+    assert_eq!(radiance(spot(), 10., false), 0.);
+
+    let cosine = || {
+        let mut light = DiffuseLight::one_sided(Arc::new(SolidColor::from(white)), 1.);
+        light.profile = EmissionProfile::Cosine(2.);
+        light
+    };
+    let cos60 = degrees_to_radians(60.).cos();
+    assert!((radiance(cosine(), 60., true) - cos60 * cos60).abs() < 1e-3);
+    assert_eq!(radiance(cosine(), 60., false), 0.);
+}
+
+#[test]
+fn material_ids_are_the_same_for_every_build_of_a_scene() {
+    let mut materials = MaterialList::new();
+    let first = materials.add(Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5))));
+    let second = materials.add(Arc::new(Metal::from(Color::from(0.9, 0.9, 0.9), 0.)));
+    assert_eq!((first.material_id(), second.material_id()), (1, 2));
+    assert_eq!(Lambertian::from(Color::new()).material_id(), 0);
+
+    // Towards the green sphere of scene 0
+    let r = Ray::new(Point3::from(-15., 0., 0.), Vec3::from(15., 2., 0.), 0.);
+    let ids: Vec<u32> = (0..2)
+        .map(|_| {
+            let (world, lights) = scene_driver(0);
+            let sample = ray_color_aov(r, &Color::new(), &world, &lights, 1);
+            assert!(sample.hit);
+            sample.material_id
+        })
+        .collect();
+    assert_ne!(ids[0], 0);
+    assert_eq!(ids[0], ids[1]);
+}
@@ -0,0 +1,94 @@
+/*
+// Description: Tests of the render statistics. Rays are traced at a single
+//              sphere, where the rays, intersection tests, hits, objects
+//              visited and path lengths are known exactly, and a small canvas render checks
+//              the sample and primary ray counts. Counters are per thread
+//              but reset and collected for every thread, so everything runs
+//              in one test to keep other tests from counting in between.
+*/
+
+use ray_trace::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn sphere_world(material: Arc<dyn Material>) -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(Point3::new(), 1., material)));
+    world
+}
+
+fn sphere_stats(stats: &RenderStats) -> &PrimitiveStats {
+    &stats.primitives[Primitive::Sphere as usize]
+}
+
+#[test]
+fn counts_match_the_rays_traced() {
+    let background = Color::new();
+    let at_sphere = Ray::new(Point3::from(0., 0., -5.), Vec3::from(0., 0., 1.), 0.);
+    let past_sphere = Ray::new(Point3::from(0., 3., -5.), Vec3::from(0., 0., 1.), 0.);
+
+    // A light source absorbs everything, so its path ends where it is hit
+    reset_stats();
+    let world = sphere_world(Arc::new(DiffuseLight::from_color(Color::from(1., 1., 1.))));
+    ray_color(at_sphere, &background, &world, &LightList::new(), 50);
+    ray_color(past_sphere, &background, &world, &LightList::new(), 50);
+    let stats = collect_stats(Duration::from_secs(1));
+    assert_eq!(stats.primary_rays, 2);
+    assert_eq!(stats.secondary_rays, 0);
+    assert_eq!(stats.shadow_rays, 0);
+    assert_eq!(sphere_stats(&stats).tests, 2);
+    assert_eq!(sphere_stats(&stats).hits, 1);
+    assert_eq!(stats.paths, 2);
+    assert_eq!(stats.average_path_length(), 0.5);
+
+    // Light scattered off a lone sphere always escapes, after a shadow ray
+    // toward the light in front of it
+    reset_stats();
+    let mut world = sphere_world(Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5))));
+    let mut lights = LightList::new();
+    lights.add(Arc::new(PointLight::from(
+        Point3::from(0., 0., -5.),
+        Color::from(1., 1., 1.),
+    )));
+    ray_color(at_sphere, &background, &world, &lights, 50);
+    let stats = collect_stats(Duration::from_secs(1));
+    assert_eq!(stats.primary_rays, 1);
+    assert_eq!(stats.secondary_rays, 1);
+    assert_eq!(stats.shadow_rays, 1);
+    assert_eq!(stats.rays(), 3);
+    assert_eq!(stats.object_visits, 3);
+    assert_eq!(sphere_stats(&stats).tests, 3);
+    assert_eq!(sphere_stats(&stats).hits, 1);
+    assert_eq!(stats.average_path_length(), 1.);
+
+    // Without a bounding volume hierarchy every ray visits every object
+    reset_stats();
+    let mirror = Arc::new(Metal::from(Color::new(), 0.));
+    world.add(Arc::new(Sphere::new(Point3::from(0., 5., 0.), 1., mirror)));
+    ray_color(past_sphere, &background, &world, &LightList::new(), 50);
+    assert_eq!(collect_stats(Duration::from_secs(1)).object_visits, 2);
+    world.objects.pop();
+
+    // Every sample of a render is counted once, on the thread that took it
+    reset_stats();
+    let settings = AdaptiveSettings {
+        min_spp: 8,
+        max_spp: 8,
+        pass_spp: 8,
+        threshold: 0.,
+        ..AdaptiveSettings::default()
+    };
+    Canvas::from_fn_progressive(4, 4, settings, |x, y| {
+        let r = Ray::new(Point3::from(x - 2., y - 2., -5.), Vec3::from(0., 0., 1.), 0.);
+        ray_color(r, &background, &world, &LightList::new(), 50)
+    });
+    let stats = collect_stats(Duration::from_secs(2));
+    assert_eq!(stats.samples(), 4 * 4 * 8);
+    assert_eq!(stats.primary_rays, 4 * 4 * 8);
+    assert_eq!(stats.paths, 4 * 4 * 8);
+
+    let json = stats.to_json();
+    assert!(json.contains("\"samples\": 128"));
+    assert!(json.contains("\"samples_per_second\": 64.0"));
+    assert!(stats.to_string().contains("sphere"));
+}
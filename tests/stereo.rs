@@ -0,0 +1,136 @@
+/*
+// Description: Tests of the stereo camera rig. Eyes are checked to sit the
+//              interocular distance apart, off-axis eyes to see the same point
+//              on the convergence plane, both rigs to give rays the cone of
+//              one eye's pixel, and the frame layouts to split into the eyes.
+*/
+
+use ray_trace::*;
+
+const EPS: f32 = 1e-4;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < EPS
+}
+
+fn close_vec(a: Vec3, b: Vec3) -> bool {
+    (0..3).all(|c| close(a[c], b[c]))
+}
+
+// A pinhole rig at the origin looking down -z, converging 5 units away
+fn planar(convergence: Convergence, layout: StereoLayout) -> StereoCamera {
+    StereoCamera::new(
+        Point3::new(),
+        Point3::from(0., 0., -1.),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.5,
+        0.,
+        5.,
+        0.,
+        0.,
+        0.5,
+        convergence,
+        layout,
+        100,
+    )
+}
+
+// Where a ray crosses the plane z = -5
+fn on_focus_plane(r: &Ray) -> Point3 {
+    let t = (-5. - r.origin().z()) / r.direction().z();
+    r.at(t)
+}
+
+#[test]
+fn eyes_are_the_interocular_distance_apart() {
+    for convergence in [Convergence::Parallel, Convergence::ToeIn, Convergence::OffAxis] {
+        let rig = planar(convergence, StereoLayout::SideBySide);
+        let left = rig.get_ray(0.25, 0.5);
+        let right = rig.get_ray(0.75, 0.5);
+        assert!(close_vec(left.origin(), Point3::from(-0.25, 0., 0.)), "{:?}", convergence);
+        assert!(close_vec(right.origin(), Point3::from(0.25, 0., 0.)), "{:?}", convergence);
+    }
+
+    let rig = StereoCamera::omnidirectional(
+        Point3::new(),
+        Point3::from(0., 0., -1.),
+        Vec3::from(0., 1., 0.),
+        0.5,
+        0.,
+        0.,
+        StereoLayout::OverUnder,
+        100,
+    );
+    for s in [0., 0.3, 0.5, 0.9] {
+        let left = rig.get_ray(s, 0.75);
+        let right = rig.get_ray(s, 0.25);
+        assert!(close((right.origin() - left.origin()).len(), 0.5), "s {}", s);
+        assert!(close(dot(right.origin() - left.origin(), left.direction()), 0.), "s {}", s);
+    }
+}
+
+#[test]
+fn off_axis_eyes_meet_on_the_convergence_plane() {
+    let rig = planar(Convergence::OffAxis, StereoLayout::SideBySide);
+    for (s, t) in [(0.5, 0.5), (0.2, 0.7), (0.9, 0.1)] {
+        let left = on_focus_plane(&rig.get_ray(s / 2., t));
+        let right = on_focus_plane(&rig.get_ray(0.5 + s / 2., t));
+        assert!(close_vec(left, right), "({}, {}): {:?} and {:?}", s, t, left, right);
+    }
+    assert!(close_vec(
+        on_focus_plane(&rig.get_ray(0.25, 0.5)),
+        Point3::from(0., 0., -5.)
+    ));
+
+    // Parallel eyes see the plane the interocular distance apart
+    let rig = planar(Convergence::Parallel, StereoLayout::SideBySide);
+    let left = on_focus_plane(&rig.get_ray(0.25, 0.5));
+    let right = on_focus_plane(&rig.get_ray(0.75, 0.5));
+    assert!(close(right.x() - left.x(), 0.5));
+}
+
+#[test]
+fn eye_rays_have_the_cone_of_an_eye_pixel() {
+    let mut mono = Camera::new(
+        Point3::from(-0.25, 0., 0.),
+        Point3::from(-0.25, 0., -1.),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.5,
+        0.,
+        5.,
+        0.,
+        0.,
+    );
+    mono.set_image_height(100);
+    let rig = planar(Convergence::Parallel, StereoLayout::SideBySide);
+    let eye = rig.get_ray(0.25, 0.5);
+    assert!(eye.spread > 0.);
+    assert!(close(eye.spread, mono.get_ray(0.5, 0.5).spread));
+
+    let rig = StereoCamera::omnidirectional(
+        Point3::new(),
+        Point3::from(0., 0., -1.),
+        Vec3::from(0., 1., 0.),
+        0.5,
+        0.,
+        0.,
+        StereoLayout::SideBySide,
+        100,
+    );
+    assert!(close(rig.get_ray(0.1, 0.5).spread, std::f32::consts::PI / 100.));
+}
+
+#[test]
+fn layouts_split_the_frame_into_the_eyes() {
+    let rig = planar(Convergence::Parallel, StereoLayout::SideBySide);
+    assert_eq!(rig.frame_size(300, 200), (600, 200));
+    assert_eq!(rig.split(0.2, 0.4), (Eye::Left, 0.4, 0.4));
+    assert_eq!(rig.split(0.75, 0.4), (Eye::Right, 0.5, 0.4));
+
+    let rig = planar(Convergence::Parallel, StereoLayout::OverUnder);
+    assert_eq!(rig.frame_size(300, 200), (300, 400));
+    assert_eq!(rig.split(0.2, 0.75), (Eye::Left, 0.2, 0.5));
+    assert_eq!(rig.split(0.2, 0.25), (Eye::Right, 0.2, 0.5));
+}
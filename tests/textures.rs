@@ -0,0 +1,190 @@
+/*
+// Description: Tests of image texture filtering. Mip levels of odd sized
+//              images must keep the average of every texel, and the texture
+//              footprint of a camera ray must grow with the distance to the
+//              hit, so a fine pattern is averaged away far off and kept
+//              sharp close up. Alpha is mipmapped and filtered like color.
+//              Perlin noise must be the same for the same seed, vanish on the
+//              lattice, be continuous across it, and keep noise, turbulence
+//              and fbm within the bounds their octave weights give.
+*/
+
+use ray_trace::*;
+use std::sync::Arc;
+
+fn grey_texels(width: usize, height: usize, value: impl Fn(usize, usize) -> f32) -> Vec<Color> {
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let v = value(x, y);
+            texels.push(Color::from(v, v, v));
+        }
+    }
+    texels
+}
+
+#[test]
+fn odd_sized_mip_levels_keep_every_texel() {
+    for (width, height) in [(3, 3), (5, 3), (7, 1), (1, 9), (6, 5)] {
+        // The last row and column are the only bright texels
+        let value = |x: usize, y: usize| if x == width - 1 || y == height - 1 { 1. } else { 0. };
+        let texels = grey_texels(width, height, value);
+        let mean = texels.iter().map(|c| c.x()).sum::<f32>() / texels.len() as f32;
+
+        let mut tex = ImageTexture::from_texels(width, height, texels);
+        tex.filter = TextureFilter::Trilinear;
+        let top = tex.sample(0.5, 0.5, 1000.).x();
+        assert!((top - mean).abs() < 1e-5, "{}x{}: {} != {}", width, height, top, mean);
+    }
+}
+
+// Camera `distance` in front of a 2x2 square facing it, and the hit of the
+// ray through the middle of the frame
+fn center_hit(distance: f32, square: &XyRect) -> (Ray, HitRecord<'_>) {
+    let mut cam = Camera::new(
+        Point3::from(0., 0., -distance),
+        Point3::new(),
+        Vec3::from(0., 1., 0.),
+        40.,
+        1.,
+        0.,
+        1.,
+        0.,
+        1.,
+    );
+    cam.set_image_height(100);
+    let r = cam.get_ray(0.5, 0.5);
+    let mut rec = HitRecord::void();
+    assert!(square.hit(&r, 0.001, f32::INFINITY, &mut rec));
+    rec.set_footprint(&r);
+    (r, rec)
+}
+
+#[test]
+fn footprint_grows_with_distance() {
+    let grey: Arc<dyn Material> = Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    let square = XyRect::from(-1., 1., -1., 1., 0., grey);
+
+    // A pixel is 2 tan(20 degrees) / 100 across per unit of distance, and
+    // the square spans 2 units of u
+    let pixel_spread = 2. * 20f32.to_radians().tan() / 100.;
+    for distance in [1., 4., 16.] {
+        let (_, rec) = center_hit(distance, &square);
+        let expected = pixel_spread * distance / 2.;
+        assert!(
+            (rec.footprint - expected).abs() < 1e-3 * expected,
+            "at {}: {} != {}",
+            distance,
+            rec.footprint,
+            expected
+        );
+    }
+
+    // Thin rays, as from a camera without an image height, read one point
+    let thin = Ray::new(Point3::from(0., 0., -4.), Vec3::from(0., 0., 1.), 0.);
+    let mut rec = HitRecord::void();
+    assert!(square.hit(&thin, 0.001, f32::INFINITY, &mut rec));
+    rec.set_footprint(&thin);
+    assert_eq!(rec.footprint, 0.);
+}
+
+#[test]
+fn distant_fine_patterns_are_averaged() {
+    const SIZE: usize = 256;
+    let checker = grey_texels(SIZE, SIZE, |x, y| ((x + y) % 2) as f32);
+    let mut tex = ImageTexture::from_texels(SIZE, SIZE, checker);
+    tex.filter = TextureFilter::Trilinear;
+    let grey: Arc<dyn Material> = Arc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    let square = XyRect::from(-1., 1., -1., 1., 0., grey);
+
+    // Close up a pixel is much smaller than a texel, so a texel center
+    // reads as black or white
+    let (_, near) = center_hit(0.5, &square);
+    let (u, v) = (0.5 + 0.5 / SIZE as f32, 0.5 + 0.5 / SIZE as f32);
+    let sharp = tex.filtered_value(u, v, &near.p, near.footprint).x();
+    assert!(!(0.05..=0.95).contains(&sharp), "near value {}", sharp);
+
+    // Far off a pixel covers many texels, which average to grey
+    let (_, far) = center_hit(200., &square);
+    assert!(far.footprint * SIZE as f32 > 4.);
+    let blurred = tex.filtered_value(u, v, &far.p, far.footprint).x();
+    assert!((blurred - 0.5).abs() < 0.05, "far value {}", blurred);
+    assert!(!(0.05..=0.95).contains(&tex.value(u, v, &far.p).x()));
+}
+
+#[test]
+fn alpha_is_filtered_like_the_color() {
+    let mut tex = ImageTexture::from_texels(4, 4, grey_texels(4, 4, |_, _| 1.));
+    tex.filter = TextureFilter::Trilinear;
+    assert!(!tex.has_alpha());
+    assert_eq!(tex.alpha(0.5, 0.5, 0.), 1.);
+
+    // Opaque left half, clear right half
+    tex.set_alpha((0..16).map(|i| if i % 4 < 2 { 1. } else { 0. }).collect());
+    assert_eq!(tex.alpha(0.1, 0.5, 0.), 1.);
+    assert_eq!(tex.alpha(0.9, 0.5, 0.), 0.);
+    assert!((tex.alpha(0.1, 0.5, 100.) - 0.5).abs() < 1e-5);
+}
+
+// Points spread over several lattice cells, off the lattice itself
+fn noise_points() -> impl Iterator<Item = Point3> {
+    (0..512).map(|i| {
+        let i = i as f32;
+        Point3::from(0.37 * i - 40., 1.13 * (0.71 * i).sin() * 9., 0.053 * i * i % 23.)
+    })
+}
+
+#[test]
+fn perlin_noise_is_the_same_for_the_same_seed() {
+    seed_random(Some(7));
+    let first = Perlin::new();
+    seed_random(Some(7));
+    let second = Perlin::new();
+    // Further along the same seed's random numbers
+    let other = Perlin::new();
+
+    let mut differs = false;
+    for p in noise_points() {
+        assert_eq!(first.noise(&p), second.noise(&p));
+        assert_eq!(first.noise(&p), first.noise(&p));
+        assert_eq!(first.turb(&p, 7), second.turb(&p, 7));
+        differs |= first.noise(&p) != other.noise(&p);
+    }
+    assert!(differs, "later tables gave the same noise");
+}
+
+#[test]
+fn perlin_noise_stays_in_range() {
+    seed_random(Some(7));
+    let perlin = Perlin::new();
+    let mut spread = (f32::INFINITY, f32::NEG_INFINITY);
+    for p in noise_points() {
+        let n = perlin.noise(&p);
+        assert!((-1. ..=1.).contains(&n), "noise {} at {:?}", n, p);
+        spread = (spread.0.min(n), spread.1.max(n));
+
+        // Octave weights halve, so their sum stays below 2
+        let turb = perlin.turb(&p, 7);
+        assert!((0. ..2.).contains(&turb), "turbulence {} at {:?}", turb, p);
+        let fbm = perlin.fbm(&p, 5, 2., 0.5);
+        assert!(fbm.abs() < 2., "fbm {} at {:?}", fbm, p);
+    }
+    assert!(spread.0 < -0.2 && spread.1 > 0.2, "noise only spans {:?}", spread);
+
+    // Gradients meet the offset to their own corner, which is zero there
+    for (x, y, z) in [(0., 0., 0.), (3., -2., 5.), (-17., 4., 255.), (300., 1., -1.)] {
+        assert_eq!(perlin.noise(&Point3::from(x, y, z)), 0.);
+    }
+}
+
+#[test]
+fn perlin_noise_is_continuous_across_the_lattice() {
+    seed_random(Some(7));
+    let perlin = Perlin::new();
+    for p in noise_points() {
+        let on_lattice = Point3::from(p.x().round(), p.y(), p.z());
+        let below = perlin.noise(&(on_lattice - Vec3::from(1e-3, 0., 0.)));
+        let above = perlin.noise(&(on_lattice + Vec3::from(1e-3, 0., 0.)));
+        assert!((below - above).abs() < 1e-2, "jump at {:?}", on_lattice);
+    }
+}